@@ -0,0 +1,326 @@
+//! Benchmarks for the hot paths of the client: parsing the lavalink payloads, mutating the
+//! queues while the queue scheduler plays them, and fanning out the guild events.
+//!
+//! The client runs against a fake lavalink server through a custom connector, so the events go
+//! through the same event loop, dispatchers and queue scheduler as on a real connection.
+//!
+//! Run with `cargo bench`.
+
+use std::io;
+
+use async_trait::async_trait;
+use async_tungstenite::{
+    tokio::{accept_async, TokioAdapter},
+    tungstenite::Message,
+    WebSocketStream,
+};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use futures::{
+    stream::{SplitSink, StreamExt},
+    SinkExt,
+};
+use lavalink_rs::{
+    connector::{Connector, Transport},
+    gateway::LavalinkEventHandler,
+    model::{GatewayEvent, GuildId, Info, Node, PlayerUpdate, Track, TrackStart, Tracks},
+    LavalinkClient,
+};
+use tokio::{
+    io::DuplexStream,
+    runtime::Runtime,
+    sync::mpsc,
+    time::{timeout, Duration, Instant},
+};
+
+const PLAYER_UPDATE: &str = r#"{"op":"playerUpdate","guildId":"182892283111276544","state":{"time":1500467109,"position":60000,"connected":true,"ping":35}}"#;
+const TRACK_START: &str = r#"{"op":"event","type":"TrackStartEvent","guildId":"182892283111276544","track":"QAAAjQIAJVJpY2sgQXN0bGV5IC0gTmV2ZXIgR29ubmEgR2l2ZSBZb3UgVXAADlJpY2tBc3RsZXlWRVZPAAAAAAADPCAAC2RRdzR3OVdnWGNRAAEAK2h0dHBzOi8vd3d3LnlvdXR1YmUuY29tL3dhdGNoP3Y9ZFF3NHc5V2dYY1EAB3lvdXR1YmUAAAAAAAAAAA=="}"#;
+
+type ServerSink = SplitSink<WebSocketStream<TokioAdapter<DuplexStream>>, Message>;
+
+/// Hands the server end of an in-memory stream to the fake lavalink server on every connection.
+struct DuplexConnector(mpsc::UnboundedSender<DuplexStream>);
+
+#[async_trait]
+impl Connector for DuplexConnector {
+    async fn connect(&self, _host: &str, _port: u16) -> io::Result<Box<dyn Transport>> {
+        let (client, server) = tokio::io::duplex(64 * 1024);
+
+        self.0.send(server).map_err(|_| {
+            io::Error::new(io::ErrorKind::ConnectionRefused, "the fake server is gone")
+        })?;
+
+        Ok(Box::new(TokioAdapter::new(client)))
+    }
+}
+
+/// Reports every player update the client dispatched.
+struct Handler(mpsc::UnboundedSender<()>);
+
+#[async_trait]
+impl LavalinkEventHandler for Handler {
+    async fn player_update(&self, _client: LavalinkClient, _event: PlayerUpdate) {
+        let _ = self.0.send(());
+    }
+}
+
+/// A client connected to the fake lavalink server.
+struct FakeServer {
+    client: LavalinkClient,
+    /// Sends the payloads to the client.
+    sink: ServerSink,
+    /// The payloads written by the client.
+    written: mpsc::UnboundedReceiver<String>,
+    /// One message for every player update dispatched to the handler.
+    player_updates: mpsc::UnboundedReceiver<()>,
+}
+
+impl FakeServer {
+    async fn start() -> Self {
+        let (connections_tx, mut connections) = mpsc::unbounded_channel();
+        let (player_updates_tx, player_updates) = mpsc::unbounded_channel();
+
+        #[cfg(feature = "discord-gateway")]
+        let mut builder = LavalinkClient::builder(1_u64, "token");
+        #[cfg(feature = "discord-gateway")]
+        builder.set_start_gateway(false);
+        #[cfg(not(feature = "discord-gateway"))]
+        let mut builder = LavalinkClient::builder(1_u64);
+
+        let client = builder
+            .set_connector(DuplexConnector(connections_tx))
+            .build(Handler(player_updates_tx))
+            .await
+            .unwrap();
+
+        let stream = connections.recv().await.unwrap();
+        let (sink, mut stream) = accept_async(stream).await.unwrap().split();
+
+        // Keeps reading, so the writer of the client never waits for room in the stream.
+        let (written_tx, written) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Some(Ok(message)) = stream.next().await {
+                if let Message::Text(text) = message {
+                    let _ = written_tx.send(text);
+                }
+            }
+        });
+
+        Self {
+            client,
+            sink,
+            written,
+            player_updates,
+        }
+    }
+
+    /// Sends the payloads to the client, all at once.
+    async fn push(&mut self, payloads: &[String]) {
+        for payload in payloads {
+            self.sink
+                .feed(Message::text(payload.clone()))
+                .await
+                .unwrap();
+        }
+
+        self.sink.flush().await.unwrap();
+    }
+
+    /// Waits for the client to write `count` payloads with the opcode.
+    async fn wait_written(&mut self, op: &str, count: usize) {
+        let mut found = 0;
+
+        while found < count {
+            let message = timeout(Duration::from_secs(10), self.written.recv())
+                .await
+                .expect("the client stopped writing")
+                .unwrap();
+
+            let payload = serde_json::from_str::<serde_json::Value>(&message).unwrap();
+            if payload["op"] == op {
+                found += 1;
+            }
+        }
+    }
+}
+
+fn player_update(guild_id: u64) -> String {
+    PLAYER_UPDATE.replace("182892283111276544", &guild_id.to_string())
+}
+
+fn track(idx: usize) -> Track {
+    Track {
+        track: format!("track-{}", idx),
+        info: Some(Info {
+            is_seekable: true,
+            is_stream: false,
+            identifier: format!("identifier-{}", idx),
+            author: "author".to_string(),
+            length: 212_000,
+            position: 0,
+            title: format!("title-{}", idx),
+            uri: format!("https://example.com/{}", idx),
+            source_name: Some("http".to_string()),
+        }),
+    }
+}
+
+fn load_result(count: usize) -> String {
+    let tracks = Tracks {
+        playlist_info: None,
+        load_type: "PLAYLIST_LOADED".to_string(),
+        tracks: (0..count).map(track).collect(),
+        ..Tracks::default()
+    };
+
+    serde_json::to_string(&tracks).unwrap()
+}
+
+fn payload_parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("payload_parse");
+
+    group.bench_function("raw_value", |b| {
+        b.iter(|| serde_json::from_str::<serde_json::Value>(black_box(PLAYER_UPDATE)).unwrap())
+    });
+
+    group.bench_function("player_update", |b| {
+        b.iter(|| {
+            let base = serde_json::from_str::<GatewayEvent>(black_box(PLAYER_UPDATE)).unwrap();
+            black_box(base);
+            serde_json::from_str::<PlayerUpdate>(black_box(PLAYER_UPDATE)).unwrap()
+        })
+    });
+
+    group.bench_function("track_start", |b| {
+        b.iter(|| serde_json::from_str::<TrackStart>(black_box(TRACK_START)).unwrap())
+    });
+
+    let playlist = load_result(100);
+
+    group.bench_function("load_tracks_100", |b| {
+        b.iter(|| serde_json::from_str::<Tracks>(black_box(&playlist)).unwrap())
+    });
+
+    group.finish();
+}
+
+fn queue_contention(c: &mut Criterion) {
+    let mut group = c.benchmark_group("queue_contention");
+    let runtime = Runtime::new().unwrap();
+
+    for tasks in [1_usize, 4, 16].iter().copied() {
+        group.bench_function(format!("queue_{}_tasks", tasks), |b| {
+            let mut server = runtime.block_on(FakeServer::start());
+
+            b.iter_custom(|iters| {
+                runtime.block_on(async {
+                    let nodes = server.client.nodes().await;
+                    let mut elapsed = Duration::from_secs(0);
+
+                    for _ in 0..iters {
+                        nodes.insert(1, Node::default());
+
+                        let start = Instant::now();
+
+                        // The queue scheduler starts the first track while the tasks push theirs.
+                        let handles = (0..tasks)
+                            .map(|task_idx| {
+                                let client = server.client.clone();
+
+                                tokio::spawn(async move {
+                                    for idx in 0..100 {
+                                        client
+                                            .play(1_u64, track(task_idx * 100 + idx))
+                                            .queue()
+                                            .unwrap();
+                                    }
+                                })
+                            })
+                            .collect::<Vec<_>>();
+
+                        for handle in handles {
+                            handle.await.unwrap();
+                        }
+
+                        server.wait_written("play", 1).await;
+
+                        elapsed += start.elapsed();
+                    }
+
+                    elapsed
+                })
+            })
+        });
+    }
+
+    group.finish();
+}
+
+fn event_fan_out(c: &mut Criterion) {
+    let mut group = c.benchmark_group("event_fan_out");
+    let runtime = Runtime::new().unwrap();
+    let payloads = vec![player_update(182_892_283_111_276_544)];
+
+    for receivers in [1_usize, 16, 256].iter().copied() {
+        group.bench_function(format!("subscribe_{}_receivers", receivers), |b| {
+            let mut server = runtime.block_on(FakeServer::start());
+            let mut receivers = (0..receivers)
+                .map(|_| {
+                    server
+                        .client
+                        .subscribe_guild_events(182_892_283_111_276_544_u64)
+                })
+                .collect::<Vec<_>>();
+
+            b.iter(|| {
+                runtime.block_on(async {
+                    server.push(&payloads).await;
+
+                    for receiver in &mut receivers {
+                        let event = receiver.recv().await.unwrap();
+
+                        if event.guild_id() != GuildId(182_892_283_111_276_544) {
+                            unreachable!();
+                        }
+                    }
+                })
+            })
+        });
+    }
+
+    group.finish();
+}
+
+fn guild_dispatch(c: &mut Criterion) {
+    let mut group = c.benchmark_group("guild_dispatch");
+    let runtime = Runtime::new().unwrap();
+
+    for guilds in [100_u64, 1_000, 5_000].iter().copied() {
+        // One player update per guild, like a stats interval with every guild playing.
+        let payloads = (0..guilds).map(player_update).collect::<Vec<_>>();
+
+        group.bench_function(format!("player_update_{}_guilds", guilds), |b| {
+            let mut server = runtime.block_on(FakeServer::start());
+
+            b.iter(|| {
+                runtime.block_on(async {
+                    server.push(&payloads).await;
+
+                    for _ in 0..guilds {
+                        server.player_updates.recv().await.unwrap();
+                    }
+                })
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    payload_parse,
+    queue_contention,
+    event_fan_out,
+    guild_dispatch
+);
+criterion_main!(benches);
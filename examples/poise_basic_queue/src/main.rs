@@ -242,7 +242,6 @@ async fn play(
             .play(guild_id, query_information.tracks[0].clone())
             // Change this to play() if you want your own custom queue or no queue at all.
             .queue()
-            .await
         {
             eprintln!("{}", why);
             return Ok(());
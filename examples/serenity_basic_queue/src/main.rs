@@ -279,7 +279,6 @@ async fn play(ctx: &Context, msg: &Message, args: Args) -> CommandResult {
             .play(guild_id, query_information.tracks[0].clone())
             // Change this to play() if you want your own custom queue or no queue at all.
             .queue()
-            .await
         {
             error!("{}", why);
             return Ok(());
@@ -286,7 +286,6 @@ async fn play(ctx: Context, msg: &Message, args: Arguments<'_>) -> ServiceResult
             .play(guild_id, query_information.tracks[0].clone())
             // Change this to play() if you want your own custom queue or no queue at all.
             .queue()
-            .await
         {
             error!("Error playing: {}", why);
 
@@ -1,28 +1,106 @@
+use crate::connector::{Connector, SharedConnector};
 use crate::error::*;
+use crate::event_loops::{dispatch_guild_event, set_now_playing};
 use crate::gateway::LavalinkEventHandler;
+use crate::model::outgoing::UpdatePlayerBody;
 use crate::model::*;
+#[cfg(feature = "discord-gateway")]
+use crate::voice::{PermissionCheck, SharedVoiceGateway, VoiceGateway};
 use crate::LavalinkClient;
 
-use std::{net::SocketAddr, time::Duration};
-use dashmap::try_result::TryResult;
+use std::{env, fmt, net::SocketAddr, str::FromStr, sync::Arc, time::Duration};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
 //use serenity::model::guild::Region;
 
-use tokio::time::sleep;
-
 #[derive(Debug, Default, Clone, PartialEq)]
+#[allow(clippy::struct_excessive_bools)]
 pub struct LavalinkClientBuilder {
     pub host: String,
+    /// Hosts tried in order when connecting to `host` fails, using the same port and SSL.
+    pub fallback_hosts: Vec<String>,
     pub port: u16,
     pub password: String,
     pub shard_count: u64,
     pub bot_id: UserId,
     pub is_ssl: bool,
+    pub retry_on_common_exception: bool,
+    pub track_stuck_policy: TrackStuckPolicy,
+    pub gapless_preload: Option<Duration>,
+    /// How often the queue scheduler checks if the next track of each guild should start.
+    pub queue_tick: Duration,
+    /// The maximum random delay added to each queue tick, to spread the wakeups of many clients.
+    pub queue_tick_jitter: Duration,
+    pub resume_key: Option<String>,
+    pub resume_timeout: Duration,
+    /// Whether the tracks that were playing are started again when lavalink restarted.
+    pub auto_restore_players: bool,
+    /// How many `stats` events are kept for `stats_history()`.
+    pub stats_history_size: usize,
+    /// When the `audio_degraded` event is triggered, never if None.
+    pub audio_degraded_thresholds: Option<AudioDegradedThresholds>,
+    /// How often the nodes are compared with the players of the server, never if None.
+    pub drift_check_interval: Option<Duration>,
+    /// If the nodes are updated to match the server when they drift.
+    pub correct_drift: bool,
+    /// How many of the latest player and track events are kept for `buffered_events()`,
+    /// disabled if 0.
+    pub event_buffer_size: usize,
+    /// The TLS configuration used by the websocket and REST requests instead of the default one.
+    pub tls_config: Option<TlsConfig>,
+    /// The custom transport used for the websocket instead of TCP.
+    pub connector: Option<SharedConnector>,
+    /// How many `loadtracks` requests can run at the same time, unlimited if None.
+    pub max_concurrent_loads: Option<usize>,
+    /// How long a `loadtracks` request waits for its turn before failing.
+    pub load_queue_timeout: Duration,
+    /// How long the `loadtracks` results are cached, not cached if None.
+    pub load_cache_ttl: Option<Duration>,
+    pub load_cache_size: usize,
+    /// How many decoded tracks are cached, disabled if 0.
+    pub decode_cache_size: usize,
+    /// How long the decoded tracks are cached, forever if None.
+    pub decode_cache_ttl: Option<Duration>,
+    pub autocomplete_debounce: Duration,
+    pub autocomplete_cache_ttl: Duration,
+    /// The `Client-Name` header, the crate name and version if None.
+    pub client_name: Option<String>,
+    /// Headers sent on both the websocket handshake and the REST requests, overriding the
+    /// default ones.
+    pub extra_headers: Vec<(String, String)>,
+    /// Decides which queries `auto_search_tracks()` loads directly instead of searching.
+    pub url_matcher: UrlMatcher,
     #[cfg(feature = "discord-gateway")]
     pub bot_token: String,
     #[cfg(feature = "discord-gateway")]
     pub start_gateway: bool,
     #[cfg(feature = "discord-gateway")]
     pub gateway_start_wait_time: Duration,
+    #[cfg(feature = "discord-gateway")]
+    pub presence: Option<Presence>,
+    /// The intents the discord gateway identifies with.
+    #[cfg(feature = "discord-gateway")]
+    pub gateway_intents: u64,
+    /// The connection properties the discord gateway identifies with.
+    #[cfg(feature = "discord-gateway")]
+    pub identify_properties: IdentifyProperties,
+    #[cfg(feature = "discord-gateway")]
+    pub rejoin_on_session_invalidated: bool,
+    /// How long `join()` waits for discord to send the voice connection of an attempt.
+    #[cfg(feature = "discord-gateway")]
+    pub join_timeout: Duration,
+    /// How many times `join()` sends the voice state update again after an attempt timed out.
+    #[cfg(feature = "discord-gateway")]
+    pub join_retries: usize,
+    /// Gets the permissions of the bot in a voice channel before `join()` joins it.
+    #[cfg(feature = "discord-gateway")]
+    pub permission_check: Option<PermissionCheck>,
+    /// Whether the bot becomes a speaker, or requests to speak, when it joins a stage channel.
+    #[cfg(feature = "discord-gateway")]
+    pub auto_stage_speaker: bool,
+    /// The transport of the voice state updates, the internal gateway if None.
+    #[cfg(feature = "discord-gateway")]
+    pub voice_gateway: Option<SharedVoiceGateway>,
 }
 
 impl LavalinkClientBuilder {
@@ -31,38 +109,127 @@ impl LavalinkClientBuilder {
     ///
     /// Default values:
     ///   - `host`: localhost
+    ///   - `fallback_hosts`: empty
     ///   - `port`: 2333
     ///   - `password`: youshallnotpass
     ///   - `shard_count`: 1
     ///   - `is_ssl`: false
+    ///   - `retry_on_common_exception`: false
+    ///   - `track_stuck_policy`: `TrackStuckPolicy::Nothing`
+    ///   - `gapless_preload`: None
+    ///   - `queue_tick`: 1 second
+    ///   - `queue_tick_jitter`: 0 seconds
+    ///   - `resume_key`: None
+    ///   - `resume_timeout`: 60 seconds
+    ///   - `auto_restore_players`: false
+    ///   - `stats_history_size`: 60, about an hour
+    ///   - `audio_degraded_thresholds`: 150 frames of deficit or nulled for 2 intervals
+    ///   - `drift_check_interval`: None
+    ///   - `correct_drift`: false
+    ///   - `event_buffer_size`: 0
+    ///   - `tls_config`: None
+    ///   - `connector`: None
+    ///   - `max_concurrent_loads`: None
+    ///   - `load_queue_timeout`: 30 seconds
+    ///   - `load_cache_ttl`: None
+    ///   - `load_cache_size`: 1024
+    ///   - `decode_cache_size`: 1024
+    ///   - `decode_cache_ttl`: None
+    ///   - `autocomplete_debounce`: 300 milliseconds
+    ///   - `autocomplete_cache_ttl`: 1 minute
+    ///   - `client_name`: None
+    ///   - `extra_headers`: empty
+    ///   - `url_matcher`: http and https URLs
     ///   - `bot_id`: <required parameter>
     ///   - `bot_token`: <required parameter>
     ///   - `start_gateway`: true
     ///   - `gateway_start_wait_time`: 6 seconds
+    ///   - `presence`: None
+    ///   - `gateway_intents`: `GUILDS` and `GUILD_VOICE_STATES`
+    ///   - `identify_properties`: the OS, and lavalink-rs as the browser and device
+    ///   - `rejoin_on_session_invalidated`: false
+    ///   - `voice_gateway`: None
+    ///   - `join_timeout`: 5 seconds
+    ///   - `join_retries`: 0
+    ///   - `permission_check`: None
+    ///   - `auto_stage_speaker`: true
     pub fn new(bot_id: impl Into<UserId>, bot_token: impl Into<String>) -> Self {
         Self {
             host: "localhost".to_string(),
             port: 2333,
             password: "youshallnotpass".to_string(),
             shard_count: 1,
+            resume_timeout: crate::RESUME_TIMEOUT,
+            queue_tick: Duration::from_secs(1),
+            stats_history_size: crate::STATS_HISTORY_CAPACITY,
+            audio_degraded_thresholds: Some(AudioDegradedThresholds::default()),
+            drift_check_interval: None,
+            correct_drift: false,
+            load_queue_timeout: Duration::from_secs(30),
+            load_cache_size: 1024,
+            decode_cache_size: 1024,
+            autocomplete_debounce: crate::AUTOCOMPLETE_DEBOUNCE,
+            autocomplete_cache_ttl: crate::AUTOCOMPLETE_CACHE_TTL,
             bot_id: bot_id.into(),
             bot_token: bot_token.into(),
             start_gateway: true,
             gateway_start_wait_time: Duration::from_secs(6),
+            gateway_intents: DEFAULT_GATEWAY_INTENTS,
+            join_timeout: Duration::from_secs(5),
+            auto_stage_speaker: true,
             ..Self::default()
         }
     }
 
+    /// Builds the `LavalinkClient` without a bot token, for when the voice connections are handled
+    /// by another library, like songbird.
+    ///
+    /// The discord gateway is not started, and its data is never allocated unless the gateway
+    /// methods are used.
+    #[cfg(feature = "discord-gateway")]
+    pub fn without_gateway(bot_id: impl Into<UserId>) -> Self {
+        let mut builder = Self::new(bot_id, String::new());
+        builder.start_gateway = false;
+        builder
+    }
+
     #[cfg(not(feature = "discord-gateway"))]
     ///
     /// Builds the `LavalinkClient`.
     ///
     /// Default values:
     ///   - `host`: localhost
+    ///   - `fallback_hosts`: empty
     ///   - `port`: 2333
     ///   - `password`: youshallnotpass
     ///   - `shard_count`: 1
     ///   - `is_ssl`: false
+    ///   - `retry_on_common_exception`: false
+    ///   - `track_stuck_policy`: `TrackStuckPolicy::Nothing`
+    ///   - `gapless_preload`: None
+    ///   - `queue_tick`: 1 second
+    ///   - `queue_tick_jitter`: 0 seconds
+    ///   - `resume_key`: None
+    ///   - `resume_timeout`: 60 seconds
+    ///   - `auto_restore_players`: false
+    ///   - `stats_history_size`: 60, about an hour
+    ///   - `audio_degraded_thresholds`: 150 frames of deficit or nulled for 2 intervals
+    ///   - `drift_check_interval`: None
+    ///   - `correct_drift`: false
+    ///   - `event_buffer_size`: 0
+    ///   - `tls_config`: None
+    ///   - `connector`: None
+    ///   - `max_concurrent_loads`: None
+    ///   - `load_queue_timeout`: 30 seconds
+    ///   - `load_cache_ttl`: None
+    ///   - `load_cache_size`: 1024
+    ///   - `decode_cache_size`: 1024
+    ///   - `decode_cache_ttl`: None
+    ///   - `autocomplete_debounce`: 300 milliseconds
+    ///   - `autocomplete_cache_ttl`: 1 minute
+    ///   - `client_name`: None
+    ///   - `extra_headers`: empty
+    ///   - `url_matcher`: http and https URLs
     ///   - `bot_id`: <required parameter>
     pub fn new(bot_id: impl Into<UserId>) -> Self {
         Self {
@@ -70,14 +237,166 @@ impl LavalinkClientBuilder {
             port: 2333,
             password: "youshallnotpass".to_string(),
             shard_count: 1,
+            resume_timeout: crate::RESUME_TIMEOUT,
+            queue_tick: Duration::from_secs(1),
+            stats_history_size: crate::STATS_HISTORY_CAPACITY,
+            audio_degraded_thresholds: Some(AudioDegradedThresholds::default()),
+            drift_check_interval: None,
+            correct_drift: false,
+            load_queue_timeout: Duration::from_secs(30),
+            load_cache_size: 1024,
+            decode_cache_size: 1024,
+            autocomplete_debounce: crate::AUTOCOMPLETE_DEBOUNCE,
+            autocomplete_cache_ttl: crate::AUTOCOMPLETE_CACHE_TTL,
             bot_id: bot_id.into(),
             ..Self::default()
         }
     }
 
+    /// Creates a builder with the values of the config, using the defaults of `new()` for the
+    /// missing ones.
+    #[cfg(feature = "discord-gateway")]
+    pub fn from_config(
+        bot_id: impl Into<UserId>,
+        bot_token: impl Into<String>,
+        config: LavalinkConfig,
+    ) -> Self {
+        let mut builder = Self::new(bot_id, bot_token);
+        builder.apply_config(config);
+        builder
+    }
+
+    /// Creates a builder with the values of the config, using the defaults of `new()` for the
+    /// missing ones.
+    #[cfg(not(feature = "discord-gateway"))]
+    pub fn from_config(bot_id: impl Into<UserId>, config: LavalinkConfig) -> Self {
+        let mut builder = Self::new(bot_id);
+        builder.apply_config(config);
+        builder
+    }
+
+    /// Creates a builder from the environment variables, see `LavalinkConfig::from_env()`.
+    #[cfg(feature = "discord-gateway")]
+    pub fn from_env(
+        bot_id: impl Into<UserId>,
+        bot_token: impl Into<String>,
+    ) -> LavalinkResult<Self> {
+        Ok(Self::from_config(
+            bot_id,
+            bot_token,
+            LavalinkConfig::from_env()?,
+        ))
+    }
+
+    /// Creates a builder from the environment variables, see `LavalinkConfig::from_env()`.
+    #[cfg(not(feature = "discord-gateway"))]
+    pub fn from_env(bot_id: impl Into<UserId>) -> LavalinkResult<Self> {
+        Ok(Self::from_config(bot_id, LavalinkConfig::from_env()?))
+    }
+
+    fn apply_config(&mut self, config: LavalinkConfig) {
+        let has_host = config.host.is_some();
+
+        if let Some(host) = config.host {
+            self.set_host(host);
+        }
+
+        for host in config.fallback_hosts {
+            self.add_fallback_host(host);
+        }
+
+        let mut nodes = config.nodes.into_iter();
+
+        if !has_host {
+            if let Some(host) = nodes.next() {
+                self.set_host(host);
+            }
+        }
+
+        for host in nodes {
+            self.add_fallback_host(host);
+        }
+
+        if let Some(port) = config.port {
+            self.port = port;
+        }
+
+        if let Some(password) = config.password {
+            self.set_password(password);
+        }
+
+        if let Some(is_ssl) = config.is_ssl {
+            self.is_ssl = is_ssl;
+        }
+
+        if let Some(shard_count) = config.shard_count {
+            self.shard_count = shard_count;
+        }
+
+        if let Some(resume_key) = config.resume_key {
+            self.resume_key = Some(resume_key);
+        }
+
+        if let Some(resume_timeout) = config.resume_timeout {
+            self.resume_timeout = Duration::from_secs(resume_timeout);
+        }
+    }
+
     /// Sets the host.
+    ///
+    /// Surrounding whitespace, the scheme and trailing slashes are removed. An `https://` or
+    /// `wss://` scheme also enables SSL. IPv6 literals can be given with or without brackets.
     pub fn set_host(&mut self, host: impl ToString) -> &mut Self {
-        self.host = host.to_string();
+        self.host = self.normalize_host(&host.to_string());
+        self
+    }
+
+    /// Adds a host to try when connecting to the previous ones fails.
+    ///
+    /// The host is cleaned up like in `set_host()`.
+    pub fn add_fallback_host(&mut self, host: impl Into<String>) -> &mut Self {
+        let host = self.normalize_host(&host.into());
+        self.fallback_hosts.push(host);
+        self
+    }
+
+    /// Sets the hosts to try when connecting to the previous ones fails.
+    pub fn set_fallback_hosts(&mut self, hosts: Vec<String>) -> &mut Self {
+        self.fallback_hosts.clear();
+
+        for host in hosts {
+            self.add_fallback_host(host);
+        }
+
+        self
+    }
+
+    fn normalize_host(&mut self, host: &str) -> String {
+        let (host, is_ssl) = strip_host(host);
+
+        if is_ssl {
+            self.is_ssl = true;
+        }
+
+        host
+    }
+
+    /// Sets a custom TLS configuration, like one with extra root certificates or a client
+    /// certificate, used for both the websocket and the REST requests.
+    ///
+    /// SSL still has to be enabled with `set_is_ssl()` or an `https://` host.
+    pub fn set_tls_config(&mut self, tls_config: TlsConfig) -> &mut Self {
+        self.tls_config = Some(tls_config);
+        self
+    }
+
+    /// Sets a custom transport for the websocket, like a Unix socket, used instead of
+    /// connecting through TCP to the host and port.
+    ///
+    /// Only the websocket goes through it. The REST requests, like loading or decoding tracks,
+    /// still use HTTP to the host and port.
+    pub fn set_connector(&mut self, connector: impl Connector + 'static) -> &mut Self {
+        self.connector = Some(SharedConnector::new(connector));
         self
     }
 
@@ -87,7 +406,7 @@ impl LavalinkClientBuilder {
         self
     }
 
-    /// Sets the host and port from an address.
+    /// Sets the host and port from an address, which can be IPv4 or IPv6.
     pub fn set_addr(&mut self, addr: impl Into<SocketAddr>) -> &mut Self {
         let addr = addr.into();
 
@@ -122,9 +441,198 @@ impl LavalinkClientBuilder {
         self
     }
 
+    /// Sets if a track should be loaded and played again once when it fails with a `COMMON`
+    /// exception, before the `track_exception` event is triggered.
+    pub fn set_retry_on_common_exception(&mut self, retry: bool) -> &mut Self {
+        self.retry_on_common_exception = retry;
+        self
+    }
+
+    /// Sets what to do when a track gets stuck while playing.
+    pub fn set_track_stuck_policy(&mut self, policy: TrackStuckPolicy) -> &mut Self {
+        self.track_stuck_policy = policy;
+        self
+    }
+
+    /// Sets how long before the end of the current track the queue starts playing the next one,
+    /// to avoid the gap between them.
+    ///
+    /// The queue is checked every `queue_tick`, so this should be at least that long.
+    pub fn set_gapless_preload(&mut self, preload: Duration) -> &mut Self {
+        self.gapless_preload = Some(preload);
+        self
+    }
+
+    /// Sets how often the queue scheduler checks if the next track of each guild should start.
+    ///
+    /// Lower values shorten the gap between tracks, at the cost of more wakeups.
+    pub fn set_queue_tick(&mut self, tick: Duration) -> &mut Self {
+        self.queue_tick = tick;
+        self
+    }
+
+    /// Adds a random delay up to `jitter` to each queue tick, so the queue schedulers of many
+    /// clients, like one per shard, don't wake up at the same time.
+    pub fn set_queue_tick_jitter(&mut self, jitter: Duration) -> &mut Self {
+        self.queue_tick_jitter = jitter;
+        self
+    }
+
+    /// Sets the key used to resume the lavalink session after the websocket disconnects, keeping
+    /// the players alive on the server in the meantime.
+    pub fn set_resume_key(&mut self, resume_key: impl Into<String>) -> &mut Self {
+        self.resume_key = Some(resume_key.into());
+        self
+    }
+
+    /// Sets how long lavalink keeps the players alive waiting for the session to be resumed.
+    pub fn set_resume_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.resume_timeout = timeout;
+        self
+    }
+
+    /// Sets if the tracks that were playing are started again at their last position when the
+    /// websocket reconnects to a lavalink server that restarted, instead of resuming the session.
+    ///
+    /// The voice updates of the players are always sent again on such reconnections.
+    pub fn set_auto_restore_players(&mut self, auto_restore_players: bool) -> &mut Self {
+        self.auto_restore_players = auto_restore_players;
+        self
+    }
+
+    /// Sets how many `stats` events are kept for `stats_history()` and the averages calculated
+    /// from it. Lavalink sends one every minute.
+    pub fn set_stats_history_size(&mut self, size: usize) -> &mut Self {
+        self.stats_history_size = size;
+        self
+    }
+
+    /// Sets when the `audio_degraded` event is triggered from the frame stats, or disables it
+    /// with None.
+    pub fn set_audio_degraded_thresholds(
+        &mut self,
+        thresholds: Option<AudioDegradedThresholds>,
+    ) -> &mut Self {
+        self.audio_degraded_thresholds = thresholds;
+        self
+    }
+
+    /// Sets how often the nodes are compared with the players of the server, triggering
+    /// `state_drift_detected` when they differ twice in a row. Disabled if None.
+    ///
+    /// This needs Andesite, or lavalink 3.7 or later.
+    pub fn set_drift_check_interval(&mut self, interval: Option<Duration>) -> &mut Self {
+        self.drift_check_interval = interval;
+        self
+    }
+
+    /// Sets if the nodes are updated to match the server when they drift. A track only playing
+    /// on the server can't be restored on the node, so it's only reported.
+    pub fn set_correct_drift(&mut self, correct_drift: bool) -> &mut Self {
+        self.correct_drift = correct_drift;
+        self
+    }
+
+    /// Sets how many of the latest player and track events are kept, so the streams created later
+    /// with `events_with_replay()` start with them. Disabled if 0.
+    pub fn set_event_buffer_size(&mut self, size: usize) -> &mut Self {
+        self.event_buffer_size = size;
+        self
+    }
+
+    /// Limits how many `loadtracks` requests can run at the same time, so a burst of commands
+    /// can't trip the ratelimits of lavalink. The rest wait for their turn.
+    pub fn set_max_concurrent_loads(&mut self, max_concurrent_loads: usize) -> &mut Self {
+        self.max_concurrent_loads = Some(max_concurrent_loads);
+        self
+    }
+
+    /// Sets how long a `loadtracks` request waits for its turn before failing with
+    /// `LavalinkError::Timeout`.
+    pub fn set_load_queue_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.load_queue_timeout = timeout;
+        self
+    }
+
+    /// Caches the `loadtracks` results for the duration, so the same query isn't loaded again
+    /// in a short time.
+    pub fn set_load_cache_ttl(&mut self, ttl: Duration) -> &mut Self {
+        self.load_cache_ttl = Some(ttl);
+        self
+    }
+
+    /// Sets how many `loadtracks` results are cached, 0 to disable the cache. The oldest ones
+    /// are evicted first when it's full.
+    ///
+    /// Only used with `set_load_cache_ttl()`.
+    pub fn set_load_cache_size(&mut self, size: usize) -> &mut Self {
+        self.load_cache_size = size;
+        self
+    }
+
+    /// Sets how many decoded tracks are cached, 0 to disable the cache.
+    pub fn set_decode_cache_size(&mut self, size: usize) -> &mut Self {
+        self.decode_cache_size = size;
+        self
+    }
+
+    /// Sets how long the decoded tracks are cached.
+    pub fn set_decode_cache_ttl(&mut self, ttl: Duration) -> &mut Self {
+        self.decode_cache_ttl = Some(ttl);
+        self
+    }
+
+    /// Sets how long `autocomplete_tracks()` waits for the user to stop typing before searching.
+    pub fn set_autocomplete_debounce(&mut self, debounce: Duration) -> &mut Self {
+        self.autocomplete_debounce = debounce;
+        self
+    }
+
+    /// Sets how long the `autocomplete_tracks()` choices are cached.
+    pub fn set_autocomplete_cache_ttl(&mut self, ttl: Duration) -> &mut Self {
+        self.autocomplete_cache_ttl = ttl;
+        self
+    }
+
     /// Sets the lavalink password.
+    ///
+    /// Surrounding whitespace is removed.
     pub fn set_password(&mut self, password: impl ToString) -> &mut Self {
-        self.password = password.to_string();
+        self.password = password.to_string().trim().to_string();
+        self
+    }
+
+    /// Sets the `Client-Name` header, which defaults to the name and version of this crate.
+    pub fn set_client_name(&mut self, client_name: impl Into<String>) -> &mut Self {
+        self.client_name = Some(client_name.into());
+        self
+    }
+
+    /// Adds a header sent on both the websocket handshake and the REST requests, like
+    /// `X-Forwarded-For` or the authentication of a proxy.
+    ///
+    /// The header overrides the default one with the same name, if any.
+    pub fn add_header(&mut self, name: impl Into<String>, value: impl Into<String>) -> &mut Self {
+        self.extra_headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Sets the regex matching the queries `auto_search_tracks()` loads directly, instead of
+    /// searching them.
+    pub fn set_url_regex(&mut self, regex: Regex) -> &mut Self {
+        self.url_matcher = UrlMatcher::Regex(regex);
+        self
+    }
+
+    /// Sets the function deciding if `auto_search_tracks()` loads a query directly, instead of
+    /// searching it.
+    ///
+    /// Useful to force searching on some domains, or to load URIs like `spotify:track:` directly.
+    pub fn set_url_matcher(
+        &mut self,
+        matcher: impl Fn(&str) -> bool + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.url_matcher = UrlMatcher::Custom(Arc::new(matcher));
         self
     }
 
@@ -142,15 +650,459 @@ impl LavalinkClientBuilder {
         self
     }
 
+    /// Sets the presence the discord gateway will identify with.
+    #[cfg(feature = "discord-gateway")]
+    pub fn set_presence(&mut self, presence: Presence) -> &mut Self {
+        self.presence = Some(presence);
+        self
+    }
+
+    /// Sets the intents the discord gateway identifies with, which must include
+    /// `GUILD_VOICE_STATES`.
+    ///
+    /// Without `GUILDS`, the voice states of the members already in a channel aren't known until
+    /// they change.
+    #[cfg(feature = "discord-gateway")]
+    pub fn set_gateway_intents(&mut self, intents: u64) -> &mut Self {
+        self.gateway_intents = intents;
+        self
+    }
+
+    /// Sets the connection properties the discord gateway identifies with.
+    #[cfg(feature = "discord-gateway")]
+    pub fn set_identify_properties(&mut self, properties: IdentifyProperties) -> &mut Self {
+        self.identify_properties = properties;
+        self
+    }
+
+    /// Sets if the voice channel should be joined again when discord invalidates the voice
+    /// session.
+    #[cfg(feature = "discord-gateway")]
+    pub fn set_rejoin_on_session_invalidated(&mut self, rejoin: bool) -> &mut Self {
+        self.rejoin_on_session_invalidated = rejoin;
+        self
+    }
+
+    /// Sets the transport of the voice state updates, to join the voice channels through the
+    /// gateway of another library instead of the internal one, see `VoiceGateway`.
+    ///
+    /// Usually combined with `without_gateway()`.
+    #[cfg(feature = "discord-gateway")]
+    pub fn set_voice_gateway(&mut self, voice_gateway: impl VoiceGateway + 'static) -> &mut Self {
+        self.voice_gateway = Some(SharedVoiceGateway::new(voice_gateway));
+        self
+    }
+
+    /// Sets how long `join()` waits for discord to send the voice connection, for each attempt.
+    #[cfg(feature = "discord-gateway")]
+    pub fn set_join_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.join_timeout = timeout;
+        self
+    }
+
+    /// Sets how many times `join()` sends the voice state update again after an attempt timed
+    /// out, before returning `LavalinkError::JoinTimedOut`.
+    #[cfg(feature = "discord-gateway")]
+    pub fn set_join_retries(&mut self, retries: usize) -> &mut Self {
+        self.join_retries = retries;
+        self
+    }
+
+    /// Sets the callback that gets the permissions bitfield of the bot in a voice channel, so
+    /// `join()` returns `LavalinkError::MissingPermissions` without `CONNECT` or `SPEAK` instead
+    /// of timing out.
+    ///
+    /// Returning None joins the channel without checking.
+    #[cfg(feature = "discord-gateway")]
+    pub fn set_permission_check(
+        &mut self,
+        check: impl Fn(GuildId, ChannelId) -> Option<u64> + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.permission_check = Some(PermissionCheck::new(check));
+        self
+    }
+
+    /// Sets if the bot becomes a speaker when it joins a stage channel as the audience, or requests
+    /// to speak if it lacks the `MUTE_MEMBERS` permission.
+    #[cfg(feature = "discord-gateway")]
+    pub fn set_auto_stage_speaker(&mut self, auto_stage_speaker: bool) -> &mut Self {
+        self.auto_stage_speaker = auto_stage_speaker;
+        self
+    }
+
+    /// Checks that the configured values can be used to connect to lavalink.
+    pub fn validate(&self) -> LavalinkResult<()> {
+        validate_host("host", &self.host)?;
+
+        for host in &self.fallback_hosts {
+            validate_host("fallback_hosts", host)?;
+        }
+
+        if self.port == 0 {
+            return Err(LavalinkError::InvalidConfig(
+                "port",
+                "the port can't be 0".to_string(),
+            ));
+        }
+
+        if self.queue_tick == Duration::from_secs(0) {
+            return Err(LavalinkError::InvalidConfig(
+                "queue_tick",
+                "the queue tick can't be 0".to_string(),
+            ));
+        }
+
+        if self.stats_history_size == 0 {
+            return Err(LavalinkError::InvalidConfig(
+                "stats_history_size",
+                "at least the latest stats must be kept".to_string(),
+            ));
+        }
+
+        if self.drift_check_interval == Some(Duration::from_secs(0)) {
+            return Err(LavalinkError::InvalidConfig(
+                "drift_check_interval",
+                "the drift check interval can't be 0".to_string(),
+            ));
+        }
+
+        if self.password.is_empty() {
+            return Err(LavalinkError::InvalidConfig(
+                "password",
+                "the password is empty".to_string(),
+            ));
+        }
+
+        if let Some(client_name) = &self.client_name {
+            if client_name.parse::<reqwest::header::HeaderValue>().is_err() {
+                return Err(LavalinkError::InvalidConfig(
+                    "client_name",
+                    format!("`{}` is not a valid header value", client_name),
+                ));
+            }
+        }
+
+        for (name, value) in &self.extra_headers {
+            if name.parse::<reqwest::header::HeaderName>().is_err()
+                || value.parse::<reqwest::header::HeaderValue>().is_err()
+            {
+                return Err(LavalinkError::InvalidConfig(
+                    "extra_headers",
+                    format!("`{}: {}` is not a valid header", name, value),
+                ));
+            }
+        }
+
+        if self.max_concurrent_loads == Some(0) {
+            return Err(LavalinkError::InvalidConfig(
+                "max_concurrent_loads",
+                "at least 1 concurrent load is required".to_string(),
+            ));
+        }
+
+        if self.shard_count == 0 {
+            return Err(LavalinkError::InvalidConfig(
+                "shard_count",
+                "there must be at least 1 shard".to_string(),
+            ));
+        }
+
+        #[cfg(feature = "discord-gateway")]
+        if self.join_timeout.as_millis() == 0 {
+            return Err(LavalinkError::InvalidConfig(
+                "join_timeout",
+                "the join timeout can't be 0".to_string(),
+            ));
+        }
+
+        #[cfg(feature = "discord-gateway")]
+        if self.start_gateway && self.bot_token.trim().is_empty() {
+            return Err(LavalinkError::InvalidConfig(
+                "bot_token",
+                "the bot token is required to start the discord gateway".to_string(),
+            ));
+        }
+
+        #[cfg(feature = "discord-gateway")]
+        if self.start_gateway
+            && self.voice_gateway.is_none()
+            && self.gateway_intents & GUILD_VOICE_STATES_INTENT == 0
+        {
+            return Err(LavalinkError::InvalidConfig(
+                "gateway_intents",
+                "the GUILD_VOICE_STATES intent is required to join voice channels".to_string(),
+            ));
+        }
+
+        #[cfg(feature = "discord-gateway")]
+        if format!("Bot {}", self.bot_token)
+            .parse::<reqwest::header::HeaderValue>()
+            .is_err()
+        {
+            return Err(LavalinkError::InvalidConfig(
+                "bot_token",
+                "the bot token contains invalid characters".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
     /// Build the builder into a Client
+    ///
+    /// Returns `LavalinkError::InvalidConfig` if the configuration is invalid, see `validate()`.
     pub async fn build(
         &self,
         handler: impl LavalinkEventHandler + Send + Sync + 'static,
     ) -> Result<LavalinkClient, LavalinkError> {
+        self.validate()?;
+
         LavalinkClient::new(self, handler).await
     }
 }
 
+/// The connection configuration of the client, to be deserialized from a config file or read
+/// from the environment.
+///
+/// Missing values use the defaults of `LavalinkClientBuilder::new()`.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LavalinkConfig {
+    pub host: Option<String>,
+    pub fallback_hosts: Vec<String>,
+    /// The hosts of the nodes, in order of preference. They share the port and password; when
+    /// `host` is unset the first one is the host, the others are tried as fallback hosts.
+    pub nodes: Vec<String>,
+    pub port: Option<u16>,
+    pub password: Option<String>,
+    #[serde(alias = "ssl")]
+    pub is_ssl: Option<bool>,
+    pub shard_count: Option<u64>,
+    pub resume_key: Option<String>,
+    /// The resume timeout, in seconds.
+    pub resume_timeout: Option<u64>,
+}
+
+impl LavalinkConfig {
+    /// Reads the config from the environment variables.
+    ///
+    /// The variables are `LAVALINK_HOST`, `LAVALINK_FALLBACK_HOSTS` and `LAVALINK_NODES` (comma
+    /// separated), `LAVALINK_PORT`, `LAVALINK_PASSWORD`, `LAVALINK_SSL`, `LAVALINK_SHARD_COUNT`,
+    /// `LAVALINK_RESUME_KEY` and `LAVALINK_RESUME_TIMEOUT`; the unset ones are left as None.
+    ///
+    /// Returns `LavalinkError::InvalidConfig` if a variable can't be parsed.
+    pub fn from_env() -> LavalinkResult<Self> {
+        Ok(Self {
+            host: env::var("LAVALINK_HOST").ok(),
+            fallback_hosts: env_list("LAVALINK_FALLBACK_HOSTS"),
+            nodes: env_list("LAVALINK_NODES"),
+            port: parse_env("LAVALINK_PORT", "port")?,
+            password: env::var("LAVALINK_PASSWORD").ok(),
+            is_ssl: parse_env("LAVALINK_SSL", "is_ssl")?,
+            shard_count: parse_env("LAVALINK_SHARD_COUNT", "shard_count")?,
+            resume_key: env::var("LAVALINK_RESUME_KEY").ok(),
+            resume_timeout: parse_env("LAVALINK_RESUME_TIMEOUT", "resume_timeout")?,
+        })
+    }
+}
+
+#[cfg(feature = "rustls")]
+type TlsBackend = tokio_rustls::rustls::ClientConfig;
+#[cfg(feature = "native")]
+type TlsBackend = tokio_native_tls::native_tls::TlsConnector;
+
+/// A custom TLS configuration for the connection to lavalink, for the TLS backend selected with
+/// the `rustls` or `native` feature.
+///
+/// Useful for self-signed certificates, client certificates, or skipping the certificate
+/// verification on lab setups.
+#[derive(Clone)]
+pub struct TlsConfig {
+    inner: Arc<TlsBackend>,
+}
+
+impl TlsConfig {
+    /// Uses the rustls client config.
+    ///
+    /// The rustls version must be the same one used by reqwest, or the REST client will fail to
+    /// build.
+    #[cfg(feature = "rustls")]
+    #[must_use]
+    pub fn from_rustls(config: tokio_rustls::rustls::ClientConfig) -> Self {
+        Self {
+            inner: Arc::new(config),
+        }
+    }
+
+    /// Uses the native-tls connector.
+    #[cfg(feature = "native")]
+    #[must_use]
+    pub fn from_native(connector: tokio_native_tls::native_tls::TlsConnector) -> Self {
+        Self {
+            inner: Arc::new(connector),
+        }
+    }
+
+    #[cfg(feature = "rustls")]
+    pub(crate) fn websocket_connector(&self) -> tokio_rustls::TlsConnector {
+        tokio_rustls::TlsConnector::from(self.inner.clone())
+    }
+
+    #[cfg(feature = "native")]
+    pub(crate) fn websocket_connector(&self) -> tokio_native_tls::TlsConnector {
+        tokio_native_tls::TlsConnector::from((*self.inner).clone())
+    }
+
+    pub(crate) fn apply_to(&self, builder: reqwest::ClientBuilder) -> reqwest::ClientBuilder {
+        builder.use_preconfigured_tls((*self.inner).clone())
+    }
+}
+
+impl fmt::Debug for TlsConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("TlsConfig { .. }")
+    }
+}
+
+impl PartialEq for TlsConfig {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.inner, &other.inner)
+    }
+}
+
+/// Decides if a query given to `auto_search_tracks()` is loaded directly, or searched on youtube.
+#[derive(Clone)]
+pub enum UrlMatcher {
+    /// Loads the query directly if the regex matches it.
+    Regex(Regex),
+    /// Loads the query directly if the function returns true.
+    Custom(Arc<dyn Fn(&str) -> bool + Send + Sync>),
+}
+
+impl UrlMatcher {
+    /// Checks if the query should be loaded directly.
+    #[must_use]
+    pub fn is_match(&self, query: &str) -> bool {
+        match self {
+            Self::Regex(regex) => regex.is_match(query),
+            Self::Custom(matcher) => matcher(query),
+        }
+    }
+}
+
+impl Default for UrlMatcher {
+    fn default() -> Self {
+        Self::Regex(Regex::new(r"https?://(?:www\.)?.+").expect("The default regex is valid."))
+    }
+}
+
+impl fmt::Debug for UrlMatcher {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Regex(regex) => f.debug_tuple("Regex").field(&regex.as_str()).finish(),
+            Self::Custom(_) => f.write_str("Custom(..)"),
+        }
+    }
+}
+
+impl PartialEq for UrlMatcher {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Regex(regex), Self::Regex(other)) => regex.as_str() == other.as_str(),
+            (Self::Custom(matcher), Self::Custom(other)) => Arc::ptr_eq(matcher, other),
+            _ => false,
+        }
+    }
+}
+
+/// Removes the scheme, trailing slashes and IPv6 brackets of the host, returning if the scheme
+/// was a secure one.
+fn strip_host(host: &str) -> (String, bool) {
+    let mut host = host.trim();
+    let mut is_ssl = false;
+
+    for scheme in &["https://", "wss://"] {
+        if let Some(stripped) = host.strip_prefix(scheme) {
+            host = stripped;
+            is_ssl = true;
+        }
+    }
+
+    for scheme in &["http://", "ws://"] {
+        if let Some(stripped) = host.strip_prefix(scheme) {
+            host = stripped;
+        }
+    }
+
+    let host = host.trim_end_matches('/');
+
+    // IPv6 literals are stored without brackets, they are added back when building the URIs.
+    let host = host
+        .strip_prefix('[')
+        .and_then(|host| host.strip_suffix(']'))
+        .unwrap_or(host);
+
+    (host.to_string(), is_ssl)
+}
+
+/// Normalizes the host like the builder does, ignoring the scheme, and validates it.
+pub(crate) fn normalize_host(field: &'static str, host: &str) -> LavalinkResult<String> {
+    let (host, _) = strip_host(host);
+    validate_host(field, &host)?;
+
+    Ok(host)
+}
+
+pub(crate) fn validate_host(field: &'static str, host: &str) -> LavalinkResult<()> {
+    if host.trim().is_empty() {
+        return Err(LavalinkError::InvalidConfig(
+            field,
+            "the host is empty".to_string(),
+        ));
+    }
+
+    if host.contains("://") || host.contains('/') {
+        return Err(LavalinkError::InvalidConfig(
+            field,
+            format!(
+                "`{}` must be only a hostname or IP, without a scheme or path",
+                host
+            ),
+        ));
+    }
+
+    if host.trim() != host {
+        return Err(LavalinkError::InvalidConfig(
+            field,
+            format!("`{}` contains whitespace", host),
+        ));
+    }
+
+    Ok(())
+}
+
+fn env_list(var: &str) -> Vec<String> {
+    env::var(var)
+        .map(|hosts| {
+            hosts
+                .split(',')
+                .map(str::trim)
+                .filter(|host| !host.is_empty())
+                .map(ToString::to_string)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn parse_env<T: FromStr>(var: &str, field: &'static str) -> LavalinkResult<Option<T>> {
+    match env::var(var) {
+        Ok(value) => value.trim().parse().map(Some).map_err(|_| {
+            LavalinkError::InvalidConfig(field, format!("`{}` from {} is not valid", value, var))
+        }),
+        Err(_) => Ok(None),
+    }
+}
+
 #[derive(Clone)]
 pub struct PlayParameters {
     pub track: Track,
@@ -159,150 +1111,132 @@ pub struct PlayParameters {
     pub finish: u64,
     pub guild_id: u64,
     pub requester: Option<UserId>,
+    pub user_data: Option<serde_json::Value>,
+    pub volume: Option<u16>,
+    pub filters: Option<Filters>,
     pub client: LavalinkClient,
 }
 
 impl PlayParameters {
     /// Starts playing the track.
+    ///
+    /// The track is sent with the update player REST route when the session of lavalink 3.7 is
+    /// known, and with the `play` op otherwise. It doesn't replace a playing track, unless
+    /// `replace` is set.
     pub async fn start(&self) -> LavalinkResult<()> {
-        let payload = crate::model::Play {
-            track: self.track.track.clone(),
+        UpdatePlayer {
+            guild_id: self.guild_id,
+            track: Some(self.track.clone()),
             no_replace: !self.replace,
-            start_time: self.start,
+            position: Some(self.start),
             end_time: if self.finish == 0 {
                 None
             } else {
                 Some(self.finish)
             },
-        };
+            volume: self.volume,
+            paused: None,
+            filters: self.filters.clone(),
+            client: self.client.clone(),
+        }
+        .send()
+        .await
+    }
 
-        let client = self.client.inner.lock();
-
-        SendOpcode::Play(payload)
-            .send(
-                self.guild_id,
-                client
-                    .socket_sender
-                    .read()
-                    .as_ref()
-                    .ok_or(LavalinkError::MissingLavalinkSocket)?
-                    .clone(),
-            )
-            .await?;
+    /// Starts playing the track, and waits until lavalink confirms it started.
+    ///
+    /// Returns `LavalinkError::Timeout` if the track doesn't start within `timeout`, like when
+    /// another track is playing and `replace` is not set, or `LavalinkError::TrackFailed` if it
+    /// fails with an exception.
+    pub async fn start_and_wait(&self, timeout: Duration) -> LavalinkResult<()> {
+        // Subscribed before starting, so the event can't be missed.
+        let mut events = self.client.subscribe_guild_events(self.guild_id);
 
-        Ok(())
+        self.start().await?;
+
+        let event = events
+            .wait_for(timeout, |event| match event {
+                GuildEvent::TrackStart(event) => event.track == self.track.track,
+                GuildEvent::TrackException(event) => event.track == self.track.track,
+                _ => false,
+            })
+            .await;
+
+        match event {
+            Some(GuildEvent::TrackException(event)) => {
+                Err(LavalinkError::TrackFailed(Box::new(event.exception)))
+            }
+            Some(_) => Ok(()),
+            None => Err(LavalinkError::Timeout),
+        }
     }
 
     /// Adds the track to the node queue.
     ///
-    /// If there's no queue loop running, this will start one up, and add it to the running loops
-    /// on [`LavalinkClient.loops`].
+    /// The guild is added to the running loops on [`LavalinkClient.loops`] if needed, where the
+    /// queue scheduler of the client starts its tracks.
     ///
     /// Needs for [`LavalinkClient::create_session`] to be called first.
     ///
+    /// Returns `LavalinkError::QueueRejected` if the track breaks the queue policy of the node.
+    ///
     /// [`LavalinkClient.loops`]: crate::LavalinkClientInner::loops
     /// [`LavalinkClient::create_session`]: crate::LavalinkClient::create_session
-    pub async fn queue(&self) -> LavalinkResult<()> {
-        let track = crate::model::TrackQueue {
-            track: self.track.clone(),
-            start_time: self.start,
-            end_time: if self.finish == 0 {
-                None
-            } else {
-                Some(self.finish)
-            },
-            requester: self.requester,
-        };
-
-        let client = self.client.clone();
-
-        let client_lock = client.inner.lock();
+    pub fn queue(&self) -> LavalinkResult<()> {
+        let track = self.to_track_queue();
 
-        if !client_lock.loops.contains(&self.guild_id) {
-            let guild_id = self.guild_id;
+        let position = self.push_to_queue(track.clone())?;
+        let guild_id = GuildId(self.guild_id);
 
-            if let TryResult::Present(ref mut node) =  client_lock.nodes.try_get_mut(&guild_id) {
-                if node.is_on_loops {
-                    node.queue.push(track);
-
-                    return Ok(());
-                }
+        dispatch_guild_event(
+            &self.client,
+            guild_id,
+            "queue_track_added",
+            |handler, client| async move {
+                handler
+                    .queue_track_added(
+                        client,
+                        QueueTrackAdded {
+                            guild_id,
+                            track,
+                            position,
+                        },
+                    )
+                    .await;
+            },
+        );
 
-                node.is_on_loops = true;
-            } else {
-                return Err(LavalinkError::NoSessionPresent);
-            }
+        Ok(())
+    }
 
-            client_lock.loops.insert(guild_id);
+    /// Pushes the track to the node queue, adding the guild to the queue scheduler if needed.
+    ///
+    /// Returns the index of the track in the queue.
+    fn push_to_queue(&self, track: TrackQueue) -> LavalinkResult<usize> {
+        let (nodes, loops, queue_notify) = {
+            let client_lock = self.client.inner.lock();
+            (
+                client_lock.nodes.clone(),
+                client_lock.loops.clone(),
+                client_lock.queue_notify.clone(),
+            )
+        };
 
-            {
-                let mut node = client_lock.nodes.get_mut(&guild_id).unwrap();
-                node.queue.push(track);
-            }
+        let position = {
+            let mut node = nodes
+                .get_mut(&self.guild_id)
+                .ok_or(LavalinkError::NoSessionPresent)?;
 
-            drop(client_lock);
-
-            let client_clone = client.clone();
-
-            tokio::spawn(async move {
-                loop {
-                    if let TryResult::Present(mut node) = client_clone.nodes().await.try_get_mut(&guild_id) {
-                        if !node.queue.is_empty() && node.now_playing.is_none() {
-                            let track = node.queue[0].clone();
-
-                            node.now_playing = Some(node.queue[0].clone());
-
-                            drop(node);
-
-                            let payload = crate::model::Play {
-                                track: track.track.track.clone(), // track
-                                no_replace: false,
-                                start_time: track.start_time,
-                                end_time: track.end_time,
-                            };
-
-                            let socket_sender = {
-                                let client_lock = client_clone.inner.lock();
-                                let x = client_lock
-                                    .socket_sender
-                                    .read()
-                                    .clone();
-                                x
-                            };
-
-                            {
-                                if let Some(socket) = socket_sender {
-                                    if let Err(why) = crate::model::SendOpcode::Play(payload)
-                                        .send(guild_id, socket)
-                                        .await
-                                    {
-                                        error!("Error playing queue on guild {}: {}", guild_id, why);
-                                    }
-                                } else {
-                                    error!(
-                                        "Error playing queue on guild {}: {}",
-                                        guild_id,
-                                        LavalinkError::MissingLavalinkSocket
-                                    );
-                                }
-                            }
-                        }
-                    } else {
-                        //client.loops.remove(guild_id);
-                        break;
-                    }
-
-                    sleep(Duration::from_secs(1)).await;
-                }
-            });
+            node.queue_policy.check(&node.queue, &track)?;
+            node.is_on_loops = true;
 
-            return Ok(());
-        }
+            insert_track(&mut node, track)
+        };
 
-        let mut node = client_lock.nodes.get_mut(&self.guild_id).unwrap();
-        node.queue.push(track);
+        loops.insert(self.guild_id);
+        queue_notify.notify_one();
 
-        Ok(())
+        Ok(position)
     }
 
     /// Generates a `TrackQueue` from the builder.
@@ -317,6 +1251,9 @@ impl PlayParameters {
                 Some(self.finish)
             },
             requester: self.requester,
+            user_data: self.user_data.clone(),
+            volume: self.volume,
+            filters: self.filters.clone(),
         }
     }
 
@@ -326,6 +1263,24 @@ impl PlayParameters {
         self
     }
 
+    /// Attaches arbitrary data to the track, available on the queued track of the events.
+    pub fn user_data(&mut self, user_data: impl Into<serde_json::Value>) -> &mut Self {
+        self.user_data = Some(user_data.into());
+        self
+    }
+
+    /// Sets the volume the track starts with.
+    pub fn volume(&mut self, volume: u16) -> &mut Self {
+        self.volume = Some(volume.min(1000));
+        self
+    }
+
+    /// Sets the filters applied when the track starts, replacing the current ones.
+    pub fn filters(&mut self, filters: Filters) -> &mut Self {
+        self.filters = Some(filters);
+        self
+    }
+
     /// Sets if the current playing track should be replaced with this new one.
     pub fn replace(&mut self, replace: bool) -> &mut Self {
         self.replace = replace;
@@ -349,3 +1304,202 @@ impl PlayParameters {
         self
     }
 }
+
+/// A partial update of the player of a guild, where only the fields that are set are changed.
+///
+/// On lavalink 3.7 and later, once the server sent a session id, the update is sent in a single
+/// `PATCH` request to the player of the session. Otherwise, every field is sent with its own
+/// websocket opcode.
+#[derive(Clone)]
+pub struct UpdatePlayer {
+    pub guild_id: u64,
+    pub track: Option<Track>,
+    pub no_replace: bool,
+    pub position: Option<u64>,
+    pub end_time: Option<u64>,
+    pub volume: Option<u16>,
+    pub paused: Option<bool>,
+    pub filters: Option<Filters>,
+    pub client: LavalinkClient,
+}
+
+impl UpdatePlayer {
+    /// Sends the update, and stores the new track, volume, paused state and filters on the node.
+    pub async fn send(&self) -> LavalinkResult<()> {
+        let guild_lock = self.client.guild_lock(self.guild_id);
+        let _guard = guild_lock.lock().await;
+
+        if self.client.session_id().is_some() {
+            if let Some(session_path) = self.client.session_path().await? {
+                self.send_rest(&session_path).await?;
+                self.update_node();
+
+                return Ok(());
+            }
+        }
+
+        self.send_opcodes().await?;
+        self.update_node();
+
+        Ok(())
+    }
+
+    async fn send_rest(&self, session_path: &str) -> LavalinkResult<()> {
+        let body = UpdatePlayerBody {
+            encoded_track: self.track.as_ref().map(|track| track.track.as_str()),
+            position: self.position,
+            end_time: self.end_time,
+            volume: self.volume,
+            paused: self.paused,
+            filters: self.filters.as_ref(),
+        };
+
+        let path = format!(
+            "{}/players/{}?noReplace={}",
+            session_path, self.guild_id, self.no_replace
+        );
+        let request = self.client.rest_request(reqwest::Method::PATCH, &path);
+
+        LavalinkClient::rest_response(request.json(&body).send().await?).await?;
+
+        Ok(())
+    }
+
+    async fn send_opcodes(&self) -> LavalinkResult<()> {
+        let socket = self
+            .client
+            .inner
+            .lock()
+            .socket_sender
+            .read()
+            .as_ref()
+            .ok_or(LavalinkError::MissingLavalinkSocket)?
+            .clone();
+
+        // The filters are sent first, so a new track never plays without them.
+        if let Some(filters) = &self.filters {
+            SendOpcode::Filters(Box::new(filters.clone()))
+                .send(self.guild_id, socket.clone())
+                .await?;
+        }
+
+        if let Some(track) = &self.track {
+            let payload = Play {
+                track: track.track.clone(),
+                no_replace: self.no_replace,
+                start_time: self.position.unwrap_or_default(),
+                end_time: self.end_time,
+                volume: self.volume,
+            };
+
+            SendOpcode::Play(payload)
+                .send(self.guild_id, socket.clone())
+                .await?;
+        } else {
+            if let Some(position) = self.position {
+                SendOpcode::Seek(Seek { position })
+                    .send(self.guild_id, socket.clone())
+                    .await?;
+            }
+
+            if let Some(volume) = self.volume {
+                SendOpcode::Volume(Volume { volume })
+                    .send(self.guild_id, socket.clone())
+                    .await?;
+            }
+        }
+
+        if let Some(pause) = self.paused {
+            SendOpcode::Pause(Pause { pause })
+                .send(self.guild_id, socket)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    fn update_node(&self) {
+        // The player can be updated without a node.
+        drop(self.client.with_node(self.guild_id, |node| {
+            if let Some(track) = &self.track {
+                // Lavalink ignores the track if one is playing and it shouldn't be replaced.
+                if !self.no_replace || node.now_playing.is_none() {
+                    let track = TrackQueue {
+                        track: track.clone(),
+                        start_time: self.position.unwrap_or_default(),
+                        end_time: self.end_time,
+                        volume: self.volume,
+                        filters: self.filters.clone(),
+                        ..TrackQueue::default()
+                    };
+
+                    set_now_playing(node, &track);
+                }
+            }
+
+            if let Some(volume) = self.volume {
+                node.volume = volume;
+            }
+
+            if let Some(paused) = self.paused {
+                node.is_paused = paused;
+            }
+
+            if self.filters.is_some() {
+                node.filters.clone_from(&self.filters);
+            }
+        }));
+    }
+
+    /// Plays a new track.
+    pub fn track(&mut self, track: Track) -> &mut Self {
+        self.track = Some(track);
+        self
+    }
+
+    /// Sets if the new track shouldn't replace the one playing.
+    pub fn no_replace(&mut self, no_replace: bool) -> &mut Self {
+        self.no_replace = no_replace;
+        self
+    }
+
+    /// Seeks to the position, or starts the new track at it.
+    pub fn position(&mut self, position: Duration) -> &mut Self {
+        self.position = Some(position.as_millis() as u64);
+        self
+    }
+
+    /// Sets the time the new track finishes at.
+    pub fn end_time(&mut self, end_time: Duration) -> &mut Self {
+        self.end_time = Some(end_time.as_millis() as u64);
+        self
+    }
+
+    /// Sets the volume.
+    pub fn volume(&mut self, volume: u16) -> &mut Self {
+        self.volume = Some(volume.min(1000));
+        self
+    }
+
+    /// Pauses or resumes the player.
+    pub fn paused(&mut self, paused: bool) -> &mut Self {
+        self.paused = Some(paused);
+        self
+    }
+
+    /// Sets the filters, replacing the current ones.
+    pub fn filters(&mut self, filters: Filters) -> &mut Self {
+        self.filters = Some(filters);
+        self
+    }
+}
+
+/// Inserts the track on the queue following the queue mode of the node, returning its index.
+fn insert_track(node: &mut Node, track: TrackQueue) -> usize {
+    let skip = usize::from(node.now_playing.is_some());
+    let position = node.queue_mode.insert_position(&node.queue, &track, skip);
+
+    node.queue.insert(position, track);
+
+    position
+}
@@ -0,0 +1,240 @@
+//! Compatibility layer with the method names used by upstream `lavalink-rs` 0.10.
+//!
+//! The methods here are thin wrappers over the ones from [`LavalinkClient`], meant to make
+//! migrating an existing bot to this fork easier. New code should prefer the native API.
+
+use crate::error::{LavalinkError, LavalinkResult};
+use crate::model::{GuildId, Track, TrackQueue, Tracks};
+use crate::LavalinkClient;
+
+use std::time::Duration;
+
+use rand::seq::SliceRandom;
+
+/// A handle to the player of a single guild.
+#[derive(Clone)]
+pub struct PlayerContext {
+    pub guild_id: GuildId,
+    pub client: LavalinkClient,
+}
+
+impl LavalinkClient {
+    /// Alias to `get_tracks()`.
+    ///
+    /// The guild is ignored, as this client only handles one node.
+    pub async fn load_tracks(
+        &self,
+        _guild_id: impl Into<GuildId>,
+        identifier: impl ToString,
+    ) -> LavalinkResult<Tracks> {
+        self.get_tracks(identifier).await
+    }
+
+    /// Gets the player context of a guild, if a session has been created for it.
+    #[must_use]
+    pub fn get_player_context(&self, guild_id: impl Into<GuildId>) -> Option<PlayerContext> {
+        let guild_id = guild_id.into();
+
+        let nodes = self.inner.lock().nodes.clone();
+
+        if nodes.contains_key(&guild_id.0) {
+            Some(PlayerContext {
+                guild_id,
+                client: self.clone(),
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Alias to `destroy()`.
+    pub async fn delete_player(&self, guild_id: impl Into<GuildId>) -> LavalinkResult<()> {
+        self.destroy(guild_id).await
+    }
+}
+
+impl PlayerContext {
+    /// Plays the track, if nothing else is playing.
+    pub async fn play(&self, track: &Track) -> LavalinkResult<()> {
+        self.client.play(self.guild_id, track.clone()).start().await
+    }
+
+    /// Plays the track, replacing the currently playing one.
+    pub async fn play_now(&self, track: &Track) -> LavalinkResult<()> {
+        self.client
+            .play(self.guild_id, track.clone())
+            .replace(true)
+            .start()
+            .await
+    }
+
+    /// Adds the track to the queue.
+    pub fn queue(&self, track: Track) -> LavalinkResult<()> {
+        self.client.play(self.guild_id, track).queue()
+    }
+
+    /// Gets a copy of the queue, the first item being the currently playing track.
+    pub async fn get_queue(&self) -> Vec<TrackQueue> {
+        self.client
+            .nodes()
+            .await
+            .get(&self.guild_id.0)
+            .map(|node| node.queue.clone())
+            .unwrap_or_default()
+    }
+
+    /// Gets the number of tracks on the queue, including the currently playing one.
+    #[must_use]
+    pub fn queue_len(&self) -> usize {
+        self.client.queue_len(self.guild_id)
+    }
+
+    /// Gets the estimated position of the currently playing track.
+    #[must_use]
+    pub fn position(&self) -> Option<Duration> {
+        self.client.position(self.guild_id)
+    }
+
+    /// Checks if the player is paused.
+    #[must_use]
+    pub fn paused(&self) -> bool {
+        self.client.is_paused(self.guild_id)
+    }
+
+    /// Gets a copy of the currently playing track.
+    #[must_use]
+    pub fn now_playing(&self) -> Option<TrackQueue> {
+        let nodes = self.client.inner.lock().nodes.clone();
+        let now_playing = nodes
+            .get(&self.guild_id.0)
+            .and_then(|node| node.now_playing.clone());
+        now_playing
+    }
+
+    /// Shuffles the queue, keeping the currently playing track first.
+    pub async fn shuffle(&self) -> LavalinkResult<()> {
+        let guild_lock = self.client.guild_lock(self.guild_id);
+        let _guard = guild_lock.lock().await;
+
+        self.client.with_node(self.guild_id, |node| {
+            let skip = usize::from(node.now_playing.is_some()).min(node.queue.len());
+            node.queue[skip..].shuffle(&mut rand::thread_rng());
+        })
+    }
+
+    /// Removes the track at the index of the queue.
+    ///
+    /// Returns None if there's no track at the index, or it's the currently playing one.
+    pub async fn remove(&self, index: usize) -> LavalinkResult<Option<TrackQueue>> {
+        let guild_lock = self.client.guild_lock(self.guild_id);
+        let _guard = guild_lock.lock().await;
+
+        self.client.with_node(self.guild_id, |node| {
+            let skip = usize::from(node.now_playing.is_some());
+
+            if index < skip || index >= node.queue.len() {
+                return None;
+            }
+
+            Some(node.queue.remove(index))
+        })
+    }
+
+    /// Moves the track at the index `from` to the index `to` of the queue.
+    ///
+    /// Returns false if any of the indices is out of the queue, or the currently playing track.
+    pub async fn move_track(&self, from: usize, to: usize) -> LavalinkResult<bool> {
+        let guild_lock = self.client.guild_lock(self.guild_id);
+        let _guard = guild_lock.lock().await;
+
+        self.client.with_node(self.guild_id, |node| {
+            let skip = usize::from(node.now_playing.is_some());
+            let len = node.queue.len();
+
+            if from < skip || to < skip || from >= len || to >= len {
+                return false;
+            }
+
+            let track = node.queue.remove(from);
+            node.queue.insert(to, track);
+
+            true
+        })
+    }
+
+    /// Removes every track from the queue, except the currently playing one.
+    pub async fn clear(&self) -> LavalinkResult<()> {
+        let guild_lock = self.client.guild_lock(self.guild_id);
+        let _guard = guild_lock.lock().await;
+
+        self.client.with_node(self.guild_id, |node| {
+            let skip = usize::from(node.now_playing.is_some()).min(node.queue.len());
+            node.queue.truncate(skip);
+        })
+    }
+
+    /// Skips to the track at the index of the queue, removing the tracks before it.
+    ///
+    /// The index 0 is the currently playing track, if any, in which case nothing is skipped.
+    ///
+    /// Returns `LavalinkError::QueueIndexOutOfRange` if there's no track at the index.
+    pub async fn skip_to(&self, index: usize) -> LavalinkResult<()> {
+        let guild_lock = self.client.guild_lock(self.guild_id);
+        let _guard = guild_lock.lock().await;
+
+        let is_playing = self.client.with_node(self.guild_id, |node| {
+            if index >= node.queue.len() {
+                return Err(LavalinkError::QueueIndexOutOfRange(index, node.queue.len()));
+            }
+
+            let skip = usize::from(node.now_playing.is_some());
+
+            if index > skip {
+                node.queue.drain(skip..index);
+            }
+
+            Ok(node.now_playing.is_some())
+        })??;
+
+        // If nothing is playing, the queue loop will start with the track at the index.
+        if is_playing && index > 0 {
+            self.client.skip_and_play_next_locked(self.guild_id).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Skips to the next track, stopping the player if the queue is empty.
+    pub async fn skip(&self) -> LavalinkResult<()> {
+        if self.client.skip(self.guild_id).await.is_none() {
+            self.client.stop(self.guild_id).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Stops the currently playing track.
+    pub async fn stop_now(&self) -> LavalinkResult<()> {
+        self.client.stop(self.guild_id).await
+    }
+
+    /// Sets the pause status.
+    pub async fn set_pause(&self, pause: bool) -> LavalinkResult<()> {
+        self.client.set_pause(self.guild_id, pause).await
+    }
+
+    /// Sets the volume of the player.
+    pub async fn set_volume(&self, volume: u16) -> LavalinkResult<()> {
+        self.client.volume(self.guild_id, volume).await
+    }
+
+    /// Jumps to a specific time in the currently playing track.
+    pub async fn set_position(&self, position: Duration) -> LavalinkResult<()> {
+        self.client.seek(self.guild_id, position).await
+    }
+
+    /// Destroys the player.
+    pub async fn close(self) -> LavalinkResult<()> {
+        self.client.destroy(self.guild_id).await
+    }
+}
@@ -0,0 +1,51 @@
+use async_trait::async_trait;
+use futures::io::{AsyncRead, AsyncWrite};
+
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+
+/// A transport the lavalink websocket can run over, like a Unix socket or an in-memory duplex
+/// stream.
+///
+/// Tokio streams can be used by wrapping them in `async_tungstenite::tokio::TokioAdapter`.
+pub trait Transport: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Transport for T {}
+
+/// Opens the transport used for the lavalink websocket, instead of a TCP connection.
+///
+/// The websocket handshake is done over the returned transport without TLS, so the connector is
+/// responsible for encrypting it if needed.
+///
+/// The REST requests are not affected, and still go through HTTP to the host and port. Tests
+/// without a network can run the websocket over an in-memory stream, like
+/// `tokio::io::duplex()`, but not the methods that call the REST API.
+#[async_trait]
+pub trait Connector: Send + Sync {
+    /// Opens a new transport to the host, called every time the websocket (re)connects.
+    async fn connect(&self, host: &str, port: u16) -> io::Result<Box<dyn Transport>>;
+}
+
+/// A shared handle to a `Connector`, used to store it on the builder and the client.
+#[derive(Clone)]
+pub struct SharedConnector(pub Arc<dyn Connector>);
+
+impl SharedConnector {
+    #[must_use]
+    pub fn new(connector: impl Connector + 'static) -> Self {
+        Self(Arc::new(connector))
+    }
+}
+
+impl fmt::Debug for SharedConnector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("SharedConnector { .. }")
+    }
+}
+
+impl PartialEq for SharedConnector {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
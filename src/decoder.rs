@@ -0,0 +1,189 @@
+use crate::model::Info;
+
+use std::convert::TryInto;
+
+/// The flag of the message header set when the track info has a version byte.
+const TRACK_INFO_VERSIONED: u32 = 1;
+
+/// Decodes the information of a track from its base64 blob, without asking lavalink.
+///
+/// Supports the versions 1 to 3 of the lavaplayer track format. Returns None for unknown
+/// versions or malformed tracks, which should be decoded with the REST API instead.
+#[must_use]
+pub fn decode_track(track: &str) -> Option<Info> {
+    let bytes = base64::decode(track).ok()?;
+    let mut reader = Reader { bytes: &bytes };
+
+    let header = reader.u32()?;
+    let flags = header >> 30;
+    let size = (header & 0x3FFF_FFFF) as usize;
+
+    // The position is the last field of the message, after any source specific data.
+    let message = reader.bytes.get(..size)?;
+    let position = u64::from_be_bytes(
+        message
+            .get(message.len().checked_sub(8)?..)?
+            .try_into()
+            .ok()?,
+    );
+
+    let mut reader = Reader { bytes: message };
+
+    let version = if flags & TRACK_INFO_VERSIONED == 0 {
+        1
+    } else {
+        reader.u8()?
+    };
+
+    if !(1..=3).contains(&version) {
+        return None;
+    }
+
+    let title = reader.utf()?;
+    let author = reader.utf()?;
+    let length = reader.u64()?;
+    let identifier = reader.utf()?;
+    let is_stream = reader.u8()? != 0;
+
+    // Version 1 has no uri.
+    let uri = if version >= 2 {
+        reader.nullable_utf()?
+    } else {
+        None
+    };
+
+    if version >= 3 {
+        // The artwork url and the isrc.
+        reader.nullable_utf()?;
+        reader.nullable_utf()?;
+    }
+
+    let source_name = reader.utf()?;
+
+    Some(Info {
+        is_seekable: !is_stream,
+        is_stream,
+        identifier,
+        author,
+        length,
+        position,
+        title,
+        uri: uri.unwrap_or_default(),
+        source_name: Some(source_name),
+    })
+}
+
+/// Reads the big endian values of a java `DataInput`.
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.bytes.len() < len {
+            return None;
+        }
+
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+
+        Some(taken)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        Some(u16::from_be_bytes(self.take(2)?.try_into().ok()?))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_be_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_be_bytes(self.take(8)?.try_into().ok()?))
+    }
+
+    /// Java's modified UTF-8 only differs from UTF-8 on nulls and surrogate pairs, which are
+    /// replaced if they can't be read.
+    fn utf(&mut self) -> Option<String> {
+        let len = self.u16()? as usize;
+        Some(String::from_utf8_lossy(self.take(len)?).into_owned())
+    }
+
+    /// The outer None is a truncated track, the inner one a missing string.
+    #[allow(clippy::option_option)]
+    fn nullable_utf(&mut self) -> Option<Option<String>> {
+        if self.u8()? == 0 {
+            Some(None)
+        } else {
+            self.utf().map(Some)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_v1() {
+        let info = decode_track(
+            "AAAAPwALTG9maSBTdHJlYW0ACUxvZmkgR2lybH//////////AAtqZktmUGZ5SlJkawEAB3lvdXR1YmUAAAAAAAAAAA==",
+        )
+        .unwrap();
+
+        assert_eq!(info.title, "Lofi Stream");
+        assert_eq!(info.author, "Lofi Girl");
+        assert_eq!(info.length, u64::MAX >> 1);
+        assert_eq!(info.identifier, "jfKfPfyJRdk");
+        assert!(info.is_stream);
+        assert!(!info.is_seekable);
+        assert_eq!(info.uri, "");
+        assert_eq!(info.source_name.as_deref(), Some("youtube"));
+        assert_eq!(info.position, 0);
+    }
+
+    #[test]
+    fn decodes_v2() {
+        let info = decode_track(
+            "QAAAjQIAJVJpY2sgQXN0bGV5IC0gTmV2ZXIgR29ubmEgR2l2ZSBZb3UgVXAADlJpY2tBc3RsZXlWRVZPAAAAAAADPCAAC2RRdzR3OVdnWGNRAAEAK2h0dHBzOi8vd3d3LnlvdXR1YmUuY29tL3dhdGNoP3Y9ZFF3NHc5V2dYY1EAB3lvdXR1YmUAAAAAAAAAAA==",
+        )
+        .unwrap();
+
+        assert_eq!(info.title, "Rick Astley - Never Gonna Give You Up");
+        assert_eq!(info.author, "RickAstleyVEVO");
+        assert_eq!(info.length, 212_000);
+        assert_eq!(info.identifier, "dQw4w9WgXcQ");
+        assert!(!info.is_stream);
+        assert!(info.is_seekable);
+        assert_eq!(info.uri, "https://www.youtube.com/watch?v=dQw4w9WgXcQ");
+        assert_eq!(info.source_name.as_deref(), Some("youtube"));
+        assert_eq!(info.position, 0);
+    }
+
+    #[test]
+    fn decodes_v3() {
+        let info = decode_track(
+            "QAAAwgMAF05ldmVyIEdvbm5hIEdpdmUgWW91IFVwAAtSaWNrIEFzdGxleQAAAAAAA0AIAAtkUXc0dzlXZ1hjUQABACtodHRwczovL3d3dy55b3V0dWJlLmNvbS93YXRjaD92PWRRdzR3OVdnWGNRAQA0aHR0cHM6Ly9pLnl0aW1nLmNvbS92aS9kUXc0dzlXZ1hjUS9tYXhyZXNkZWZhdWx0LmpwZwEADEdCQVJMOTMwMDEzNQAHeW91dHViZQAAAAAAABOI",
+        )
+        .unwrap();
+
+        assert_eq!(info.title, "Never Gonna Give You Up");
+        assert_eq!(info.author, "Rick Astley");
+        assert_eq!(info.length, 213_000);
+        assert_eq!(info.identifier, "dQw4w9WgXcQ");
+        assert_eq!(info.uri, "https://www.youtube.com/watch?v=dQw4w9WgXcQ");
+        assert_eq!(info.source_name.as_deref(), Some("youtube"));
+        assert_eq!(info.position, 5000);
+    }
+
+    #[test]
+    fn rejects_malformed_tracks() {
+        assert!(decode_track("not base64").is_none());
+        // The v2 track above, cut in the middle of the title.
+        assert!(decode_track("QAAAjQIAJVJpY2sgQXN0bGV5").is_none());
+    }
+}
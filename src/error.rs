@@ -1,8 +1,15 @@
 use std::{
     error::Error,
     fmt::{Display, Formatter, Result},
+    time::Duration,
 };
 
+use crate::model::{Band, Exception, WriterDiagnostics};
+#[cfg(feature = "discord-gateway")]
+use crate::model::{ChannelId, GuildId};
+#[cfg(feature = "discord-gateway")]
+use crate::voice::{CONNECT, SPEAK};
+
 use async_tungstenite::tungstenite::error::Error as TungsteniteError;
 use reqwest::{header::InvalidHeaderValue, Error as ReqwestError};
 use tokio::sync::mpsc::error::SendError;
@@ -13,22 +20,81 @@ pub type LavalinkResult<T> = std::result::Result<T, LavalinkError>;
 #[allow(clippy::module_name_repetitions)]
 pub enum LavalinkError {
     /// TungsteniteError redirect.
-    ErrorWebsocketPayload(TungsteniteError),
+    ErrorWebsocketPayload(Box<TungsteniteError>),
     /// Invalid Headers redirect.
     InvalidHeaderValue(InvalidHeaderValue),
     /// ReqwestError redirect.
     ReqwestError(ReqwestError),
+    /// When a lavalink response could not be parsed.
+    InvalidResponse(serde_json::Error),
     /// Returned by [`PlayParameters::queue`] if no queue is present.
     ///
     /// [`PlayParameters::queue`]: crate::builders::PlayParameters
     NoSessionPresent,
-    /// When joining a voice channel times out.
-    #[cfg(feature = "discord-gateway")]
+    /// When leaving a voice channel, or waiting for a track to start, times out.
     Timeout,
     #[cfg(feature = "discord-gateway")]
     MissingConnectionField(&'static str),
+    /// When discord didn't send the voice server update after joining the voice channel of the
+    /// guild, usually because of missing permissions or a wrong channel id.
+    #[cfg(feature = "discord-gateway")]
+    JoinTimedOut(GuildId, ChannelId),
+    /// When the bot lacks the permissions to join a voice channel, with the bitfield of the
+    /// missing ones.
+    #[cfg(feature = "discord-gateway")]
+    MissingPermissions(u64),
     MissingLavalinkSocket,
     ChannelSendError,
+    /// When the message could not be sent to, or written by, the lavalink websocket writer.
+    WriterChannelClosed(Box<WriterDiagnostics>),
+    /// When loading a track returns no results.
+    NoTracksFound,
+    /// When an equalizer band has an index over 14, or a gain outside of -0.25 to 1.0.
+    InvalidBand(Band),
+    /// When the client builder has an invalid value, with the name of the field and the reason.
+    InvalidConfig(&'static str, String),
+    /// When a session is created for a new guild while the client is draining.
+    Draining,
+    /// When a track can't be queued because of the queue policy of the guild.
+    QueueRejected(QueueRejected),
+    /// When a track fails to start, with the exception reported by lavalink.
+    TrackFailed(Box<Exception>),
+    /// When the server didn't send the session id the sessions endpoints of lavalink 3.7 and
+    /// later need.
+    NoServerSession,
+    /// When a queue index is out of range, with the index and the length of the queue.
+    QueueIndexOutOfRange(usize, usize),
+}
+
+/// The reason a track was not queued.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QueueRejected {
+    /// The queue has reached the maximum length.
+    QueueFull(usize),
+    /// The track is longer than the maximum duration.
+    TrackTooLong(Duration),
+    /// The track is already on the queue, with this identifier.
+    Duplicate(String),
+}
+
+impl Display for QueueRejected {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        match self {
+            QueueRejected::QueueFull(max) => {
+                write!(f, "The queue is full, it can only have {} tracks.", max)
+            }
+            QueueRejected::TrackTooLong(max) => {
+                write!(
+                    f,
+                    "The track is too long, the maximum duration is {} seconds.",
+                    max.as_secs()
+                )
+            }
+            QueueRejected::Duplicate(identifier) => {
+                write!(f, "The track `{}` is already on the queue.", identifier)
+            }
+        }
+    }
 }
 
 impl Error for LavalinkError {}
@@ -49,33 +115,100 @@ impl Display for LavalinkError {
             LavalinkError::ReqwestError(why) => {
                 write!(f, "Reqwest Error => {:?}", why)
             }
+            LavalinkError::InvalidResponse(why) => {
+                write!(f, "Invalid lavalink response => {:?}", why)
+            }
             LavalinkError::NoSessionPresent => {
                 write!(
                     f,
                     "Please, call client.create_session() for this method to work correctly."
                 )
             }
-            #[cfg(feature = "discord-gateway")]
             LavalinkError::Timeout => {
-                write!(f, "Joining the voice channel timed out.")
+                write!(f, "The operation timed out.")
             }
             #[cfg(feature = "discord-gateway")]
             &LavalinkError::MissingConnectionField(field) => {
                 write!(f, "Gateway connection is missing the field `{}`", field)
             }
+            #[cfg(feature = "discord-gateway")]
+            LavalinkError::JoinTimedOut(guild_id, channel_id) => {
+                write!(
+                    f,
+                    "Timed out joining the voice channel {} of the guild {}.",
+                    channel_id, guild_id
+                )
+            }
+            #[cfg(feature = "discord-gateway")]
+            &LavalinkError::MissingPermissions(missing) => {
+                let names = [(CONNECT, "CONNECT"), (SPEAK, "SPEAK")]
+                    .iter()
+                    .filter(|(permission, _)| missing & permission != 0)
+                    .map(|(_, name)| *name)
+                    .collect::<Vec<_>>();
+
+                write!(
+                    f,
+                    "The bot is missing the permissions {} in the voice channel.",
+                    names.join(", ")
+                )
+            }
             LavalinkError::MissingLavalinkSocket => {
                 write!(f, "Initialize a lavalink websocket connection.")
             }
             LavalinkError::ChannelSendError => {
                 write!(f, "The channel receiver is closed.")
             }
+            LavalinkError::WriterChannelClosed(diagnostics) => {
+                write!(
+                    f,
+                    "The lavalink websocket writer is not available ({}).",
+                    diagnostics
+                )
+            }
+            LavalinkError::NoTracksFound => {
+                write!(f, "No tracks were found.")
+            }
+            LavalinkError::InvalidBand(band) => {
+                write!(
+                    f,
+                    "Invalid equalizer band {} with gain {}",
+                    band.band, band.gain
+                )
+            }
+            LavalinkError::InvalidConfig(field, reason) => {
+                write!(
+                    f,
+                    "Invalid client configuration for `{}`: {}",
+                    field, reason
+                )
+            }
+            LavalinkError::Draining => {
+                write!(f, "The client is draining and doesn't accept new players.")
+            }
+            LavalinkError::QueueRejected(reason) => {
+                write!(f, "The track was not queued: {}", reason)
+            }
+            LavalinkError::TrackFailed(exception) => {
+                write!(f, "The track failed to start: {}", exception.message)
+            }
+            LavalinkError::NoServerSession => {
+                write!(f, "The server didn't send a session id on the websocket.")
+            }
+            LavalinkError::QueueIndexOutOfRange(index, len) => {
+                write!(
+                    f,
+                    "The index {} is out of range, the queue has {} tracks.",
+                    index, len
+                )
+            }
         }
     }
 }
 
 impl From<TungsteniteError> for LavalinkError {
     fn from(err: TungsteniteError) -> LavalinkError {
-        LavalinkError::ErrorWebsocketPayload(err)
+        LavalinkError::ErrorWebsocketPayload(Box::new(err))
     }
 }
 
@@ -85,12 +218,24 @@ impl From<InvalidHeaderValue> for LavalinkError {
     }
 }
 
+impl From<serde_json::Error> for LavalinkError {
+    fn from(err: serde_json::Error) -> Self {
+        LavalinkError::InvalidResponse(err)
+    }
+}
+
 impl From<ReqwestError> for LavalinkError {
     fn from(err: ReqwestError) -> LavalinkError {
         LavalinkError::ReqwestError(err)
     }
 }
 
+impl From<QueueRejected> for LavalinkError {
+    fn from(reason: QueueRejected) -> LavalinkError {
+        LavalinkError::QueueRejected(reason)
+    }
+}
+
 impl<T> From<SendError<T>> for LavalinkError {
     fn from(_: SendError<T>) -> LavalinkError {
         LavalinkError::ChannelSendError
@@ -1,28 +1,46 @@
+use crate::builders::TlsConfig;
+use crate::error::{LavalinkError, LavalinkResult};
 use crate::gateway::LavalinkEventHandler;
+use crate::model::outgoing::ConfigureResuming;
+#[cfg(feature = "discord-gateway")]
+use crate::model::outgoing::{GatewayPayload, Identify, Resume};
 use crate::model::*;
 #[cfg(feature = "discord-gateway")]
-use crate::voice::{raw_handle_event_voice_server_update, raw_handle_event_voice_state_update};
-use crate::LavalinkClient;
+use crate::voice::{
+    backfill_voice_sessions, raw_handle_event_voice_server_update,
+    raw_handle_event_voice_state_update, raw_handle_guild_voice_states,
+    raw_handle_member_voice_state, raw_handle_stage_suppress,
+};
+use crate::{LavalinkClient, WeakLavalinkClient};
 
+#[cfg(feature = "discord-gateway")]
+use async_tungstenite::tokio::connect_async;
 use async_tungstenite::{
-    tokio::connect_async,
-    tungstenite::handshake::client::generate_key,
+    client_async,
+    tokio::connect_async_with_tls_connector,
+    tungstenite::{handshake::client::generate_key, Error as TungsteniteError},
 };
+use futures::future::{BoxFuture, FutureExt};
 use futures::stream::StreamExt;
-use futures::SinkExt;
+use futures::{Future, Sink, SinkExt, Stream};
 use http::Request;
 #[cfg(feature = "discord-gateway")]
 use parking_lot::RwLock;
+use rand::Rng;
 #[cfg(feature = "discord-gateway")]
 use serde::Deserialize;
-#[cfg(feature = "discord-gateway")]
-use serde_json::json;
-#[cfg(feature = "discord-gateway")]
+use std::any::Any;
+use std::collections::HashMap;
+use std::panic::AssertUnwindSafe;
+use std::pin::Pin;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime};
 use tokio::sync::mpsc;
 
 use async_tungstenite::tungstenite::Message as TungsteniteMessage;
+use dashmap::mapref::entry::Entry;
+use dashmap::DashMap;
 use dashmap::try_result::TryResult;
 
 #[cfg(feature = "discord-gateway")]
@@ -47,16 +65,29 @@ struct BaseEventNoData {
 
 #[cfg(feature = "discord-gateway")]
 #[allow(clippy::too_many_lines)]
-pub async fn discord_event_loop(client: LavalinkClient, token: &str, mut wait_time: Duration) {
+pub async fn discord_event_loop(client: LavalinkClient, mut wait_time: Duration) {
     let reconnect = Arc::new(RwLock::new(false));
+    // Set when the bot token changed, so the next connection identifies instead of resuming.
+    let mut token_rotated = false;
     let was_reconnected = Arc::new(RwLock::new(false));
     let session_id = Arc::new(RwLock::new(String::new()));
     let seq = Arc::new(RwLock::new(0_usize));
     let rec_seq = Arc::new(RwLock::new(0_usize));
 
     loop {
-        let headers = client.discord_gateway_data().lock().headers.clone();
-        let socket_uri = client.discord_gateway_data().lock().socket_uri;
+        if client.is_shutdown() {
+            break;
+        }
+
+        let (headers, socket_uri, token) = {
+            let discord_ws = client.discord_gateway_data();
+            let discord_ws = discord_ws.lock();
+            (
+                discord_ws.headers.clone(),
+                discord_ws.socket_uri,
+                discord_ws.bot_token.clone(),
+            )
+        };
 
         let mut url_builder = Request::builder();
 
@@ -108,7 +139,9 @@ pub async fn discord_event_loop(client: LavalinkClient, token: &str, mut wait_ti
                         // thread 'tokio-runtime-worker' panicked at 'called `Result::unwrap()` on an `Err` value: SendError("{\"op\":1,\"d\":64}")', /home/nitsuga/.cargo/git/checkouts/lavalink-rs-38e41c1b59bb345b/0900b34/src/event_loops.rs:108:78
                         // note: run with `RUST_BACKTRACE=1` environment variable to display a backtrace
 
-                        drop(tx_hb.send(format!(r#"{{"op":1,"d":{}}}"#, val)));
+                        if tx_hb.send(GatewayPayload::new(1, val).to_json()).is_err() {
+                            break;
+                        }
                         val += 1;
                     }
                 });
@@ -117,7 +150,12 @@ pub async fn discord_event_loop(client: LavalinkClient, token: &str, mut wait_ti
             None => panic!("Failed to connect to the discord gateway: No Reason Provided"),
         }
 
-        let identify = if *reconnect.read() {
+        if token_rotated {
+            token_rotated = false;
+            *reconnect.write() = false;
+        }
+
+        let payload = if *reconnect.read() {
             *reconnect.write() = false;
             *was_reconnected.write() = true;
             let session_id = session_id.read().clone();
@@ -137,33 +175,36 @@ pub async fn discord_event_loop(client: LavalinkClient, token: &str, mut wait_ti
 
             *rec_seq.write() = seq;
 
-            json!({
-                "op": 6,
-                "d": {
-                  "token": token,
-                  "session_id": &session_id,
-                  "seq": seq
-                }
-            })
+            let resume = Resume {
+                token: &token,
+                session_id: &session_id,
+                seq,
+            };
+
+            GatewayPayload::new(6, resume).to_json()
         } else {
-            json!({
-                "op": 2,
-                "d": {
-                    //"compress": true, // implement this when i figure out how to deserialize binary
-                    "large_threshold": 250,
-                    "token": token,
-                    "intents": 1 << 7, // GUILD_VOICE_STATES // 128
-                    "v": "v9",
-                    "properties": {
-                        "$browser": "lavalink-rs",
-                        "$device": "lavalink-rs",
-                        "$os": std::env::consts::OS,
-                    },
-                },
-            })
-        };
+            let (presence, intents, properties) = {
+                let gateway_data = client.discord_gateway_data();
+                let gateway_data = gateway_data.lock();
+                (
+                    gateway_data.presence.clone(),
+                    gateway_data.intents,
+                    gateway_data.identify_properties.clone(),
+                )
+            };
 
-        let payload = serde_json::to_string(&identify).unwrap();
+            // `compress` isn't set until the binary payloads can be deserialized.
+            let identify = Identify {
+                large_threshold: 250,
+                token: &token,
+                intents,
+                v: "v9",
+                properties: &properties,
+                presence: presence.as_ref(),
+            };
+
+            GatewayPayload::new(2, identify).to_json()
+        };
 
         let identify_request = write.send(TungsteniteMessage::text(payload)).await;
 
@@ -187,11 +228,11 @@ pub async fn discord_event_loop(client: LavalinkClient, token: &str, mut wait_ti
                     let resp_text = resp.to_string();
                     if resp_text.starts_with("Discord") {
                         *reconnect_clone.write() = true;
-                        tx_hb.send("reconnect".to_string()).unwrap();
+                        drop(tx_hb.send("reconnect".to_string()));
                         continue 'events;
                     }
 
-                    tx_hb.send("reconnect".to_string()).unwrap();
+                    drop(tx_hb.send("reconnect".to_string()));
                     break 'events;
                 } else if let Ok(x) = resp.clone().into_text() {
                     x
@@ -207,13 +248,25 @@ pub async fn discord_event_loop(client: LavalinkClient, token: &str, mut wait_ti
                     *seq_clone.write() = s;
                 }
 
-                match event_name.t.unwrap_or_default().as_str() {
+                let event_type = event_name.t.unwrap_or_default();
+
+                if !matches!(
+                    event_type.as_str(),
+                    "" | "VOICE_STATE_UPDATE" | "VOICE_SERVER_UPDATE"
+                ) {
+                    forward_gateway_event(&client_clone, &text_resp);
+                }
+
+                match event_type.as_str() {
                     "READY" => {
                         let event: BaseEvent<EventReady> =
                             serde_json::from_str(&text_resp).unwrap();
 
                         *session_id_clone.write() = event.d.session_id;
                         info!("Lavalink discord gateway ready event received.");
+
+                        // The voice sessions are lost if the gateway session could not be resumed.
+                        backfill_voice_sessions(&client_clone);
                     }
                     "VOICE_STATE_UPDATE" => {
                         let event: BaseEvent<EventVoiceStateUpdate> =
@@ -221,6 +274,21 @@ pub async fn discord_event_loop(client: LavalinkClient, token: &str, mut wait_ti
                         debug!("Voice State Update");
                         debug!("{:#?}", event);
 
+                        raw_handle_member_voice_state(
+                            &client_clone,
+                            event.d.guild_id,
+                            event.d.user_id,
+                            event.d.member_voice_state(),
+                        );
+
+                        raw_handle_stage_suppress(
+                            &client_clone,
+                            event.d.guild_id,
+                            event.d.channel_id,
+                            event.d.user_id,
+                            event.d.suppress,
+                        );
+
                         raw_handle_event_voice_state_update(
                             &client_clone,
                             event.d.guild_id,
@@ -243,6 +311,26 @@ pub async fn discord_event_loop(client: LavalinkClient, token: &str, mut wait_ti
                         )
                         .await;
                     }
+                    "GUILD_CREATE" => {
+                        if let Ok(event) =
+                            serde_json::from_str::<BaseEvent<EventGuildCreate>>(&text_resp)
+                        {
+                            raw_handle_guild_voice_states(
+                                &client_clone,
+                                event.d.id,
+                                event.d.voice_states,
+                            );
+                        }
+                    }
+                    "GUILD_DELETE" => {
+                        if let Ok(event) =
+                            serde_json::from_str::<BaseEvent<EventGuildDelete>>(&text_resp)
+                        {
+                            let voice_states =
+                                client_clone.discord_gateway_data().lock().voice_states.clone();
+                            voice_states.remove(&event.d.id);
+                        }
+                    }
                     "RESUMED" => info!("Resumed the discord websocket."),
                     "" => (),
                     _ => debug!("Unknown event: {}", &text_resp),
@@ -261,6 +349,17 @@ pub async fn discord_event_loop(client: LavalinkClient, token: &str, mut wait_ti
                 break;
             }
 
+            if &v == "shutdown" {
+                drop(write.send(TungsteniteMessage::Close(None)).await);
+                break;
+            }
+
+            if &v == "rotate_token" {
+                token_rotated = true;
+                drop(write.send(TungsteniteMessage::Close(None)).await);
+                break;
+            }
+
             if let Err(why) = write.send(TungsteniteMessage::text(v)).await {
                 error!("Error sending discord event: {}", why);
             }
@@ -268,23 +367,802 @@ pub async fn discord_event_loop(client: LavalinkClient, token: &str, mut wait_ti
     }
 }
 
+/// Finds the track on the node, looking at the currently playing track first.
+fn resolve_track_queue(
+    client: &LavalinkClient,
+    guild_id: GuildId,
+    track: &str,
+) -> Option<TrackQueue> {
+    let nodes = client.inner.lock().nodes.clone();
+
+    let track_queue = if let TryResult::Present(node) = nodes.try_get(&guild_id.0) {
+        node.now_playing
+            .iter()
+            .chain(node.queue.iter())
+            .find(|i| i.track.track == track)
+            .cloned()
+    } else {
+        None
+    };
+
+    track_queue
+}
+
+/// Returns the track to retry, if retrying is enabled and the track was not retried already.
+fn track_to_retry(client: &LavalinkClient, event: &TrackException) -> Option<TrackQueue> {
+    let (retry_on_common_exception, nodes) = {
+        let client_lock = client.inner.lock();
+        (
+            client_lock.retry_on_common_exception,
+            client_lock.nodes.clone(),
+        )
+    };
+
+    if !retry_on_common_exception || event.exception.severity != ExceptionSeverity::Common {
+        return None;
+    }
+
+    let track_queue = event.track_queue.clone()?;
+    let identifier = track_queue.track.info.as_ref()?.identifier.clone();
+
+    let retry = if let TryResult::Present(mut node) = nodes.try_get_mut(&event.guild_id.0) {
+        if node.retried_track.as_ref() == Some(&identifier) {
+            None
+        } else {
+            node.retried_track = Some(identifier);
+
+            Some(track_queue)
+        }
+    } else {
+        None
+    };
+
+    retry
+}
+
+/// Forgets the retried track once a different track starts, so it can be retried again if it's
+/// played later.
+fn reset_retried_track(client: &LavalinkClient, track_start: &TrackStart) {
+    let identifier = track_start
+        .track_queue
+        .as_ref()
+        .and_then(|track_queue| track_queue.track.info.as_ref())
+        .map(|info| info.identifier.clone());
+
+    let nodes = client.inner.lock().nodes.clone();
+    let node = nodes.get_mut(&track_start.guild_id.0);
+
+    if let Some(mut node) = node {
+        if node.retried_track.is_some() && node.retried_track != identifier {
+            node.retried_track = None;
+        }
+    }
+}
+
+/// Loads the track again and plays it from the last known position, triggering the
+/// `track_exception` event if it fails.
+async fn retry_track(client: LavalinkClient, event: TrackException, track_queue: TrackQueue) {
+    let guild_id = event.guild_id;
+
+    if let Err(why) = replay_track(&client, guild_id, track_queue).await {
+        warn!("Error retrying track on guild {}: {}", guild_id, why);
+
+        broadcast_guild_event(&client, GuildEvent::TrackException(event.clone()));
+        dispatch_guild_event(
+            &client,
+            guild_id,
+            "track_exception",
+            |handler, client| async move {
+                handler.track_exception(client, event).await;
+            },
+        );
+    } else {
+        debug!("Retried track on guild {} after a common exception.", guild_id);
+    }
+}
+
+async fn replay_track(
+    client: &LavalinkClient,
+    guild_id: GuildId,
+    mut track_queue: TrackQueue,
+) -> LavalinkResult<()> {
+    let uri = track_queue
+        .track
+        .info
+        .as_ref()
+        .map(|info| info.uri.clone())
+        .unwrap_or_default();
+
+    let old_track = track_queue.track.track.clone();
+
+    track_queue.track = client
+        .get_tracks(uri)
+        .await?
+        .tracks
+        .into_iter()
+        .next()
+        .ok_or(LavalinkError::NoTracksFound)?;
+
+    let start_time = client
+        .position(guild_id)
+        .map_or(track_queue.start_time, |position| position.as_millis() as u64);
+
+    let nodes = client.inner.lock().nodes.clone();
+
+    if let TryResult::Present(mut node) = nodes.try_get_mut(&guild_id.0) {
+        if let Some(now_playing) = node.now_playing.as_mut() {
+            if now_playing.track.track == old_track {
+                now_playing.track = track_queue.track.clone();
+            }
+        }
+
+        if let Some(first) = node.queue.first_mut() {
+            if first.track.track == old_track {
+                first.track = track_queue.track.clone();
+            }
+        }
+    }
+
+    let socket = {
+        let client_lock = client.inner.lock();
+        let socket = client_lock
+            .socket_sender
+            .read()
+            .as_ref()
+            .ok_or(LavalinkError::MissingLavalinkSocket)?
+            .clone();
+        socket
+    };
+
+    let payload = Play {
+        track: track_queue.track.track,
+        no_replace: false,
+        start_time,
+        end_time: track_queue.end_time,
+        volume: None,
+    };
+
+    SendOpcode::Play(payload).send(guild_id, socket).await
+}
+
+/// Applies the stuck track policy, returning if anything was done.
+async fn recover_stuck_track(
+    client: &LavalinkClient,
+    recovered: &TrackStuckRecovered,
+) -> LavalinkResult<bool> {
+    let guild_id = recovered.guild_id;
+
+    match recovered.policy {
+        TrackStuckPolicy::Nothing => Ok(false),
+        TrackStuckPolicy::Skip => {
+            // Replaces the stuck track with the next one, or stops it if the queue finished.
+            client.skip_and_play_next(guild_id).await?;
+
+            Ok(true)
+        }
+        TrackStuckPolicy::Replay => {
+            let start_time = client
+                .position(guild_id)
+                .map_or(0, |position| position.as_millis() as u64);
+
+            let socket = client
+                .inner
+                .lock()
+                .socket_sender
+                .read()
+                .clone()
+                .ok_or(LavalinkError::MissingLavalinkSocket)?;
+
+            let payload = Play {
+                track: recovered.track.clone(),
+                no_replace: false,
+                start_time,
+                end_time: resolve_track_queue(client, guild_id, &recovered.track)
+                    .and_then(|track_queue| track_queue.end_time),
+                volume: None,
+            };
+
+            SendOpcode::Play(payload).send(guild_id, socket).await?;
+
+            Ok(true)
+        }
+    }
+}
+
+/// Sends the last voice update of the guild to lavalink again.
+async fn resend_voice_update(client: &LavalinkClient, guild_id: GuildId) -> LavalinkResult<()> {
+    let (nodes, socket) = {
+        let client_lock = client.inner.lock();
+        let socket = client_lock.socket_sender.read().clone();
+
+        (client_lock.nodes.clone(), socket)
+    };
+
+    let voice_update = match nodes.try_get(&guild_id.0) {
+        TryResult::Present(node) => node.voice_update.clone(),
+        _ => None,
+    };
+
+    let voice_update = voice_update.ok_or(LavalinkError::NoSessionPresent)?;
+    let socket = socket.ok_or(LavalinkError::MissingLavalinkSocket)?;
+
+    debug!("Resending the voice update on guild {}", guild_id);
+
+    SendOpcode::VoiceUpdate(voice_update)
+        .send(guild_id, socket)
+        .await
+}
+
+/// Joins the voice channel of the guild again, if enabled on the builder.
+#[cfg(feature = "discord-gateway")]
+fn rejoin_voice_channel(client: &LavalinkClient, guild_id: GuildId) {
+    let gateway_data = client.discord_gateway_data();
+
+    if !gateway_data.lock().rejoin_on_session_invalidated {
+        return;
+    }
+
+    let channel_id = gateway_data
+        .lock()
+        .connections
+        .get(&guild_id)
+        .and_then(|connection| connection.channel_id);
+
+    if let Some(channel_id) = channel_id {
+        let client = client.clone();
+
+        tokio::spawn(async move {
+            if let Err(why) = client.join(guild_id, channel_id).await {
+                error!("Error rejoining the voice channel on guild {}: {}", guild_id, why);
+            }
+        });
+    }
+}
+
+/// Sends the discord gateway event to every `gateway_events()` stream, parsing it only if there's
+/// one.
+#[cfg(feature = "discord-gateway")]
+fn forward_gateway_event(client: &LavalinkClient, text: &str) {
+    let sender = client.discord_gateway_data().lock().events.clone();
+
+    if sender.receiver_count() == 0 {
+        return;
+    }
+
+    if let Ok(event) = serde_json::from_str::<DiscordGatewayEvent>(text) {
+        drop(sender.send(event));
+    }
+}
+
+/// Stamps a lavalink event of the guild with the current time and its next sequence number.
+fn next_event_meta(client: &LavalinkClient, guild_id: GuildId) -> EventMeta {
+    let sequences = client.inner.lock().event_sequences.clone();
+
+    let mut sequence = sequences.entry(guild_id).or_insert(0);
+    *sequence += 1;
+
+    EventMeta {
+        received_at: SystemTime::now(),
+        sequence: *sequence,
+    }
+}
+
+/// How long the event queue of a guild is kept without receiving any event.
+const GUILD_DISPATCHER_IDLE: Duration = Duration::from_secs(60);
+
+/// Calls the event handler through the event queue of the guild, so the events of a guild are
+/// handled in order while the ones of different guilds are handled concurrently.
+///
+/// If the handler panics, the panic is logged and sent to `event_error` with the event name.
+pub(crate) fn dispatch_guild_event<F, Fut>(
+    client: &LavalinkClient,
+    guild_id: GuildId,
+    event_name: &'static str,
+    event: F,
+) where
+    F: FnOnce(Arc<dyn LavalinkEventHandler + Send + Sync>, LavalinkClient) -> Fut,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    dispatch_event(client, Some(guild_id), event_name, event);
+}
+
+/// Calls the handler of an event that's not tied to a guild, like `stats`, through the event
+/// queue of the client, so a slow handler doesn't hold up the lavalink websocket.
+///
+/// Panics are handled like in `dispatch_guild_event()`.
+pub(crate) fn dispatch_client_event<F, Fut>(
+    client: &LavalinkClient,
+    event_name: &'static str,
+    event: F,
+) where
+    F: FnOnce(Arc<dyn LavalinkEventHandler + Send + Sync>, LavalinkClient) -> Fut,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    dispatch_event(client, None, event_name, event);
+}
+
+/// Queues the handler call on the event queue of the guild, or of the client if None.
+fn dispatch_event<F, Fut>(
+    client: &LavalinkClient,
+    guild_id: Option<GuildId>,
+    event_name: &'static str,
+    event: F,
+) where
+    F: FnOnce(Arc<dyn LavalinkEventHandler + Send + Sync>, LavalinkClient) -> Fut,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let (handler, dispatchers) = {
+        let client_lock = client.inner.lock();
+        (
+            client_lock.handler.clone(),
+            client_lock.guild_dispatchers.clone(),
+        )
+    };
+
+    let future = event(handler.clone(), client.clone());
+    let client = client.clone();
+
+    let event: BoxFuture<'static, ()> = Box::pin(async move {
+        if let Err(panic) = AssertUnwindSafe(future).catch_unwind().await {
+            let message = panic_message(panic.as_ref());
+
+            match guild_id {
+                Some(guild_id) => error!(
+                    "The {} event handler panicked on guild {}: {}",
+                    event_name, guild_id, message
+                ),
+                None => error!("The {} event handler panicked: {}", event_name, message),
+            }
+
+            let event = EventError {
+                guild_id,
+                event_name: event_name.to_string(),
+                message,
+            };
+
+            if AssertUnwindSafe(handler.event_error(client, event))
+                .catch_unwind()
+                .await
+                .is_err()
+            {
+                error!("The event_error handler panicked after the {} event.", event_name);
+            }
+        }
+    });
+
+    let mut sender = dispatchers
+        .entry(guild_id)
+        .or_insert_with(|| spawn_guild_dispatcher(dispatchers.clone(), guild_id));
+
+    // The queue stops if its task is aborted, so a new one takes its place.
+    if let Err(error) = sender.send(event) {
+        *sender = spawn_guild_dispatcher(dispatchers.clone(), guild_id);
+        drop(sender.send(error.0));
+    }
+}
+
+/// Gets the message of a panic, which is a `&str` or a `String` when it was raised by `panic!`.
+fn panic_message(panic: &(dyn Any + Send)) -> String {
+    panic
+        .downcast_ref::<&str>()
+        .map(|message| (*message).to_string())
+        .or_else(|| panic.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string())
+}
+
+/// Starts the event queue of a guild, or of the client if None, which stops itself once it's
+/// idle.
+fn spawn_guild_dispatcher(
+    dispatchers: Arc<DashMap<Option<GuildId>, mpsc::UnboundedSender<BoxFuture<'static, ()>>>>,
+    guild_id: Option<GuildId>,
+) -> mpsc::UnboundedSender<BoxFuture<'static, ()>> {
+    let (sender, mut receiver) = mpsc::unbounded_channel::<BoxFuture<'static, ()>>();
+
+    tokio::spawn(async move {
+        loop {
+            if let Ok(Some(event)) =
+                tokio::time::timeout(GUILD_DISPATCHER_IDLE, receiver.recv()).await
+            {
+                event.await;
+                continue;
+            }
+
+            // No event can be queued while the entry is held, so none is lost when stopping.
+            let entry = dispatchers.entry(guild_id);
+
+            if let Ok(event) = receiver.try_recv() {
+                drop(entry);
+                event.await;
+                continue;
+            }
+
+            if let Entry::Occupied(entry) = entry {
+                entry.remove();
+            }
+
+            break;
+        }
+    });
+
+    sender
+}
+
+/// Sends the event to every `guild_events()` stream, ignoring the error if there are none, and
+/// buffers it for `events_with_replay()`.
+fn broadcast_guild_event(client: &LavalinkClient, event: GuildEvent) {
+    client.inner.lock().push_event(event);
+}
+
+/// Queues a track recommended from the seed track, returning it if one was found.
+async fn autoplay_track(
+    client: &LavalinkClient,
+    guild_id: GuildId,
+    seed: &TrackQueue,
+) -> LavalinkResult<Option<TrackQueue>> {
+    let info = match seed.track.info.as_ref() {
+        Some(info) => info,
+        None => return Ok(None),
+    };
+
+    let tracks = client.get_tracks(info.recommendation_query()).await?;
+
+    let track = tracks.tracks.into_iter().find(|track| {
+        track
+            .info
+            .as_ref()
+            .map_or(true, |i| i.identifier != info.identifier)
+    });
+
+    let track = match track {
+        Some(track) => track,
+        None => return Ok(None),
+    };
+
+    let play = client.play(guild_id, track);
+    play.queue()?;
+
+    Ok(Some(play.to_track_queue()))
+}
+
+/// Checks if the queue already moved on from the finished track, because the next one was
+/// preloaded to avoid the gap between them.
+fn was_preloaded(client: &LavalinkClient, track_finish: &TrackFinish) -> bool {
+    let (gapless, nodes) = {
+        let client_lock = client.inner.lock();
+        (client_lock.gapless_preload.is_some(), client_lock.nodes.clone())
+    };
+
+    if !gapless {
+        return false;
+    }
+
+    let preloaded = nodes.get(&track_finish.guild_id.0).map_or(false, |node| {
+        node.now_playing
+            .as_ref()
+            .map_or(false, |now_playing| now_playing.track.track != track_finish.track)
+    });
+    preloaded
+}
+
+/// Sends a websocket message with an op or event type that's not modeled to the handler.
+fn unknown_event(client: &LavalinkClient, text: &str) {
+    if let Ok(data) = serde_json::from_str(text) {
+        let session_id = client.inner.lock().server_session_id.clone();
+
+        dispatch_client_event(client, "unknown_event", |handler, client| async move {
+            handler
+                .unknown_event(client, UnknownEvent { session_id, data })
+                .await;
+        });
+    }
+}
+
+/// Triggers the `drained` event if the client is draining and no players are active anymore.
+pub fn check_drained(client: &LavalinkClient) {
+    let nodes = {
+        let client_lock = client.inner.lock();
+
+        if !client_lock.draining || client_lock.drain_notified {
+            return;
+        }
+
+        client_lock.nodes.clone()
+    };
+
+    let active = nodes
+        .iter()
+        .any(|node| node.now_playing.is_some() || !node.queue.is_empty());
+
+    if active {
+        return;
+    }
+
+    {
+        let mut client_lock = client.inner.lock();
+
+        if client_lock.drain_notified {
+            return;
+        }
+
+        client_lock.drain_notified = true;
+    }
+
+    info!("The client is drained, no players are active.");
+    dispatch_client_event(client, "drained", |handler, client| async move {
+        handler.drained(client).await;
+    });
+}
+
+type WsWrite = Pin<Box<dyn Sink<TungsteniteMessage, Error = TungsteniteError> + Send>>;
+type WsRead = Pin<Box<dyn Stream<Item = Result<TungsteniteMessage, TungsteniteError>> + Send>>;
+
+/// Connects to the lavalink websocket, through the custom connector if there's one.
+///
+/// Also returns if lavalink resumed the previous session, from the `Session-Resumed` header.
+async fn connect_lavalink(
+    client: &LavalinkClient,
+    url: Request<()>,
+    host: &str,
+) -> Result<(WsWrite, WsRead, bool), TungsteniteError> {
+    let (connector, port, tls_connector) = {
+        let client_lock = client.inner.lock();
+        (
+            client_lock.connector.clone(),
+            client_lock.port,
+            client_lock
+                .tls_config
+                .as_ref()
+                .map(TlsConfig::websocket_connector),
+        )
+    };
+
+    if let Some(connector) = connector {
+        let transport = connector.0.connect(host, port).await?;
+        let (ws_stream, response) = client_async(url, transport).await?;
+        let (write, read) = ws_stream.split();
+        let is_resumed = is_session_resumed(&response);
+
+        Ok((Box::pin(write), Box::pin(read), is_resumed))
+    } else {
+        let (ws_stream, response) = connect_async_with_tls_connector(url, tls_connector).await?;
+        let (write, read) = ws_stream.split();
+        let is_resumed = is_session_resumed(&response);
+
+        Ok((Box::pin(write), Box::pin(read), is_resumed))
+    }
+}
+
+fn is_session_resumed<T>(response: &http::Response<T>) -> bool {
+    response
+        .headers()
+        .get("Session-Resumed")
+        .map_or(false, |resumed| resumed == "true")
+}
+
+/// Sets the players up again after reconnecting to a lavalink server that lost them, sending the
+/// voice updates and, if `auto_restore_players` is enabled, the tracks that were playing.
+async fn restore_players(client: LavalinkClient) {
+    let (nodes, auto_restore_players) = {
+        let client_lock = client.inner.lock();
+        (client_lock.nodes.clone(), client_lock.auto_restore_players)
+    };
+
+    let guild_ids = nodes.iter().map(|node| *node.key()).collect::<Vec<_>>();
+
+    for guild_id in guild_ids {
+        let guild_id = GuildId(guild_id);
+
+        // The player is restored from the node, which no other operation may change meanwhile.
+        let guild_lock = client.guild_lock(guild_id);
+        let _guard = guild_lock.lock().await;
+
+        if let Err(why) = resend_voice_update(&client, guild_id).await {
+            warn!(
+                "Error resending the voice update on guild {} after lavalink restarted: {}",
+                guild_id, why
+            );
+            continue;
+        }
+
+        if !auto_restore_players {
+            continue;
+        }
+
+        if let Err(why) = restore_track(&client, guild_id).await {
+            warn!(
+                "Error restoring the track on guild {} after lavalink restarted: {}",
+                guild_id, why
+            );
+        }
+    }
+}
+
+/// Updates the nodes with the players the server kept for the resumed session, emitting
+/// `player_resumed` for every guild still playing.
+///
+/// Needs the session id of lavalink 3.7 and later. The guilds without a node are ignored, and the
+/// nodes the server has no playing player for are left to the drift check.
+async fn sync_resumed_players(client: LavalinkClient) {
+    let remote_players = match client.get_remote_players().await {
+        Ok(remote_players) => remote_players,
+        Err(why) => {
+            warn!("Failed to get the players of the resumed session: {}", why);
+            return;
+        }
+    };
+
+    let nodes = client.inner.lock().nodes.clone();
+
+    for (guild_id, player) in remote_players {
+        let track = match player["track"]["encoded"].as_str() {
+            Some(encoded) => Track {
+                track: encoded.to_string(),
+                info: serde_json::from_value(player["track"]["info"].clone()).ok(),
+            },
+            None => continue,
+        };
+
+        let remote = RemotePlayerState::from_value(&player);
+        let state = serde_json::from_value::<PlayerUpdateState>(player["state"].clone()).ok();
+        let filters = serde_json::from_value::<Filters>(player["filters"].clone()).ok();
+
+        let guild_lock = client.guild_lock(guild_id);
+        let _guard = guild_lock.lock().await;
+
+        if let Some(mut node) = nodes.get_mut(&guild_id.0) {
+            let same_track = node
+                .now_playing
+                .as_ref()
+                .map(|now_playing| &now_playing.track.track)
+                == Some(&track.track);
+
+            // The queued track keeps its metadata, like the requester.
+            if !same_track {
+                node.now_playing = Some(TrackQueue {
+                    track: track.clone(),
+                    ..TrackQueue::default()
+                });
+            }
+
+            node.is_paused = remote.paused;
+
+            if let Some(volume) = remote.volume {
+                node.volume = volume;
+            }
+
+            if state.is_some() {
+                node.player_state = state;
+            }
+
+            if filters.is_some() {
+                node.filters = filters;
+            }
+        } else {
+            continue;
+        }
+
+        let event = PlayerResumed {
+            guild_id,
+            track,
+            state,
+            paused: remote.paused,
+            volume: remote.volume,
+        };
+
+        dispatch_guild_event(
+            &client,
+            guild_id,
+            "player_resumed",
+            |handler, client| async move {
+                handler.player_resumed(client, event).await;
+            },
+        );
+    }
+}
+
+/// Plays the track the guild was playing again, from its last known position.
+///
+/// The guild lock has to be held, so the node doesn't change meanwhile.
+async fn restore_track(client: &LavalinkClient, guild_id: GuildId) -> LavalinkResult<()> {
+    let nodes = client.inner.lock().nodes.clone();
+
+    let playing = nodes.get(&guild_id.0).and_then(|node| {
+        Some((
+            node.now_playing.clone()?,
+            node.player_state,
+            node.is_paused,
+            node.volume,
+            node.filters.clone(),
+        ))
+    });
+
+    if let Some((track_queue, player_state, is_paused, volume, filters)) = playing {
+        // The track stopped when the websocket disconnected, not when it reconnected.
+        let disconnected_at = client
+            .inner
+            .lock()
+            .disconnected_at
+            .unwrap_or_else(SystemTime::now);
+
+        let start_time = player_state.map_or(track_queue.start_time, |state| {
+            let mut position = state.position();
+
+            if !is_paused {
+                position += state.age_at(disconnected_at);
+            }
+
+            let position = position.as_millis() as u64;
+
+            match track_queue.track.info.as_ref() {
+                Some(info) => position.min(info.length),
+                None => position,
+            }
+        });
+
+        let socket = client
+            .inner
+            .lock()
+            .socket_sender
+            .read()
+            .clone()
+            .ok_or(LavalinkError::MissingLavalinkSocket)?;
+
+        let payload = Play {
+            track: track_queue.track.track,
+            no_replace: false,
+            start_time,
+            end_time: track_queue.end_time,
+            volume: Some(volume),
+        };
+
+        SendOpcode::Play(payload)
+            .send(guild_id, socket.clone())
+            .await?;
+
+        if let Some(filters) = filters {
+            SendOpcode::Filters(Box::new(filters))
+                .send(guild_id, socket.clone())
+                .await?;
+        }
+
+        if is_paused {
+            SendOpcode::Pause(Pause { pause: true })
+                .send(guild_id, socket)
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
 #[allow(clippy::too_many_lines)]
-pub async fn lavalink_event_loop(
-    handler: impl LavalinkEventHandler + Send + Sync + 'static,
-    client: LavalinkClient,
-    host: String,
-) {
+pub async fn lavalink_event_loop(client: LavalinkClient) {
+    // The players are lost if a reconnection doesn't resume the session.
+    let mut has_connected = false;
+
     loop {
+        if client.is_shutdown() {
+            break;
+        }
+
         debug!("Starting lavalink event loop.");
 
+        let (host, socket_uri) = {
+            let client_lock = client.inner.lock();
+            (
+                client_lock.current_host().to_string(),
+                client_lock.socket_uri.clone(),
+            )
+        };
+
         let mut url = Request::builder()
             .method("GET")
-            .header("Host", &host)
+            .header("Host", crate::uri_host(&host))
             .header("Connection", "Upgrade")
             .header("Upgrade", "websocket")
             .header("Sec-WebSocket-Version", "13")
             .header("Sec-WebSocket-Key", generate_key())
-            .uri(client.inner.lock().socket_uri.clone())
+            .uri(socket_uri)
             .body(())
             .unwrap();
 
@@ -294,9 +1172,15 @@ pub async fn lavalink_event_loop(
             ref_headers.extend(headers.clone());
         }
 
-        let (ws_stream, _) = match connect_async(url).await {
+        let (mut write, mut read, is_resumed) = match connect_lavalink(&client, url, &host).await {
             Err(why) => {
-                error!("Failed to connect to lavalink gateway: {}", why);
+                error!("Failed to connect to lavalink gateway on {}: {}", host, why);
+
+                // Every host is tried once before waiting.
+                if !client.inner.lock().use_next_host() {
+                    debug!("Trying the next lavalink host.");
+                    continue;
+                }
 
                 debug!("Waiting 15 seconds before reconnecting.");
                 tokio::time::sleep(Duration::from_secs(15)).await;
@@ -306,39 +1190,156 @@ pub async fn lavalink_event_loop(
             Ok(x) => x,
         };
 
-        let (mut write, mut read) = ws_stream.split();
         let (rx, mut tx) = mpsc::unbounded_channel();
 
-        *client.inner.lock().socket_sender.write() = Some(rx);
+        let writer_state = Arc::new(WriterState::default());
+        writer_state.alive.store(true, Ordering::SeqCst);
+
+        *client.inner.lock().socket_sender.write() = Some(SocketSender {
+            sender: rx,
+            state: writer_state.clone(),
+        });
 
         tokio::spawn(async move {
             while let Some(msg) = tx.recv().await {
-                if let Err(why) = write.send(msg.0).await {
+                writer_state.queue_depth.fetch_sub(1, Ordering::SeqCst);
+
+                let written = if let Err(why) = write.send(msg.0).await {
                     error!("Error sending lavalink event: {}", why);
-                }
+                    Err(LavalinkError::ErrorWebsocketPayload(Box::new(why)))
+                } else {
+                    *writer_state.last_write.lock() = Some(Instant::now());
+                    Ok(())
+                };
 
-                msg.1.send(()).unwrap();
+                // The sender might have stopped waiting for the acknowledgement.
+                let _ = msg.1.send(written);
             }
+
+            writer_state.alive.store(false, Ordering::SeqCst);
         });
 
+        let (resume_key, resume_timeout) = {
+            let client_lock = client.inner.lock();
+            (client_lock.resume_key.clone(), client_lock.resume_timeout)
+        };
+
+        if let Some(resume_key) = resume_key {
+            let payload = ConfigureResuming::new(&resume_key, resume_timeout.as_secs()).to_json();
+
+            let socket = client.inner.lock().socket_sender.read().clone();
+
+            if let Some(socket) = socket {
+                if let Err(why) = socket.send(TungsteniteMessage::text(payload)).await {
+                    error!("Error configuring the lavalink session resuming: {}", why);
+                }
+            }
+        }
+
+        if has_connected && !is_resumed {
+            info!("Lavalink didn't resume the session, restoring the players.");
+            tokio::spawn(restore_players(client.clone()));
+        }
+
+        has_connected = true;
+
+        let connected = Connected {
+            host: host.clone(),
+            resumed: is_resumed,
+        };
+        dispatch_client_event(&client, "connected", |handler, client| async move {
+            handler.connected(client, connected).await;
+        });
+
+        if client.protocol().is_none() {
+            let client = client.clone();
+
+            tokio::spawn(async move {
+                match client.detect_protocol().await {
+                    Ok(protocol) if !protocol.is_supported() => warn!(
+                        "The server runs {:?} {}, which doesn't support the v3 protocol used by this client.",
+                        protocol.flavor,
+                        protocol.version.as_deref().unwrap_or("unknown"),
+                    ),
+                    Ok(protocol) => debug!("Detected the server protocol: {:?}", protocol),
+                    Err(why) => warn!("Failed to detect the server protocol: {}", why),
+                }
+            });
+        }
+
         while let Some(Ok(resp)) = read.next().await {
             if let TungsteniteMessage::Text(x) = &resp {
-                if let Ok(base_event) = serde_json::from_str::<GatewayEvent>(x) {
-                    match base_event.op.as_str() {
+                if let Ok(raw_event) = serde_json::from_str::<serde_json::Value>(x) {
+                    dispatch_client_event(&client, "raw", |handler, client| async move {
+                        handler.raw(client, &raw_event).await;
+                    });
+                }
+
+                if let Ok(base_event) = serde_json::from_str::<PayloadHeader>(x) {
+                    match &*base_event.op {
+                        "ready" => {
+                            if let Ok(ready) = serde_json::from_str::<Ready>(x) {
+                                client.inner.lock().server_session_id = Some(ready.session_id);
+
+                                // The players of the session are only reachable with its id.
+                                if ready.resumed || is_resumed {
+                                    tokio::spawn(sync_resumed_players(client.clone()));
+                                }
+                            }
+                        }
                         "stats" => {
                             if let Ok(stats) = serde_json::from_str::<Stats>(x) {
-                                handler.stats(client.clone(), stats).await;
+                                let audio_degraded = {
+                                    let mut client_lock = client.inner.lock();
+                                    client_lock.push_stats(stats.clone());
+
+                                    if client_lock.check_frame_stats(&stats) {
+                                        Some(AudioDegraded {
+                                            host: client_lock.current_host().to_string(),
+                                            frame_stats: stats.frame_stats.clone().unwrap(),
+                                            intervals: client_lock.degraded_intervals,
+                                        })
+                                    } else {
+                                        None
+                                    }
+                                };
+
+                                dispatch_client_event(
+                                    &client,
+                                    "stats",
+                                    |handler, client| async move {
+                                        handler.stats(client, stats).await;
+                                    },
+                                );
+
+                                if let Some(event) = audio_degraded {
+                                    warn!(
+                                        "Lavalink audio is degraded on {}: {:?}",
+                                        event.host, event.frame_stats
+                                    );
+                                    dispatch_client_event(
+                                        &client,
+                                        "audio_degraded",
+                                        |handler, client| async move {
+                                            handler.audio_degraded(client, event).await;
+                                        },
+                                    );
+                                }
                             }
                         }
                         "playerUpdate" => {
-                            if let Ok(player_update) = serde_json::from_str::<PlayerUpdate>(x) {
+                            if let Ok(mut player_update) = serde_json::from_str::<PlayerUpdate>(x) {
+                                player_update.meta =
+                                    next_event_meta(&client, player_update.guild_id);
+
                                 {
-                                    let client_clone = client.clone();
-                                    let client_lock = client_clone.inner.lock();
+                                    let nodes = client.inner.lock().nodes.clone();
 
                                     if let TryResult::Present(mut node) =
-                                        client_lock.nodes.try_get_mut(&player_update.guild_id.0)
+                                        nodes.try_get_mut(&player_update.guild_id.0)
                                     {
+                                        node.player_state = Some(player_update.state);
+
                                         if let Some(mut current_track) = node.now_playing.as_mut() {
                                             let mut info =
                                                 current_track.track.info.as_mut().unwrap().clone();
@@ -353,73 +1354,697 @@ pub async fn lavalink_event_loop(
                                     };
                                 }
 
-                                handler.player_update(client.clone(), player_update).await;
+                                broadcast_guild_event(
+                                    &client,
+                                    GuildEvent::PlayerUpdate(player_update.clone()),
+                                );
+                                dispatch_guild_event(
+                                    &client,
+                                    player_update.guild_id,
+                                    "player_update",
+                                    |handler, client| async move {
+                                        handler.player_update(client, player_update).await;
+                                    },
+                                );
                             }
                         }
-                        "event" => match base_event.event_type.unwrap().as_str() {
+                        "event" => match base_event.event_type.as_deref().unwrap() {
                             "WebSocketClosedEvent" => {
-                                if let Ok(websocket_closed) =
+                                if let Ok(mut websocket_closed) =
                                     serde_json::from_str::<WebSocketClosed>(x)
                                 {
-                                    handler
-                                        .websocket_closed(client.clone(), websocket_closed)
-                                        .await;
+                                    websocket_closed.meta =
+                                        next_event_meta(&client, websocket_closed.guild_id);
+
+                                    broadcast_guild_event(
+                                        &client,
+                                        GuildEvent::WebSocketClosed(websocket_closed.clone()),
+                                    );
+
+                                    let guild_id = websocket_closed.guild_id;
+                                    let code = websocket_closed.code;
+                                    let is_resumable = websocket_closed.is_resumable();
+                                    let is_session_invalidated =
+                                        websocket_closed.is_session_invalidated();
+
+                                    dispatch_guild_event(
+                                        &client,
+                                        guild_id,
+                                        "websocket_closed",
+                                        |handler, client| async move {
+                                            handler
+                                                .websocket_closed(client, websocket_closed)
+                                                .await;
+                                        },
+                                    );
+
+                                    if is_resumable {
+                                        if let Err(why) = resend_voice_update(&client, guild_id).await
+                                        {
+                                            error!(
+                                                "Error resuming the voice connection on guild {}: {}",
+                                                guild_id, why
+                                            );
+                                        }
+                                    } else if is_session_invalidated {
+                                        dispatch_guild_event(
+                                            &client,
+                                            guild_id,
+                                            "voice_session_invalidated",
+                                            |handler, client| async move {
+                                                handler
+                                                    .voice_session_invalidated(
+                                                        client,
+                                                        VoiceSessionInvalidated { guild_id, code },
+                                                    )
+                                                    .await;
+                                            },
+                                        );
+
+                                        #[cfg(feature = "discord-gateway")]
+                                        rejoin_voice_channel(&client, guild_id);
+                                    }
                                 }
                             }
                             "PlayerDestroyedEvent" => {
-                                if let Ok(player_destroyed) =
+                                if let Ok(mut player_destroyed) =
                                     serde_json::from_str::<PlayerDestroyed>(x)
                                 {
-                                    handler
-                                        .player_destroyed(client.clone(), player_destroyed)
-                                        .await;
+                                    player_destroyed.meta =
+                                        next_event_meta(&client, player_destroyed.guild_id);
+
+                                    broadcast_guild_event(
+                                        &client,
+                                        GuildEvent::PlayerDestroyed(player_destroyed.clone()),
+                                    );
+                                    dispatch_guild_event(
+                                        &client,
+                                        player_destroyed.guild_id,
+                                        "player_destroyed",
+                                        |handler, client| async move {
+                                            handler
+                                                .player_destroyed(client, player_destroyed)
+                                                .await;
+                                        },
+                                    );
                                 }
                             }
                             "TrackStartEvent" => {
-                                if let Ok(track_start) = serde_json::from_str::<TrackStart>(x) {
-                                    handler.track_start(client.clone(), track_start).await;
+                                if let Ok(mut track_start) = serde_json::from_str::<TrackStart>(x) {
+                                    track_start.meta =
+                                        next_event_meta(&client, track_start.guild_id);
+                                    track_start.track_queue = resolve_track_queue(
+                                        &client,
+                                        track_start.guild_id,
+                                        &track_start.track,
+                                    );
+                                    reset_retried_track(&client, &track_start);
+
+                                    broadcast_guild_event(
+                                        &client,
+                                        GuildEvent::TrackStart(track_start.clone()),
+                                    );
+                                    dispatch_guild_event(
+                                        &client,
+                                        track_start.guild_id,
+                                        "track_start",
+                                        |handler, client| async move {
+                                            handler.track_start(client, track_start).await;
+                                        },
+                                    );
                                 }
                             }
                             "TrackEndEvent" => {
-                                if let Ok(track_finish) = serde_json::from_str::<TrackFinish>(x) {
-                                    if track_finish.reason == "FINISHED" {
-                                        let client_lock = client.inner.lock();
+                                if let Ok(mut track_finish) = serde_json::from_str::<TrackFinish>(x) {
+                                    track_finish.meta =
+                                        next_event_meta(&client, track_finish.guild_id);
+                                    track_finish.track_queue = resolve_track_queue(
+                                        &client,
+                                        track_finish.guild_id,
+                                        &track_finish.track,
+                                    );
+
+                                    let mut queue_looped = None;
+                                    let mut queue_finished = None;
+                                    let mut autoplay_seed = None;
+
+                                    if track_finish.reason == "FINISHED"
+                                        && !was_preloaded(&client, &track_finish)
+                                    {
+                                        let nodes = client.inner.lock().nodes.clone();
 
                                         if let TryResult::Present(mut node) =
-                                            client_lock.nodes.try_get_mut(&track_finish.guild_id.0)
+                                            nodes.try_get_mut(&track_finish.guild_id.0)
                                         {
                                             if !node.queue.is_empty() {
-                                                node.queue.remove(0);
+                                                let finished = node.queue.remove(0);
+
+                                                if node.repeat_queue {
+                                                    node.looped_tracks.push(finished);
+                                                }
                                             }
                                             node.now_playing = None;
+
+                                            if node.is_on_loops && node.queue.is_empty() {
+                                                if node.repeat_queue
+                                                    && !node.looped_tracks.is_empty()
+                                                {
+                                                    node.queue =
+                                                        std::mem::take(&mut node.looped_tracks);
+
+                                                    queue_looped = Some(QueueLooped {
+                                                        guild_id: track_finish.guild_id,
+                                                        track_count: node.queue.len(),
+                                                    });
+                                                } else if node.autoplay
+                                                    && track_finish.track_queue.is_some()
+                                                {
+                                                    autoplay_seed
+                                                        .clone_from(&track_finish.track_queue);
+                                                } else {
+                                                    queue_finished = Some(QueueFinished {
+                                                        guild_id: track_finish.guild_id,
+                                                    });
+                                                }
+                                            }
                                         };
                                     }
 
-                                    handler.track_finish(client.clone(), track_finish).await;
+                                    let guild_id = track_finish.guild_id;
+
+                                    broadcast_guild_event(
+                                        &client,
+                                        GuildEvent::TrackFinish(track_finish.clone()),
+                                    );
+                                    dispatch_guild_event(
+                                        &client,
+                                        guild_id,
+                                        "track_finish",
+                                        |handler, client| async move {
+                                            handler.track_finish(client, track_finish).await;
+                                        },
+                                    );
+
+                                    if let Some(seed) = autoplay_seed {
+                                        let client = client.clone();
+
+                                        // Loading the recommendations can take a while.
+                                        tokio::spawn(async move {
+                                            match autoplay_track(&client, guild_id, &seed).await {
+                                                Ok(Some(track)) => dispatch_guild_event(
+                                                    &client,
+                                                    guild_id,
+                                                    "autoplay_track_added",
+                                                    |handler, client| async move {
+                                                        handler
+                                                            .autoplay_track_added(
+                                                                client,
+                                                                AutoplayTrackAdded {
+                                                                    guild_id,
+                                                                    track,
+                                                                },
+                                                            )
+                                                            .await;
+                                                    },
+                                                ),
+                                                Ok(None) => dispatch_guild_event(
+                                                    &client,
+                                                    guild_id,
+                                                    "queue_finished",
+                                                    |handler, client| async move {
+                                                        handler
+                                                            .queue_finished(
+                                                                client,
+                                                                QueueFinished { guild_id },
+                                                            )
+                                                            .await;
+                                                    },
+                                                ),
+                                                Err(why) => {
+                                                    error!(
+                                                        "Error queueing an autoplay track on guild {}: {}",
+                                                        guild_id, why
+                                                    );
+                                                    dispatch_guild_event(
+                                                        &client,
+                                                        guild_id,
+                                                        "queue_finished",
+                                                        |handler, client| async move {
+                                                            handler
+                                                                .queue_finished(
+                                                                    client,
+                                                                    QueueFinished { guild_id },
+                                                                )
+                                                                .await;
+                                                        },
+                                                    );
+                                                }
+                                            }
+                                        });
+                                    }
+
+                                    if let Some(event) = queue_looped {
+                                        dispatch_guild_event(
+                                            &client,
+                                            guild_id,
+                                            "queue_looped",
+                                            |handler, client| async move {
+                                                handler.queue_looped(client, event).await;
+                                            },
+                                        );
+                                    }
+
+                                    if let Some(event) = queue_finished {
+                                        dispatch_guild_event(
+                                            &client,
+                                            guild_id,
+                                            "queue_finished",
+                                            |handler, client| async move {
+                                                handler.queue_finished(client, event).await;
+                                            },
+                                        );
+                                    }
                                 }
                             }
                             "TrackExceptionEvent" => {
-                                if let Ok(track_exception) =
+                                if let Ok(mut track_exception) =
                                     serde_json::from_str::<TrackException>(x)
                                 {
-                                    handler
-                                        .track_exception(client.clone(), track_exception)
-                                        .await;
+                                    track_exception.meta =
+                                        next_event_meta(&client, track_exception.guild_id);
+                                    track_exception.track_queue = resolve_track_queue(
+                                        &client,
+                                        track_exception.guild_id,
+                                        &track_exception.track,
+                                    );
+
+                                    if let Some(track_queue) =
+                                        track_to_retry(&client, &track_exception)
+                                    {
+                                        let client_clone = client.clone();
+
+                                        tokio::spawn(async move {
+                                            retry_track(client_clone, track_exception, track_queue)
+                                                .await;
+                                        });
+                                    } else {
+                                        broadcast_guild_event(
+                                            &client,
+                                            GuildEvent::TrackException(track_exception.clone()),
+                                        );
+                                        dispatch_guild_event(
+                                            &client,
+                                            track_exception.guild_id,
+                                            "track_exception",
+                                            |handler, client| async move {
+                                                handler
+                                                    .track_exception(client, track_exception)
+                                                    .await;
+                                            },
+                                        );
+                                    }
                                 }
                             }
                             "TrackStuckEvent" => {
-                                if let Ok(track_stuck) = serde_json::from_str::<TrackStuck>(x) {
-                                    handler.track_stuck(client.clone(), track_stuck).await;
+                                if let Ok(mut track_stuck) = serde_json::from_str::<TrackStuck>(x) {
+                                    track_stuck.meta =
+                                        next_event_meta(&client, track_stuck.guild_id);
+
+                                    broadcast_guild_event(
+                                        &client,
+                                        GuildEvent::TrackStuck(track_stuck.clone()),
+                                    );
+                                    let policy = client.inner.lock().track_stuck_policy;
+                                    let recovered = TrackStuckRecovered {
+                                        guild_id: track_stuck.guild_id,
+                                        track: track_stuck.track.clone(),
+                                        policy,
+                                    };
+
+                                    dispatch_guild_event(
+                                        &client,
+                                        track_stuck.guild_id,
+                                        "track_stuck",
+                                        |handler, client| async move {
+                                            handler.track_stuck(client, track_stuck).await;
+                                        },
+                                    );
+
+                                    let client = client.clone();
+
+                                    // Skipping waits on the guild lock, which would hold up the
+                                    // events of every other guild.
+                                    tokio::spawn(async move {
+                                        match recover_stuck_track(&client, &recovered).await {
+                                            Ok(true) => dispatch_guild_event(
+                                                &client,
+                                                recovered.guild_id,
+                                                "track_stuck_recovered",
+                                                |handler, client| async move {
+                                                    handler
+                                                        .track_stuck_recovered(client, recovered)
+                                                        .await;
+                                                },
+                                            ),
+                                            Ok(false) => (),
+                                            Err(why) => error!(
+                                                "Error recovering stuck track on guild {}: {}",
+                                                recovered.guild_id, why
+                                            ),
+                                        }
+                                    });
                                 }
                             }
-                            _ => warn!("Unknown event: {}", &x),
+                            _ => {
+                                warn!("Unknown event: {}", &x);
+                                unknown_event(&client, x);
+                            }
                         },
-                        _ => warn!("Unknown socket response: {}", &x),
+                        _ => {
+                            warn!("Unknown socket response: {}", &x);
+                            unknown_event(&client, x);
+                        }
                     }
                 }
+
+                check_drained(&client);
             }
         }
 
+        // The session id of this connection is stale once it's closed, a new one comes with the
+        // next `ready` op.
+        {
+            let mut client_lock = client.inner.lock();
+            client_lock.server_session_id = None;
+            client_lock.disconnected_at = Some(SystemTime::now());
+        }
+
+        if client.is_shutdown() {
+            info!("Lavalink event loop stopped.");
+            break;
+        }
+
         error!("Event loop ended unexpectedly.");
     }
 }
+
+/// Starts the next track of the queue of every guild on `LavalinkClient.loops`.
+///
+/// A single task handles every guild, waking up every queue tick, or as soon as a track is queued.
+pub async fn queue_scheduler(weak_client: WeakLavalinkClient) {
+    loop {
+        let client = match weak_client.upgrade() {
+            Some(client) if !client.is_shutdown() => client,
+            _ => break,
+        };
+
+        let (loops, queue_notify, queue_tick, queue_tick_jitter) = {
+            let client_lock = client.inner.lock();
+            (
+                client_lock.loops.clone(),
+                client_lock.queue_notify.clone(),
+                client_lock.queue_tick,
+                client_lock.queue_tick_jitter,
+            )
+        };
+
+        let guild_ids = loops.iter().map(|guild_id| *guild_id).collect::<Vec<_>>();
+
+        futures::stream::iter(guild_ids)
+            .for_each_concurrent(None, |guild_id| {
+                let client = &client;
+                let loops = &loops;
+
+                async move {
+                    if !advance_queue(client, guild_id).await {
+                        loops.remove(&guild_id);
+                    }
+                }
+            })
+            .await;
+
+        drop(client);
+
+        let jitter = if queue_tick_jitter == Duration::from_secs(0) {
+            queue_tick_jitter
+        } else {
+            rand::thread_rng().gen_range(Duration::from_secs(0)..=queue_tick_jitter)
+        };
+
+        // Timing out just means it's time for the next tick.
+        drop(tokio::time::timeout(queue_tick + jitter, queue_notify.notified()).await);
+    }
+}
+
+/// Compares the nodes with the players of the server every `interval`, reporting the drift
+/// found twice in a row so changes still on their way to the server aren't reported.
+pub async fn drift_checker(weak_client: WeakLavalinkClient, interval: Duration) {
+    let mut previous_drift = HashMap::<u64, Vec<StateDrift>>::new();
+
+    loop {
+        tokio::time::sleep(interval).await;
+
+        let client = match weak_client.upgrade() {
+            Some(client) if !client.is_shutdown() => client,
+            _ => break,
+        };
+
+        let remote_players = match client.get_remote_players().await {
+            Ok(remote_players) => remote_players,
+            // Lavalink before 3.7 has no session to list the players of, and 3.7 and later only
+            // once the websocket is ready.
+            Err(LavalinkError::NoServerSession) => continue,
+            Err(why) => {
+                warn!("Failed to get the players of the server: {}", why);
+                continue;
+            }
+        };
+
+        let (nodes, correct_drift) = {
+            let client_lock = client.inner.lock();
+            (client_lock.nodes.clone(), client_lock.correct_drift)
+        };
+
+        let guild_ids = nodes.iter().map(|node| *node.key()).collect::<Vec<_>>();
+        let mut current_drift = HashMap::new();
+
+        for guild_id in guild_ids {
+            let guild_lock = client.guild_lock(guild_id);
+            let _guard = guild_lock.lock().await;
+
+            let remote = remote_players
+                .get(&GuildId(guild_id))
+                .map(RemotePlayerState::from_value);
+
+            let drift = nodes
+                .get(&guild_id)
+                .map(|node| detect_drift(&node, remote.as_ref()))
+                .unwrap_or_default();
+
+            if drift.is_empty() {
+                continue;
+            }
+
+            if previous_drift.get(&guild_id) != Some(&drift) {
+                current_drift.insert(guild_id, drift);
+                continue;
+            }
+
+            if correct_drift {
+                if let Some(mut node) = nodes.get_mut(&guild_id) {
+                    correct_node(&mut node, &drift);
+                }
+            }
+
+            warn!(
+                "The node of guild {} drifted from the server: {:?}",
+                guild_id, drift
+            );
+
+            let event = StateDriftDetected {
+                guild_id: GuildId(guild_id),
+                drift,
+                corrected: correct_drift,
+            };
+
+            dispatch_guild_event(
+                &client,
+                GuildId(guild_id),
+                "state_drift_detected",
+                |handler, client| async move {
+                    handler.state_drift_detected(client, event).await;
+                },
+            );
+        }
+
+        previous_drift = current_drift;
+    }
+}
+
+/// The differences between the node and the player of the server, if it has one.
+fn detect_drift(node: &Node, remote: Option<&RemotePlayerState>) -> Vec<StateDrift> {
+    let remote = remote.copied().unwrap_or_default();
+    let mut drift = Vec::new();
+
+    match (remote.playing, node.now_playing.is_some()) {
+        (true, false) => drift.push(StateDrift::ServerPlaying),
+        (false, true) => drift.push(StateDrift::ServerIdle),
+        _ => (),
+    }
+
+    if remote.playing && remote.paused != node.is_paused {
+        drift.push(StateDrift::Paused(remote.paused));
+    }
+
+    if let Some(volume) = remote.volume {
+        if volume != node.volume {
+            drift.push(StateDrift::Volume(volume));
+        }
+    }
+
+    drift
+}
+
+/// Updates the node to match the server. A track only playing on the server is left alone.
+fn correct_node(node: &mut Node, drift: &[StateDrift]) {
+    for drift in drift {
+        match *drift {
+            StateDrift::ServerPlaying => (),
+            // The track ended on the server, the queue scheduler starts the next one once nothing
+            // is playing.
+            StateDrift::ServerIdle => {
+                node.now_playing = None;
+
+                if !node.queue.is_empty() {
+                    let finished = node.queue.remove(0);
+
+                    if node.repeat_queue {
+                        node.looped_tracks.push(finished);
+                    }
+                }
+            }
+            StateDrift::Paused(paused) => node.is_paused = paused,
+            StateDrift::Volume(volume) => node.volume = volume,
+        }
+    }
+}
+
+/// Starts the next track of the queue of the guild if nothing is playing, or right before the
+/// current one ends with gapless playback.
+///
+/// Returns false if the guild has no node anymore.
+async fn advance_queue(client: &LavalinkClient, guild_id: u64) -> bool {
+    let guild_lock = client.guild_lock(guild_id);
+
+    // A guild busy with another operation is checked again on the next tick, instead of holding
+    // up the tick of every other guild.
+    let _guard = match guild_lock.try_lock() {
+        Ok(guard) => guard,
+        Err(_) => return true,
+    };
+
+    let gapless_preload = client.inner.lock().gapless_preload;
+    let remaining = gapless_preload.and_then(|_| client.remaining(guild_id));
+
+    let nodes = client.inner.lock().nodes.clone();
+
+    let track = match nodes.try_get_mut(&guild_id) {
+        TryResult::Present(mut node) => {
+            // A paused player, or one told to stop at the end of the track, keeps the rest of the
+            // queue waiting.
+            let advance = node.auto_advance && !node.is_paused;
+
+            let preload = match (gapless_preload, remaining) {
+                (Some(preload), Some(remaining)) => {
+                    advance
+                        && node.now_playing.is_some()
+                        && node.queue.len() > 1
+                        && remaining <= preload
+                }
+                _ => false,
+            };
+
+            if preload {
+                // The next track replaces the current one right before it ends, to avoid the gap
+                // between them.
+                let finished = node.queue.remove(0);
+
+                if node.repeat_queue {
+                    node.looped_tracks.push(finished);
+                }
+
+                node.now_playing = None;
+            }
+
+            if advance && !node.queue.is_empty() && node.now_playing.is_none() {
+                let track = node.queue[0].clone();
+                set_now_playing(&mut node, &track);
+
+                Some(track)
+            } else {
+                None
+            }
+        }
+        // The node is only locked for short synchronous sections, so it's checked again on the
+        // next tick.
+        TryResult::Locked => None,
+        TryResult::Absent => return false,
+    };
+
+    if let Some(track) = track {
+        let socket_sender = {
+            let client_lock = client.inner.lock();
+            let x = client_lock.socket_sender.read().clone();
+            x
+        };
+
+        let result = match socket_sender {
+            Some(socket) => play_track_queue(socket, guild_id, track).await,
+            None => Err(LavalinkError::MissingLavalinkSocket),
+        };
+
+        if let Err(why) = result {
+            error!("Error playing queue on guild {}: {}", guild_id, why);
+        }
+    }
+
+    true
+}
+
+/// Sets the track as the one playing on the node, with its volume and filters.
+pub(crate) fn set_now_playing(node: &mut Node, track: &TrackQueue) {
+    node.now_playing = Some(track.clone());
+
+    if let Some(volume) = track.volume {
+        node.volume = volume;
+    }
+
+    if track.filters.is_some() {
+        node.filters.clone_from(&track.filters);
+    }
+}
+
+/// Sends the filters and the track of the queue entry to lavalink, replacing the one playing.
+pub(crate) async fn play_track_queue(
+    socket: SocketSender,
+    guild_id: u64,
+    track: TrackQueue,
+) -> LavalinkResult<()> {
+    if let Some(filters) = track.filters {
+        SendOpcode::Filters(Box::new(filters))
+            .send(guild_id, socket.clone())
+            .await?;
+    }
+
+    let payload = Play {
+        track: track.track.track,
+        no_replace: false,
+        start_time: track.start_time,
+        end_time: track.end_time,
+        volume: track.volume,
+    };
+
+    SendOpcode::Play(payload).send(guild_id, socket).await?;
+
+    Ok(())
+}
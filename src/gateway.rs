@@ -3,10 +3,33 @@ use crate::LavalinkClient;
 
 use async_trait::async_trait;
 
+use std::sync::Arc;
+
+/// Handles the events of the client.
+///
+/// The events of a guild are handled in order, each one after the previous one finished, while
+/// the events of different guilds are handled concurrently.
 #[async_trait]
 pub trait LavalinkEventHandler {
+    /// Event that triggers for every message received from the lavalink websocket, before it's
+    /// dispatched to the typed events.
+    ///
+    /// Useful to log, forward or handle payloads that are not modeled by this library yet.
+    async fn raw(&self, _client: LavalinkClient, _value: &serde_json::Value) {}
+    /// Event that triggers when the websocket connection to lavalink is established, or
+    /// established again after a disconnection. Sessions can be created from this point.
+    async fn connected(&self, _client: LavalinkClient, _event: Connected) {}
     /// Periodic event that returns the statistics of the server.
     async fn stats(&self, _client: LavalinkClient, _event: Stats) {}
+    /// Event that triggers when the frame stats of the server stay over the configured
+    /// thresholds, so the audio is choppy for the listeners.
+    async fn audio_degraded(&self, _client: LavalinkClient, _event: AudioDegraded) {}
+    /// Event that triggers when the drift check finds a node out of sync with its player on
+    /// the server.
+    async fn state_drift_detected(&self, _client: LavalinkClient, _event: StateDriftDetected) {}
+    /// Event that triggers for every guild still playing on the server when the session is
+    /// resumed, after its node was updated to match the server.
+    async fn player_resumed(&self, _client: LavalinkClient, _event: PlayerResumed) {}
     /// Event that triggers when a player updates.
     async fn player_update(&self, _client: LavalinkClient, _event: PlayerUpdate) {}
     /// Event that triggers when a track starts playing.
@@ -17,10 +40,240 @@ pub trait LavalinkEventHandler {
     async fn track_exception(&self, _client: LavalinkClient, _event: TrackException) {}
     /// Event that triggers when a track gets stuck while playing.
     async fn track_stuck(&self, _client: LavalinkClient, _event: TrackStuck) {}
+    /// Event that triggers after the configured `TrackStuckPolicy` was applied to a stuck track.
+    async fn track_stuck_recovered(&self, _client: LavalinkClient, _event: TrackStuckRecovered) {}
     /// Event that triggers when the websocket connection to the voice channel closes.
     async fn websocket_closed(&self, _client: LavalinkClient, _event: WebSocketClosed) {}
+    /// Event that triggers when discord invalidates the voice session of a guild, and the voice
+    /// channel needs to be joined again.
+    async fn voice_session_invalidated(
+        &self,
+        _client: LavalinkClient,
+        _event: VoiceSessionInvalidated,
+    ) {
+    }
+    /// Event that triggers when the bot is moved to another voice channel, as tracked from the
+    /// discord gateway.
+    async fn self_moved(&self, _client: LavalinkClient, _event: SelfMoved) {}
+    /// Event that triggers when the bot leaves, or is disconnected from, a voice channel, as
+    /// tracked from the discord gateway.
+    async fn self_disconnected(&self, _client: LavalinkClient, _event: SelfDisconnected) {}
+    /// Event that triggers when the bot is server muted or unmuted, as tracked from the discord
+    /// gateway. Useful to pause the player while nobody can hear it.
+    async fn self_server_muted(&self, _client: LavalinkClient, _event: SelfServerMuted) {}
+    /// Event that triggers when the bot becomes a speaker of the stage channel it was suppressed
+    /// in, after being unsuppressed or having its request to speak accepted.
+    async fn stage_speaker_granted(&self, _client: LavalinkClient, _event: StageSpeakerGranted) {}
     /// Event that triggers when the player gets destroyed on a guild.
     async fn player_destroyed(&self, _client: LavalinkClient, _event: PlayerDestroyed) {}
+    /// Event that triggers when a track is added to the queue of a guild.
+    async fn queue_track_added(&self, _client: LavalinkClient, _event: QueueTrackAdded) {}
+    /// Event that triggers when the queue of a guild is drained and nothing is playing.
+    async fn queue_finished(&self, _client: LavalinkClient, _event: QueueFinished) {}
+    /// Event that triggers when the queue of a guild starts again from the first track, if
+    /// `repeat_queue` is enabled on the node.
+    async fn queue_looped(&self, _client: LavalinkClient, _event: QueueLooped) {}
+    /// Event that triggers when the queue of a guild finishes with `autoplay` enabled on the node,
+    /// and a recommended track is queued.
+    async fn autoplay_track_added(&self, _client: LavalinkClient, _event: AutoplayTrackAdded) {}
+    /// Event that triggers when `check_guild_shard()` finds a guild on a different shard than
+    /// expected, usually because the configured shard count is wrong.
+    async fn shard_mismatch(&self, _client: LavalinkClient, _event: ShardMismatch) {}
+    /// Event that triggers once the client is draining and no players are active anymore, so the
+    /// lavalink server can be restarted without interrupting anyone.
+    async fn drained(&self, _client: LavalinkClient) {}
+    /// Event that triggers for the messages of the lavalink websocket with an op or event type
+    /// that's not modeled, like the events of lavalink plugins.
+    async fn unknown_event(&self, _client: LavalinkClient, _event: UnknownEvent) {}
+    /// Event that triggers when the handler of an event panics. The panic is also logged, and the
+    /// next events are still handled.
+    async fn event_error(&self, _client: LavalinkClient, _event: EventError) {}
+}
+
+/// Runs several event handlers in order, so separate concerns like metrics, logging and the bot
+/// logic can live in their own handlers.
+///
+/// Every handler receives every event, and waits for the previous one to finish handling it.
+#[derive(Clone, Default)]
+pub struct HandlerChain {
+    pub handlers: Vec<Arc<dyn LavalinkEventHandler + Send + Sync>>,
+}
+
+impl HandlerChain {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a handler to the end of the chain.
+    #[must_use]
+    pub fn with(mut self, handler: impl LavalinkEventHandler + Send + Sync + 'static) -> Self {
+        self.handlers.push(Arc::new(handler));
+        self
+    }
+}
+
+#[async_trait]
+impl LavalinkEventHandler for HandlerChain {
+    async fn raw(&self, client: LavalinkClient, value: &serde_json::Value) {
+        for handler in &self.handlers {
+            handler.raw(client.clone(), value).await;
+        }
+    }
+    async fn connected(&self, client: LavalinkClient, event: Connected) {
+        for handler in &self.handlers {
+            handler.connected(client.clone(), event.clone()).await;
+        }
+    }
+    async fn stats(&self, client: LavalinkClient, event: Stats) {
+        for handler in &self.handlers {
+            handler.stats(client.clone(), event.clone()).await;
+        }
+    }
+    async fn audio_degraded(&self, client: LavalinkClient, event: AudioDegraded) {
+        for handler in &self.handlers {
+            handler.audio_degraded(client.clone(), event.clone()).await;
+        }
+    }
+    async fn state_drift_detected(&self, client: LavalinkClient, event: StateDriftDetected) {
+        for handler in &self.handlers {
+            handler
+                .state_drift_detected(client.clone(), event.clone())
+                .await;
+        }
+    }
+    async fn player_resumed(&self, client: LavalinkClient, event: PlayerResumed) {
+        for handler in &self.handlers {
+            handler.player_resumed(client.clone(), event.clone()).await;
+        }
+    }
+    async fn player_update(&self, client: LavalinkClient, event: PlayerUpdate) {
+        for handler in &self.handlers {
+            handler.player_update(client.clone(), event.clone()).await;
+        }
+    }
+    async fn track_start(&self, client: LavalinkClient, event: TrackStart) {
+        for handler in &self.handlers {
+            handler.track_start(client.clone(), event.clone()).await;
+        }
+    }
+    async fn track_finish(&self, client: LavalinkClient, event: TrackFinish) {
+        for handler in &self.handlers {
+            handler.track_finish(client.clone(), event.clone()).await;
+        }
+    }
+    async fn track_exception(&self, client: LavalinkClient, event: TrackException) {
+        for handler in &self.handlers {
+            handler.track_exception(client.clone(), event.clone()).await;
+        }
+    }
+    async fn track_stuck(&self, client: LavalinkClient, event: TrackStuck) {
+        for handler in &self.handlers {
+            handler.track_stuck(client.clone(), event.clone()).await;
+        }
+    }
+    async fn track_stuck_recovered(&self, client: LavalinkClient, event: TrackStuckRecovered) {
+        for handler in &self.handlers {
+            handler
+                .track_stuck_recovered(client.clone(), event.clone())
+                .await;
+        }
+    }
+    async fn websocket_closed(&self, client: LavalinkClient, event: WebSocketClosed) {
+        for handler in &self.handlers {
+            handler
+                .websocket_closed(client.clone(), event.clone())
+                .await;
+        }
+    }
+    async fn voice_session_invalidated(
+        &self,
+        client: LavalinkClient,
+        event: VoiceSessionInvalidated,
+    ) {
+        for handler in &self.handlers {
+            handler
+                .voice_session_invalidated(client.clone(), event.clone())
+                .await;
+        }
+    }
+    async fn self_moved(&self, client: LavalinkClient, event: SelfMoved) {
+        for handler in &self.handlers {
+            handler.self_moved(client.clone(), event.clone()).await;
+        }
+    }
+    async fn self_disconnected(&self, client: LavalinkClient, event: SelfDisconnected) {
+        for handler in &self.handlers {
+            handler
+                .self_disconnected(client.clone(), event.clone())
+                .await;
+        }
+    }
+    async fn self_server_muted(&self, client: LavalinkClient, event: SelfServerMuted) {
+        for handler in &self.handlers {
+            handler
+                .self_server_muted(client.clone(), event.clone())
+                .await;
+        }
+    }
+    async fn stage_speaker_granted(&self, client: LavalinkClient, event: StageSpeakerGranted) {
+        for handler in &self.handlers {
+            handler
+                .stage_speaker_granted(client.clone(), event.clone())
+                .await;
+        }
+    }
+    async fn player_destroyed(&self, client: LavalinkClient, event: PlayerDestroyed) {
+        for handler in &self.handlers {
+            handler
+                .player_destroyed(client.clone(), event.clone())
+                .await;
+        }
+    }
+    async fn queue_track_added(&self, client: LavalinkClient, event: QueueTrackAdded) {
+        for handler in &self.handlers {
+            handler
+                .queue_track_added(client.clone(), event.clone())
+                .await;
+        }
+    }
+    async fn queue_finished(&self, client: LavalinkClient, event: QueueFinished) {
+        for handler in &self.handlers {
+            handler.queue_finished(client.clone(), event.clone()).await;
+        }
+    }
+    async fn queue_looped(&self, client: LavalinkClient, event: QueueLooped) {
+        for handler in &self.handlers {
+            handler.queue_looped(client.clone(), event.clone()).await;
+        }
+    }
+    async fn autoplay_track_added(&self, client: LavalinkClient, event: AutoplayTrackAdded) {
+        for handler in &self.handlers {
+            handler
+                .autoplay_track_added(client.clone(), event.clone())
+                .await;
+        }
+    }
+    async fn shard_mismatch(&self, client: LavalinkClient, event: ShardMismatch) {
+        for handler in &self.handlers {
+            handler.shard_mismatch(client.clone(), event.clone()).await;
+        }
+    }
+    async fn drained(&self, client: LavalinkClient) {
+        for handler in &self.handlers {
+            handler.drained(client.clone()).await;
+        }
+    }
+    async fn unknown_event(&self, client: LavalinkClient, event: UnknownEvent) {
+        for handler in &self.handlers {
+            handler.unknown_event(client.clone(), event.clone()).await;
+        }
+    }
+    async fn event_error(&self, client: LavalinkClient, event: EventError) {
+        for handler in &self.handlers {
+            handler.event_error(client.clone(), event.clone()).await;
+        }
+    }
 }
 
 #[cfg(feature = "discord-gateway")]
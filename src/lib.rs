@@ -18,6 +18,13 @@ extern crate log;
 
 /// Builder structures
 pub mod builders;
+/// Compatibility with the upstream lavalink-rs API
+pub mod compat;
+/// Custom transports for the lavalink connection
+pub mod connector;
+#[cfg(feature = "decode-tracks-locally")]
+/// Local decoding of the lavaplayer track format
+pub mod decoder;
 /// Library's errors
 pub mod error;
 mod event_loops;
@@ -25,6 +32,9 @@ mod event_loops;
 pub mod gateway;
 /// Library models
 pub mod model;
+#[cfg(feature = "poise")]
+/// Poise framework helpers
+pub mod poise;
 #[cfg(feature = "discord-gateway")]
 /// Voice connection handling
 pub mod voice;
@@ -35,26 +45,37 @@ pub use async_trait::async_trait;
 pub use typemap_rev;
 
 use builders::*;
+use connector::SharedConnector;
 use error::LavalinkError;
 use error::LavalinkResult;
 
 #[cfg(feature = "discord-gateway")]
 use event_loops::discord_event_loop;
-use event_loops::lavalink_event_loop;
+use event_loops::{
+    drift_checker, lavalink_event_loop, play_track_queue, queue_scheduler, set_now_playing,
+};
 
 use gateway::LavalinkEventHandler;
+#[cfg(feature = "discord-gateway")]
+use model::outgoing::GatewayPayload;
+use model::outgoing::SessionUpdate;
 use model::*;
 
 use std::{
-    cmp::{max, min},
-    sync::Arc,
-    time::Duration,
+    cmp::{min, Ordering},
+    collections::{HashMap, VecDeque},
+    net::Ipv6Addr,
+    sync::{Arc, Weak},
+    time::{Duration, Instant, SystemTime},
 };
 
 #[cfg(feature = "songbird")]
 use songbird_dep::ConnectionInfo as SongbirdConnectionInfo;
 
-use reqwest::{header::HeaderMap, Client as ReqwestClient, Url};
+use reqwest::{
+    header::{HeaderMap, HeaderName},
+    Client as ReqwestClient, Url,
+};
 
 #[cfg(feature = "native")]
 use tokio_native_tls::TlsStream;
@@ -64,14 +85,14 @@ use tokio_rustls::client::TlsStream;
 use parking_lot::{Mutex, RwLock};
 use tokio::net::TcpStream;
 
-use regex::Regex;
-
-use async_tungstenite::{
-    stream::Stream, tokio::TokioAdapter, tungstenite::Message as TungsteniteMessage,
-    WebSocketStream,
-};
+use async_tungstenite::{stream::Stream, tokio::TokioAdapter, WebSocketStream};
 
 use tokio::sync::mpsc;
+use tokio::sync::{broadcast, Notify, Semaphore};
+use tokio::task::JoinHandle;
+
+use futures::future::BoxFuture;
+use futures::stream;
 
 use dashmap::{DashMap, DashSet};
 use dashmap::try_result::TryResult;
@@ -94,35 +115,460 @@ pub const EQ_PIANO: [f64; 15] = [
     -0.25, -0.25, -0.125, 0.0, 0.25, 0.25, 0.0, -0.25, -0.25, 0.0, 0.0, 0.5, 0.25, -0.025, 0.0,
 ];
 
+/// Equalizer preset for jazz.
+pub const EQ_JAZZ: [f64; 15] = [
+    -0.13, -0.11, -0.1, -0.1, 0.14, 0.2, -0.18, 0.0, 0.24, 0.22, 0.2, 0.0, 0.0, 0.0, 0.0,
+];
+/// Equalizer preset for pop.
+pub const EQ_POP: [f64; 15] = [
+    -0.02, -0.01, 0.08, 0.1, 0.15, 0.1, 0.03, -0.02, -0.035, -0.05, -0.05, -0.05, -0.05, -0.05,
+    -0.05,
+];
+/// Equalizer preset for electronic music.
+pub const EQ_ELECTRONIC: [f64; 15] = [
+    0.375, 0.35, 0.125, 0.0, 0.0, -0.125, -0.125, 0.0, 0.25, 0.125, 0.15, 0.2, 0.25, 0.35, 0.4,
+];
+/// Equalizer preset for rock.
+pub const EQ_ROCK: [f64; 15] = [
+    0.3, 0.25, 0.2, 0.1, 0.05, -0.05, -0.15, -0.2, -0.1, -0.05, 0.05, 0.1, 0.2, 0.25, 0.3,
+];
+/// Equalizer preset that boosts the lows.
+pub const EQ_BASS: [f64; 15] = [
+    0.25, 0.2, 0.15, 0.1, 0.05, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0,
+];
+/// Equalizer preset that boosts the highs.
+pub const EQ_TREBLE: [f64; 15] = [
+    0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.05, 0.1, 0.15, 0.2, 0.25, 0.25, 0.25,
+];
+
+/// How many `stats` events are kept for `stats_history()` by default, about an hour with
+/// lavalink's default interval of a minute.
+const STATS_HISTORY_CAPACITY: usize = 60;
+
+/// How many guild events can be buffered for the `guild_events()` streams before they start lagging.
+const GUILD_EVENTS_CAPACITY: usize = 256;
+
+/// How many discord gateway events can be buffered for the `gateway_events()` streams before they
+/// start lagging.
+#[cfg(feature = "discord-gateway")]
+const GATEWAY_EVENTS_CAPACITY: usize = 256;
+
+/// The most choices discord accepts for an autocomplete interaction.
+const AUTOCOMPLETE_MAX_CHOICES: usize = 25;
+
+/// The most characters discord accepts for the name and value of an autocomplete choice.
+const AUTOCOMPLETE_MAX_LENGTH: usize = 100;
+
+/// How long `autocomplete_tracks()` waits for the user to stop typing before searching, by default.
+const AUTOCOMPLETE_DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// How long the `autocomplete_tracks()` choices are cached, by default.
+const AUTOCOMPLETE_CACHE_TTL: Duration = Duration::from_secs(60);
+
+/// How long lavalink keeps the players of a disconnected session by default, when resuming.
+const RESUME_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How long `close()` waits for each background task to end before aborting it.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
 pub type WsStream =
     WebSocketStream<Stream<TokioAdapter<TcpStream>, TokioAdapter<TlsStream<TcpStream>>>>;
 
+/// The cached `autocomplete_tracks()` choices of each query, with the time they were searched at.
+pub type AutocompleteCache = DashMap<String, (Instant, Vec<(String, String)>)>;
+
 /// NOTE: All fields are public for those who want to do their own implementation of things, you
 /// should not be touching them if you don't know what you are doing.
+#[allow(clippy::struct_excessive_bools)]
 pub struct LavalinkClientInner {
     //pub socket_uri: String,
     pub rest_uri: String,
     pub headers: HeaderMap,
+    /// The client used for the REST requests.
+    pub http_client: ReqwestClient,
+    /// The custom TLS configuration, used when connecting to the websocket.
+    pub tls_config: Option<TlsConfig>,
+    /// The custom transport for the websocket, used instead of TCP.
+    pub connector: Option<SharedConnector>,
+    /// The server flavor and version, detected when first connecting.
+    pub protocol: Option<ServerProtocol>,
+    /// The session id sent by lavalink 3.7 and later on the `ready` op.
+    pub server_session_id: Option<String>,
+    /// When the websocket last disconnected, the players stop where they were at that time.
+    pub disconnected_at: Option<SystemTime>,
+    /// The `loadtracks` requests running, that other requests for the same identifier wait on.
+    pub inflight_loads: Arc<DashMap<String, broadcast::Sender<Option<Tracks>>>>,
+    /// The cached `decodetrack` results.
+    pub decode_cache: Arc<Mutex<DecodeCache>>,
+    /// The cached `loadtracks` results, with the time they were loaded at.
+    pub load_cache: Arc<DashMap<String, (Instant, Tracks)>>,
+    pub load_cache_ttl: Option<Duration>,
+    /// The maximum number of cached `loadtracks` results.
+    pub load_cache_size: usize,
+    /// Decides which queries `auto_search_tracks()` loads directly.
+    pub url_matcher: UrlMatcher,
+    /// The latest `autocomplete_tracks()` request of each user, to debounce them.
+    pub autocomplete_requests: Arc<DashMap<UserId, u64>>,
+    /// The cached `autocomplete_tracks()` choices, with the time they were searched at.
+    pub autocomplete_cache: Arc<AutocompleteCache>,
+    /// How long `autocomplete_tracks()` waits for the user to stop typing before searching.
+    pub autocomplete_debounce: Duration,
+    /// How long the `autocomplete_tracks()` choices are cached.
+    pub autocomplete_cache_ttl: Duration,
+    /// Limits the concurrent `loadtracks` requests, if `max_concurrent_loads` is set.
+    pub load_semaphore: Option<Arc<Semaphore>>,
+    pub load_queue_timeout: Duration,
+    /// The latest `stats` events received, oldest first.
+    pub stats_history: VecDeque<Stats>,
+    /// How many stats are kept on `stats_history`.
+    pub stats_history_size: usize,
+    pub audio_degraded_thresholds: Option<AudioDegradedThresholds>,
+    /// How many consecutive `stats` events had degraded frame stats.
+    pub degraded_intervals: usize,
+    /// If the drift check updates the nodes to match the server.
+    pub correct_drift: bool,
+    /// The latest player and track events, oldest first.
+    pub event_buffer: VecDeque<GuildEvent>,
+    /// How many events are kept on `event_buffer`.
+    pub event_buffer_size: usize,
 
     /// The sender websocket split.
-    pub socket_sender: RwLock<Option<mpsc::UnboundedSender<(TungsteniteMessage, mpsc::UnboundedSender<()>)>>>,
+    pub socket_sender: RwLock<Option<SocketSender>>,
     //pub socket_write: Arc<Mutex<Option<SplitSink<WsStream, TungsteniteMessage>>>>,
     // cannot be cloned, and cannot be behind a lock
     // because it would always be open by the event loop.
     //pub socket_read: SplitStream<WsStream>,
     pub socket_uri: String,
+    /// The hosts of the node, the first one being the primary and the rest the fallbacks.
+    pub hosts: Vec<String>,
+    /// The index of the host currently in use.
+    pub host_index: usize,
+    pub port: u16,
+    pub is_ssl: bool,
 
     //_shard_id: Option<ShardId>,
     pub nodes: Arc<DashMap<u64, Node>>,
     pub loops: Arc<DashSet<u64>>,
-
+    /// Wakes up the queue scheduler before its next tick, when a track is queued.
+    pub queue_notify: Arc<Notify>,
+    /// The locks used to keep the order of the operations on each guild.
+    pub guild_locks: Arc<DashMap<u64, Arc<tokio::sync::Mutex<()>>>>,
+
+    /// The sender used to broadcast the events to the `guild_events()` streams.
+    pub guild_events: broadcast::Sender<GuildEvent>,
+
+    /// The event handler the client was built with.
+    pub handler: Arc<dyn LavalinkEventHandler + Send + Sync>,
+    /// The sequence number of the last lavalink event received for each guild.
+    pub event_sequences: Arc<DashMap<GuildId, u64>>,
+    /// The queues delivering the handler events of each guild in order, and the one of the events
+    /// not tied to a guild under None.
+    pub guild_dispatchers:
+        Arc<DashMap<Option<GuildId>, mpsc::UnboundedSender<BoxFuture<'static, ()>>>>,
+    /// The event loops and background tasks of the client, awaited by `close()`.
+    pub tasks: Vec<JoinHandle<()>>,
+
+    /// If tracks that fail with a `COMMON` exception should be retried once.
+    pub retry_on_common_exception: bool,
+    /// What to do when a track gets stuck while playing.
+    pub track_stuck_policy: TrackStuckPolicy,
+    /// How long before the end of a track the next one of the queue starts.
+    pub gapless_preload: Option<Duration>,
+    /// How often the queue scheduler checks if the next track should start.
+    pub queue_tick: Duration,
+    /// The maximum random delay added to each queue tick.
+    pub queue_tick_jitter: Duration,
+    /// The shard count sent to lavalink with the `Num-Shards` header.
+    pub shard_count: u64,
+    /// The key and timeout used to resume the lavalink session.
+    pub resume_key: Option<String>,
+    pub resume_timeout: Duration,
+    pub auto_restore_players: bool,
+    /// If `shutdown()` was called, stopping the event loops.
+    pub is_shutdown: bool,
+    /// If new players are rejected, see `start_drain()`.
+    pub draining: bool,
+    /// If the `drained` event was already triggered for the current drain.
+    pub drain_notified: bool,
+
+    /// The discord gateway data, created when it's first used.
     #[cfg(feature = "discord-gateway")]
-    pub discord_gateway_data: Arc<Mutex<DiscordGatewayData>>,
+    pub discord_gateway_data: Option<Arc<Mutex<DiscordGatewayData>>>,
+    /// The discord gateway settings of the builder, used to create the gateway data.
+    #[cfg(feature = "discord-gateway")]
+    pub gateway_config: DiscordGatewayConfig,
     // Unused
     //_region: Option<Region>,
     //_identifier: Option<String>,
 }
 
+impl LavalinkClientInner {
+    /// Stores the stats, dropping the oldest ones if the history is full.
+    pub fn push_stats(&mut self, stats: Stats) {
+        while self.stats_history.len() >= self.stats_history_size.max(1) {
+            self.stats_history.pop_front();
+        }
+
+        self.stats_history.push_back(stats);
+    }
+
+    /// Counts the consecutive `stats` events with degraded frame stats, returning true once the
+    /// count reaches the configured intervals.
+    pub fn check_frame_stats(&mut self, stats: &Stats) -> bool {
+        let thresholds = self.audio_degraded_thresholds;
+        let is_degraded = thresholds.map_or(false, |thresholds| {
+            stats
+                .frame_stats
+                .as_ref()
+                .map_or(false, |frame_stats| thresholds.is_degraded(frame_stats))
+        });
+
+        if is_degraded {
+            self.degraded_intervals += 1;
+        } else {
+            self.degraded_intervals = 0;
+        }
+
+        // Only once per streak of degraded intervals.
+        thresholds.map_or(false, |thresholds| {
+            self.degraded_intervals == thresholds.intervals.max(1)
+        })
+    }
+
+    /// Stores the event if the buffer is enabled, dropping the oldest one if it's full, and sends
+    /// it to the event streams.
+    ///
+    /// Both are done together, so a stream created with `events_with_replay()` doesn't miss or
+    /// repeat any event.
+    pub fn push_event(&mut self, event: GuildEvent) {
+        if self.event_buffer_size > 0 {
+            if self.event_buffer.len() == self.event_buffer_size {
+                self.event_buffer.pop_front();
+            }
+
+            self.event_buffer.push_back(event.clone());
+        }
+
+        drop(self.guild_events.send(event));
+    }
+
+    /// The host currently in use.
+    #[must_use]
+    pub fn current_host(&self) -> &str {
+        &self.hosts[self.host_index]
+    }
+
+    /// Switches to the next host, updating the URIs.
+    ///
+    /// Returns true if it went back to the primary host, after trying every fallback.
+    pub fn use_next_host(&mut self) -> bool {
+        self.host_index = (self.host_index + 1) % self.hosts.len();
+
+        let (socket_uri, rest_uri) =
+            node_uris(&self.hosts[self.host_index], self.port, self.is_ssl);
+        self.socket_uri = socket_uri;
+        self.rest_uri = rest_uri;
+
+        self.host_index == 0
+    }
+}
+
+/// Builds the headers sent on both the websocket handshake and the REST requests.
+fn node_headers(builder: &LavalinkClientBuilder) -> LavalinkResult<HeaderMap> {
+    let mut headers = HeaderMap::new();
+    headers.insert("Authorization", builder.password.parse()?);
+    headers.insert("Num-Shards", builder.shard_count.to_string().parse()?);
+    headers.insert("User-Id", builder.bot_id.to_string().parse()?);
+    if let Some(resume_key) = &builder.resume_key {
+        headers.insert("Resume-Key", resume_key.parse()?);
+    }
+    headers.insert(
+        "Client-Name",
+        builder
+            .client_name
+            .as_deref()
+            .unwrap_or(concat!(
+                env!("CARGO_PKG_NAME"),
+                "/",
+                env!("CARGO_PKG_VERSION")
+            ))
+            .parse()?,
+    );
+
+    for (name, value) in &builder.extra_headers {
+        let name = name.parse::<HeaderName>().map_err(|_| {
+            LavalinkError::InvalidConfig(
+                "extra_headers",
+                format!("`{}` is not a valid header name", name),
+            )
+        })?;
+
+        headers.insert(name, value.parse()?);
+    }
+
+    Ok(headers)
+}
+
+/// Formats the host to be used in an URI, wrapping IPv6 literals in brackets.
+pub(crate) fn uri_host(host: &str) -> String {
+    if host.parse::<Ipv6Addr>().is_ok() {
+        format!("[{}]", host)
+    } else {
+        host.to_string()
+    }
+}
+
+/// Caches the `loadtracks` result, removing the expired ones and then the oldest ones if the
+/// cache is full.
+fn cache_load(
+    load_cache: &DashMap<String, (Instant, Tracks)>,
+    identifier: String,
+    tracks: Tracks,
+    ttl: Duration,
+    size: usize,
+) {
+    if size == 0 {
+        return;
+    }
+
+    load_cache.retain(|_, entry| entry.0.elapsed() < ttl);
+
+    while load_cache.len() >= size && !load_cache.contains_key(&identifier) {
+        let oldest = load_cache
+            .iter()
+            .min_by_key(|entry| entry.0)
+            .map(|entry| entry.key().clone());
+
+        match oldest {
+            Some(oldest) => drop(load_cache.remove(&oldest)),
+            None => break,
+        }
+    }
+
+    load_cache.insert(identifier, (Instant::now(), tracks));
+}
+
+/// Creates the node of the guild with the options if it doesn't exist, and sets its voice update.
+///
+/// Returns the volume to send to lavalink, if the node was created with one.
+#[cfg(any(feature = "songbird", feature = "discord-gateway"))]
+fn upsert_node(
+    nodes: &DashMap<u64, Node>,
+    guild_id: GuildId,
+    voice_update: VoiceUpdate,
+    options: NodeOptions,
+) -> Option<u16> {
+    let mut volume = None;
+
+    let mut node = nodes.entry(guild_id.0).or_insert_with(|| {
+        volume = options.volume.map(|volume| volume.min(1000));
+        options.into_node(guild_id)
+    });
+    node.voice_update = Some(voice_update);
+
+    volume
+}
+
+/// Truncates the name of an autocomplete choice to discord's limit.
+fn autocomplete_name(title: &str) -> String {
+    if title.chars().count() <= AUTOCOMPLETE_MAX_LENGTH {
+        return title.to_string();
+    }
+
+    let mut name = title
+        .chars()
+        .take(AUTOCOMPLETE_MAX_LENGTH - 1)
+        .collect::<String>();
+    name.push('…');
+    name
+}
+
+/// A `loadtracks` request that other requests for the same identifier are waiting on.
+///
+/// It's removed from the in-flight requests when dropped, so the waiting requests don't hang if
+/// this one is cancelled.
+struct InflightLoad {
+    loads: Arc<DashMap<String, broadcast::Sender<Option<Tracks>>>>,
+    identifier: String,
+    finished: bool,
+}
+
+impl InflightLoad {
+    /// Shares the result with the waiting requests, None if the request failed.
+    fn finish(mut self, tracks: Option<Tracks>) {
+        self.finished = true;
+
+        if let Some((_, sender)) = self.loads.remove(&self.identifier) {
+            // There might be no one waiting.
+            drop(sender.send(tracks));
+        }
+    }
+}
+
+impl Drop for InflightLoad {
+    fn drop(&mut self) {
+        // A new request for the identifier might be running already if this one finished.
+        if !self.finished {
+            self.loads.remove(&self.identifier);
+        }
+    }
+}
+
+/// Builds the websocket and REST URIs of the node.
+fn node_uris(host: &str, port: u16, is_ssl: bool) -> (String, String) {
+    let host = uri_host(host);
+
+    if is_ssl {
+        (
+            format!("wss://{}:{}", host, port),
+            format!("https://{}:{}", host, port),
+        )
+    } else {
+        (
+            format!("ws://{}:{}", host, port),
+            format!("http://{}:{}", host, port),
+        )
+    }
+}
+
+/// The builder settings of the discord gateway, kept until the gateway data is created.
+#[cfg(feature = "discord-gateway")]
+#[derive(Clone)]
+pub struct DiscordGatewayConfig {
+    pub bot_id: UserId,
+    pub bot_token: String,
+    pub wait_time: Duration,
+    pub presence: Option<Presence>,
+    pub intents: u64,
+    pub identify_properties: IdentifyProperties,
+    pub rejoin_on_session_invalidated: bool,
+    pub join_timeout: Duration,
+    pub join_retries: usize,
+    pub permission_check: Option<voice::PermissionCheck>,
+    pub auto_stage_speaker: bool,
+    pub voice_gateway: Option<voice::SharedVoiceGateway>,
+}
+
+#[cfg(feature = "discord-gateway")]
+impl From<&LavalinkClientBuilder> for DiscordGatewayConfig {
+    fn from(builder: &LavalinkClientBuilder) -> Self {
+        Self {
+            bot_id: builder.bot_id,
+            bot_token: builder.bot_token.clone(),
+            wait_time: builder.gateway_start_wait_time,
+            presence: builder.presence.clone(),
+            intents: builder.gateway_intents,
+            identify_properties: builder.identify_properties.clone(),
+            rejoin_on_session_invalidated: builder.rejoin_on_session_invalidated,
+            join_timeout: builder.join_timeout,
+            join_retries: builder.join_retries,
+            permission_check: builder.permission_check.clone(),
+            auto_stage_speaker: builder.auto_stage_speaker,
+            voice_gateway: builder.voice_gateway.clone(),
+        }
+    }
+}
+
 #[cfg(feature = "discord-gateway")]
 pub struct DiscordGatewayData {
     pub shard_count: u64,
@@ -132,7 +578,69 @@ pub struct DiscordGatewayData {
     pub headers: HeaderMap,
     pub sender: mpsc::UnboundedSender<String>,
     pub connections: Arc<DashMap<GuildId, ConnectionInfo>>,
+    /// The voice states of the members in the voice channels of each guild.
+    pub voice_states: Arc<DashMap<GuildId, HashMap<UserId, MemberVoiceState>>>,
     pub socket_uri: &'static str,
+    pub presence: Option<Presence>,
+    pub intents: u64,
+    pub identify_properties: IdentifyProperties,
+    pub rejoin_on_session_invalidated: bool,
+    pub join_timeout: Duration,
+    pub join_retries: usize,
+    pub permission_check: Option<voice::PermissionCheck>,
+    pub auto_stage_speaker: bool,
+    /// The guilds where the bot is suppressed in a stage channel.
+    pub stage_suppressed: Arc<DashSet<GuildId>>,
+    /// The sender used to forward the non-voice events to the `gateway_events()` streams.
+    pub events: broadcast::Sender<DiscordGatewayEvent>,
+    /// The transport of the voice state updates, the internal gateway if None.
+    pub voice_gateway: Option<voice::SharedVoiceGateway>,
+}
+
+#[cfg(feature = "discord-gateway")]
+impl DiscordGatewayData {
+    fn new(config: &DiscordGatewayConfig, shard_count: u64) -> Self {
+        let mut headers = HeaderMap::new();
+
+        // The builder rejects these tokens, and starting the gateway fails with them.
+        match format!("Bot {}", config.bot_token).parse() {
+            Ok(authorization) => {
+                headers.insert("Authorization", authorization);
+            }
+            Err(why) => error!(
+                "The bot token is not a valid header, the discord requests are sent without it: {}",
+                why
+            ),
+        }
+        headers.insert("bot", reqwest::header::HeaderValue::from_static("True"));
+        headers.insert(
+            "Content-type",
+            reqwest::header::HeaderValue::from_static("application/json"),
+        );
+
+        Self {
+            shard_count,
+            bot_id: config.bot_id,
+            bot_token: config.bot_token.clone(),
+            wait_time: config.wait_time,
+            headers,
+            sender: mpsc::unbounded_channel().0,
+            connections: Arc::new(DashMap::new()),
+            voice_states: Arc::new(DashMap::new()),
+            socket_uri: "wss://gateway.discord.gg/?v=9&encoding=json",
+            presence: config.presence.clone(),
+            intents: config.intents,
+            identify_properties: config.identify_properties.clone(),
+            rejoin_on_session_invalidated: config.rejoin_on_session_invalidated,
+            join_timeout: config.join_timeout,
+            join_retries: config.join_retries,
+            permission_check: config.permission_check.clone(),
+            auto_stage_speaker: config.auto_stage_speaker,
+            stage_suppressed: Arc::new(DashSet::new()),
+            events: broadcast::channel(GATEWAY_EVENTS_CAPACITY).0,
+            voice_gateway: config.voice_gateway.clone(),
+        }
+    }
 }
 
 /// A Client for Lavalink.
@@ -146,76 +654,106 @@ pub struct LavalinkClient {
     pub inner: Arc<Mutex<LavalinkClientInner>>,
 }
 
+/// A weak handle to the Client, that doesn't keep it alive.
+///
+/// Useful for background jobs and caches that should stop once the client is dropped or shut
+/// down.
+#[derive(Clone)]
+pub struct WeakLavalinkClient {
+    pub inner: Weak<Mutex<LavalinkClientInner>>,
+}
+
+impl WeakLavalinkClient {
+    /// Gets the client back, if it's still alive.
+    #[must_use]
+    pub fn upgrade(&self) -> Option<LavalinkClient> {
+        self.inner.upgrade().map(|inner| LavalinkClient { inner })
+    }
+}
+
 impl LavalinkClient {
     /// Builds the Client connection.
     pub async fn new(
         builder: &LavalinkClientBuilder,
         handler: impl LavalinkEventHandler + Send + Sync + 'static,
     ) -> LavalinkResult<Self> {
-        let (lavalink_headers, lavalink_rest_uri, lavalink_socket_uri) = {
-            let socket_uri;
-            let rest_uri;
-
-            if builder.is_ssl {
-                socket_uri = format!("wss://{}:{}", &builder.host, builder.port);
-                rest_uri = format!("https://{}:{}", &builder.host, builder.port);
-            } else {
-                socket_uri = format!("ws://{}:{}", &builder.host, builder.port);
-                rest_uri = format!("http://{}:{}", &builder.host, builder.port);
-            }
-
-            let mut headers = HeaderMap::new();
-            headers.insert("Authorization", builder.password.parse()?);
-            headers.insert("Num-Shards", builder.shard_count.to_string().parse()?);
-            headers.insert("User-Id", builder.bot_id.to_string().parse()?);
-            headers.insert(
-                "Client-Name",
-                concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"))
-                    .to_owned()
-                    .parse()?,
-            );
-
-            (headers, rest_uri, socket_uri)
-        };
-
-        #[cfg(feature = "discord-gateway")]
-        let (discord_socket_uri, discord_headers) = {
-            let socket_uri = "wss://gateway.discord.gg/?v=9&encoding=json";
+        let lavalink_headers = node_headers(builder)?;
+        let (lavalink_socket_uri, lavalink_rest_uri) =
+            node_uris(&builder.host, builder.port, builder.is_ssl);
 
-            let mut headers = HeaderMap::new();
-            headers.insert(
-                "Authorization",
-                format!("Bot {}", builder.bot_token).parse()?,
-            );
-            headers.insert("bot", "True".to_string().parse()?);
-            headers.insert("Content-type", "application/json".to_string().parse()?);
-
-            (socket_uri, headers)
+        let http_client = match &builder.tls_config {
+            Some(tls_config) => tls_config.apply_to(ReqwestClient::builder()).build()?,
+            None => ReqwestClient::new(),
         };
 
-        #[cfg(feature = "discord-gateway")]
-        let discord_gateway_data = {
-            Arc::new(Mutex::new(DiscordGatewayData {
-                shard_count: builder.shard_count,
-                bot_id: builder.bot_id,
-                bot_token: builder.bot_token.to_string(),
-                wait_time: builder.gateway_start_wait_time,
-                headers: discord_headers,
-                sender: mpsc::unbounded_channel().0,
-                connections: Arc::new(DashMap::new()),
-                socket_uri: discord_socket_uri,
-            }))
-        };
+        let handler: Arc<dyn LavalinkEventHandler + Send + Sync> = Arc::new(handler);
 
         let client_inner = LavalinkClientInner {
             headers: lavalink_headers,
             socket_sender: RwLock::new(None),
             rest_uri: lavalink_rest_uri,
+            http_client,
+            tls_config: builder.tls_config.clone(),
+            connector: builder.connector.clone(),
+            protocol: None,
+            server_session_id: None,
+            disconnected_at: None,
+            stats_history: VecDeque::with_capacity(builder.stats_history_size),
+            stats_history_size: builder.stats_history_size,
+            audio_degraded_thresholds: builder.audio_degraded_thresholds,
+            degraded_intervals: 0,
+            correct_drift: builder.correct_drift,
+            event_buffer: VecDeque::with_capacity(builder.event_buffer_size),
+            event_buffer_size: builder.event_buffer_size,
+            load_semaphore: builder
+                .max_concurrent_loads
+                .map(|permits| Arc::new(Semaphore::new(permits))),
+            load_queue_timeout: builder.load_queue_timeout,
+            inflight_loads: Arc::new(DashMap::new()),
+            load_cache: Arc::new(DashMap::new()),
+            decode_cache: Arc::new(Mutex::new(DecodeCache::new(
+                builder.decode_cache_size,
+                builder.decode_cache_ttl,
+            ))),
+            load_cache_ttl: builder.load_cache_ttl,
+            load_cache_size: builder.load_cache_size,
+            url_matcher: builder.url_matcher.clone(),
+            autocomplete_requests: Arc::new(DashMap::new()),
+            autocomplete_cache: Arc::new(DashMap::new()),
+            autocomplete_debounce: builder.autocomplete_debounce,
+            autocomplete_cache_ttl: builder.autocomplete_cache_ttl,
             nodes: Arc::new(DashMap::new()),
             loops: Arc::new(DashSet::new()),
+            queue_notify: Arc::new(Notify::new()),
+            guild_locks: Arc::new(DashMap::new()),
+            guild_events: broadcast::channel(GUILD_EVENTS_CAPACITY).0,
+            handler: handler.clone(),
+            event_sequences: Arc::new(DashMap::new()),
+            guild_dispatchers: Arc::new(DashMap::new()),
+            tasks: Vec::new(),
+            retry_on_common_exception: builder.retry_on_common_exception,
+            track_stuck_policy: builder.track_stuck_policy,
+            gapless_preload: builder.gapless_preload,
+            queue_tick: builder.queue_tick,
+            queue_tick_jitter: builder.queue_tick_jitter,
+            shard_count: builder.shard_count,
+            resume_key: builder.resume_key.clone(),
+            resume_timeout: builder.resume_timeout,
+            auto_restore_players: builder.auto_restore_players,
+            is_shutdown: false,
+            draining: false,
+            drain_notified: false,
             socket_uri: lavalink_socket_uri,
+            hosts: std::iter::once(builder.host.clone())
+                .chain(builder.fallback_hosts.iter().cloned())
+                .collect(),
+            host_index: 0,
+            port: builder.port,
+            is_ssl: builder.is_ssl,
+            #[cfg(feature = "discord-gateway")]
+            discord_gateway_data: None,
             #[cfg(feature = "discord-gateway")]
-            discord_gateway_data,
+            gateway_config: builder.into(),
         };
 
         let client = Self {
@@ -223,23 +761,23 @@ impl LavalinkClient {
         };
 
         let client_clone = client.clone();
-        let host = builder.host.to_string();
 
-        tokio::spawn(async move {
-            lavalink_event_loop(handler, client_clone, host).await;
-        });
+        let mut tasks = vec![
+            tokio::spawn(async move {
+                lavalink_event_loop(client_clone).await;
+            }),
+            tokio::spawn(queue_scheduler(client.downgrade())),
+        ];
+
+        if let Some(interval) = builder.drift_check_interval {
+            tasks.push(tokio::spawn(drift_checker(client.downgrade(), interval)));
+        }
+
+        client.inner.lock().tasks = tasks;
 
         #[cfg(feature = "discord-gateway")]
         if builder.start_gateway {
-            let client_clone = client.clone();
-            let token = builder.bot_token.clone();
-            let wait_time = builder.gateway_start_wait_time;
-
-            tokio::spawn(async move {
-                debug!("Starting discord event loop.");
-                discord_event_loop(client_clone, &token, wait_time).await;
-                error!("Event loop ended unexpectedly.");
-            });
+            client.start_discord_gateway(None).await?;
         }
 
         Ok(client)
@@ -282,52 +820,168 @@ impl LavalinkClient {
     /// configured that way.
     ///
     /// If `wait_time` is passed, it will override the previosuly configured wait time.
+    ///
+    /// Returns `LavalinkError::InvalidConfig` if the client was built without a bot token.
     #[cfg(feature = "discord-gateway")]
-    pub async fn start_discord_gateway(&self, wait_time: Option<Duration>) {
+    pub async fn start_discord_gateway(&self, wait_time: Option<Duration>) -> LavalinkResult<()> {
+        if self.inner.lock().gateway_config.bot_token.trim().is_empty() {
+            return Err(LavalinkError::InvalidConfig(
+                "bot_token",
+                "a bot token is required to start the discord gateway".to_string(),
+            ));
+        }
+
         let client_clone = self.clone();
-        let token = self.discord_gateway_data().lock().bot_token.clone();
         let wait_time = if let Some(t) = wait_time {
             t
         } else {
             self.discord_gateway_data().lock().wait_time
         };
 
-        tokio::spawn(async move {
+        let task = tokio::spawn(async move {
             debug!("Starting discord event loop.");
-            discord_event_loop(client_clone, &token, wait_time).await;
+            discord_event_loop(client_clone, wait_time).await;
             error!("Event loop ended unexpectedly.");
         });
+
+        self.inner.lock().tasks.push(task);
+
+        Ok(())
     }
 
     /// Returns the tracks from the URL or query provided.
+    ///
+    /// Concurrent requests for the same identifier share a single request to lavalink, and the
+    /// results are cached for `load_cache_ttl` if it's set, up to `load_cache_size` of them.
+    ///
+    /// If `max_concurrent_loads` is set, this waits for the previous requests to finish, and
+    /// returns `LavalinkError::Timeout` if it waits longer than `load_queue_timeout`.
     pub async fn get_tracks(&self, query: impl ToString) -> LavalinkResult<Tracks> {
-        let (rest_uri, headers) = {
+        let identifier = query.to_string();
+
+        let (inflight_loads, load_cache, load_cache_ttl, load_cache_size) = {
+            let client = self.inner.lock();
+            (
+                client.inflight_loads.clone(),
+                client.load_cache.clone(),
+                client.load_cache_ttl,
+                client.load_cache_size,
+            )
+        };
+
+        if let Some(ttl) = load_cache_ttl {
+            let cached = load_cache
+                .get(&identifier)
+                .filter(|entry| entry.0.elapsed() < ttl)
+                .map(|entry| entry.1.clone());
+
+            if let Some(tracks) = cached {
+                return Ok(tracks);
+            }
+        }
+
+        let receiver = match inflight_loads.entry(identifier.clone()) {
+            dashmap::mapref::entry::Entry::Occupied(entry) => Some(entry.get().subscribe()),
+            dashmap::mapref::entry::Entry::Vacant(entry) => {
+                entry.insert(broadcast::channel(1).0);
+                None
+            }
+        };
+
+        // Another request for the identifier is running, its result is shared. If it fails, the
+        // request is done again here, as the errors can't be shared.
+        if let Some(mut receiver) = receiver {
+            if let Ok(Some(tracks)) = receiver.recv().await {
+                return Ok(tracks);
+            }
+
+            return self.request_tracks(&identifier, None).await;
+        }
+
+        let inflight = InflightLoad {
+            loads: inflight_loads,
+            identifier,
+            finished: false,
+        };
+
+        let result = self.request_tracks(&inflight.identifier, None).await;
+
+        if let Ok(tracks) = &result {
+            if let Some(ttl) = load_cache_ttl {
+                if tracks.load_type != "LOAD_FAILED" {
+                    cache_load(
+                        &load_cache,
+                        inflight.identifier.clone(),
+                        tracks.clone(),
+                        ttl,
+                        load_cache_size,
+                    );
+                }
+            }
+        }
+
+        inflight.finish(result.as_ref().ok().cloned());
+
+        result
+    }
+
+    /// Requests the tracks to lavalink, waiting for a free slot if `max_concurrent_loads` is set.
+    ///
+    /// Only the first `limit` tracks are deserialized, if set.
+    async fn request_tracks(
+        &self,
+        identifier: &str,
+        limit: Option<usize>,
+    ) -> LavalinkResult<Tracks> {
+        let (rest_uri, headers, reqwest, load_semaphore, load_queue_timeout) = {
             let client = self.inner.lock();
-            (client.rest_uri.to_string(), client.headers.clone())
+            (
+                client.rest_uri.clone(),
+                client.headers.clone(),
+                client.http_client.clone(),
+                client.load_semaphore.clone(),
+                client.load_queue_timeout,
+            )
+        };
+
+        // Held until the response is parsed.
+        let _permit = match load_semaphore {
+            Some(semaphore) => Some(
+                tokio::time::timeout(load_queue_timeout, semaphore.acquire_owned())
+                    .await
+                    .map_err(|_| LavalinkError::Timeout)?
+                    .expect("The load semaphore is never closed."),
+            ),
+            None => None,
         };
 
-        let reqwest = ReqwestClient::new();
         let url = Url::parse_with_params(
             &format!("{}/loadtracks", rest_uri),
-            &[("identifier", &query.to_string())],
+            &[("identifier", identifier)],
         )
         .expect("The query cannot be formatted to a url.");
 
         let raw_resp = reqwest.get(url).headers(headers).send().await?;
 
-        let resp = raw_resp.json::<Tracks>().await?;
+        let resp = match limit {
+            Some(limit) => Tracks::from_json_with_limit(&raw_resp.bytes().await?, limit)?,
+            None => raw_resp.json::<Tracks>().await?,
+        };
 
         Ok(resp)
     }
 
     /// Will automatically search the query on youtube if it's not a valid URL.
+    ///
+    /// What is a URL can be changed with `set_url_matcher()` on the builder.
     pub async fn auto_search_tracks(&self, query: impl ToString) -> LavalinkResult<Tracks> {
-        let r = Regex::new(r"https?://(?:www\.)?.+").unwrap();
-        if r.is_match(&query.to_string()) {
-            self.get_tracks(query.to_string()).await
+        let query = query.to_string();
+        let url_matcher = self.inner.lock().url_matcher.clone();
+
+        if url_matcher.is_match(&query) {
+            self.get_tracks(query).await
         } else {
-            self.get_tracks(format!("ytsearch:{}", query.to_string()))
-                .await
+            self.get_tracks(format!("ytsearch:{}", query)).await
         }
     }
 
@@ -338,17 +992,137 @@ impl LavalinkClient {
             .await
     }
 
+    /// Returns at most `limit` tracks from the search query, like for autocompletion.
+    /// Uses youtube to search.
+    ///
+    /// Lavalink always sends every result, but the extra tracks are skipped without being
+    /// deserialized. Unlike `search_tracks()`, the request isn't shared with the concurrent ones
+    /// for the same query, nor cached.
+    pub async fn search_tracks_with_limit(
+        &self,
+        query: impl ToString,
+        limit: usize,
+    ) -> LavalinkResult<Tracks> {
+        self.request_tracks(&format!("ytsearch:{}", query.to_string()), Some(limit))
+            .await
+    }
+
+    /// Searches the partial query of an autocomplete interaction on youtube, returning the
+    /// `(title, identifier)` choices within discord's limits.
+    ///
+    /// The search waits `autocomplete_debounce` for the user to stop typing, and returns no
+    /// choices if a newer request from the same user arrives meanwhile. The choices are cached
+    /// for `autocomplete_cache_ttl`.
+    pub async fn autocomplete_tracks(
+        &self,
+        user_id: impl Into<UserId>,
+        partial: impl ToString,
+    ) -> LavalinkResult<Vec<(String, String)>> {
+        let partial = partial.to_string().trim().to_string();
+
+        if partial.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let (requests, cache, debounce, cache_ttl) = {
+            let client = self.inner.lock();
+            (
+                client.autocomplete_requests.clone(),
+                client.autocomplete_cache.clone(),
+                client.autocomplete_debounce,
+                client.autocomplete_cache_ttl,
+            )
+        };
+
+        let cached = cache
+            .get(&partial)
+            .filter(|entry| entry.0.elapsed() < cache_ttl)
+            .map(|entry| entry.1.clone());
+
+        if let Some(choices) = cached {
+            return Ok(choices);
+        }
+
+        let user_id = user_id.into();
+        let generation = {
+            let mut current = requests.entry(user_id).or_insert(0);
+            *current = current.wrapping_add(1);
+            *current
+        };
+
+        tokio::time::sleep(debounce).await;
+
+        let superseded = requests
+            .get(&user_id)
+            .map_or(true, |current| *current != generation);
+
+        if superseded {
+            return Ok(Vec::new());
+        }
+
+        let result = self
+            .search_tracks_with_limit(&partial, AUTOCOMPLETE_MAX_CHOICES)
+            .await;
+
+        requests.remove_if(&user_id, |_, current| *current == generation);
+
+        let choices = result?
+            .tracks
+            .into_iter()
+            .filter_map(|track| track.info)
+            .filter(|info| info.identifier.chars().count() <= AUTOCOMPLETE_MAX_LENGTH)
+            .map(|info| (autocomplete_name(&info.title), info.identifier))
+            .collect::<Vec<_>>();
+
+        cache.retain(|_, entry| entry.0.elapsed() < cache_ttl);
+        cache.insert(partial, (Instant::now(), choices.clone()));
+
+        Ok(choices)
+    }
+
+    /// Returns tracks from the search query, using the search engine provided.
+    pub async fn search_with_engine(
+        &self,
+        engine: SearchEngines,
+        query: impl ToString,
+    ) -> LavalinkResult<TrackLoadData> {
+        let tracks = self.get_tracks(engine.to_query(&query.to_string())).await?;
+
+        Ok(tracks.data())
+    }
+
     /// Decodes a track to it's information
+    ///
+    /// The results are cached, see `set_decode_cache_size()` on the builder. With the
+    /// `decode-tracks-locally` feature, the track is decoded without asking lavalink if its
+    /// format version is known.
     pub async fn decode_track(&self, track: impl ToString) -> LavalinkResult<Info> {
-        let (rest_uri, headers) = {
+        let track = track.to_string();
+
+        #[cfg(feature = "decode-tracks-locally")]
+        if let Some(info) = crate::decoder::decode_track(&track) {
+            return Ok(info);
+        }
+
+        let (rest_uri, headers, reqwest, decode_cache) = {
             let client = self.inner.lock();
-            (client.rest_uri.to_string(), client.headers.clone())
+            (
+                client.rest_uri.clone(),
+                client.headers.clone(),
+                client.http_client.clone(),
+                client.decode_cache.clone(),
+            )
         };
 
-        let reqwest = ReqwestClient::new();
+        let cached = decode_cache.lock().get(&track);
+
+        if let Some(info) = cached {
+            return Ok(info);
+        }
+
         let url = Url::parse_with_params(
             &format!("{}/decodetrack", &rest_uri),
-            &[("track", &track.to_string())],
+            &[("track", &track)],
         )
         .expect("The query cannot be formatted to a url.");
 
@@ -360,31 +1134,369 @@ impl LavalinkClient {
             .json::<Info>()
             .await?;
 
+        decode_cache.lock().insert(track, resp.clone());
+
         Ok(resp)
     }
 
-    /// Creates a lavalink session on the specified guild.
+    /// Gets the size and hit rate of the decoded tracks cache.
+    #[must_use]
+    pub fn decode_cache_stats(&self) -> DecodeCacheStats {
+        let decode_cache = self.inner.lock().decode_cache.clone();
+        let stats = DecodeCacheStats::from(&*decode_cache.lock());
+        stats
+    }
+
+    /// Detects the flavor and version of the server, and caches it for `protocol()`.
     ///
-    /// This also creates a Node and inserts it. The node is not added on loops unless
-    /// `Play::queue()` is ran.
-    #[cfg(feature = "songbird")]
-    pub async fn create_session_with_songbird(
-        &self,
-        connection_info: &SongbirdConnectionInfo,
-    ) -> LavalinkResult<()> {
-        let event = crate::model::Event {
-            token: connection_info.token.to_string(),
-            endpoint: connection_info.endpoint.to_string(),
-            guild_id: connection_info.guild_id.0.to_string(),
+    /// Lavalink is also probed on `/v4/info` and `/v3/info` for the version of its REST API,
+    /// used by the session endpoints. This is done automatically the first time the client
+    /// connects.
+    pub async fn detect_protocol(&self) -> LavalinkResult<ServerProtocol> {
+        let (rest_uri, headers, reqwest) = {
+            let client = self.inner.lock();
+            (
+                client.rest_uri.clone(),
+                client.headers.clone(),
+                client.http_client.clone(),
+            )
         };
 
-        let payload = crate::model::VoiceUpdate {
-            session_id: connection_info.session_id.to_string(),
-            event,
+        let resp = reqwest
+            .get(format!("{}/version", rest_uri))
+            .headers(headers)
+            .send()
+            .await?;
+
+        // Andesite reports its version on every response, lavalink before 3.4 has no `/version`.
+        let protocol = if let Some(version) = resp.headers().get("Andesite-Version") {
+            ServerProtocol {
+                flavor: ServerFlavor::Andesite,
+                version: version.to_str().ok().map(ToString::to_string),
+                api_version: None,
+            }
+        } else {
+            let version = if resp.status().is_success() {
+                Some(resp.text().await?.trim().to_string())
+            } else {
+                None
+            };
+
+            ServerProtocol {
+                flavor: ServerFlavor::Lavalink,
+                version,
+                api_version: self.detect_api_version().await?,
+            }
+        };
+
+        self.inner.lock().protocol = Some(protocol.clone());
+
+        Ok(protocol)
+    }
+
+    /// Finds the newest versioned REST API of lavalink that answers its info endpoint.
+    async fn detect_api_version(&self) -> LavalinkResult<Option<u64>> {
+        for version in &[4, 3] {
+            let path = format!("/v{}/info", version);
+            let resp = self
+                .rest_request(reqwest::Method::GET, &path)
+                .send()
+                .await?;
+
+            if resp.status().is_success() {
+                return Ok(Some(*version));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Sends a GET request to `path` on the lavalink REST API, with the authorization of the
+    /// client, for plugin endpoints or endpoints not wrapped yet.
+    ///
+    /// The path is relative to the node, like `/v3/info`. Error statuses are returned as
+    /// `LavalinkError::ReqwestError`, and an empty response as `Value::Null`.
+    pub async fn rest_get(&self, path: impl AsRef<str>) -> LavalinkResult<serde_json::Value> {
+        let request = self.rest_request(reqwest::Method::GET, path.as_ref());
+
+        Self::rest_response(request.send().await?).await
+    }
+
+    /// Sends a POST request with a json `body` to `path` on the lavalink REST API, with the
+    /// authorization of the client.
+    ///
+    /// See `rest_get()` for the path and the response.
+    pub async fn rest_post(
+        &self,
+        path: impl AsRef<str>,
+        body: &serde_json::Value,
+    ) -> LavalinkResult<serde_json::Value> {
+        let request = self.rest_request(reqwest::Method::POST, path.as_ref());
+
+        Self::rest_response(request.json(body).send().await?).await
+    }
+
+    fn rest_request(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        let (rest_uri, headers, reqwest) = {
+            let client = self.inner.lock();
+            (
+                client.rest_uri.clone(),
+                client.headers.clone(),
+                client.http_client.clone(),
+            )
+        };
+
+        let url = format!("{}/{}", rest_uri, path.trim_start_matches('/'));
+
+        reqwest.request(method, &url).headers(headers)
+    }
+
+    async fn rest_response(resp: reqwest::Response) -> LavalinkResult<serde_json::Value> {
+        let resp = resp.error_for_status()?;
+
+        if resp.status() == reqwest::StatusCode::NO_CONTENT || resp.content_length() == Some(0) {
+            return Ok(serde_json::Value::Null);
+        }
+
+        Ok(resp.json::<serde_json::Value>().await?)
+    }
+
+    /// Gets the player of the guild as the server sees it, to debug differences with the
+    /// state cached by the client.
+    ///
+    /// Andesite is asked on `/player/{guild_id}`, and lavalink 3.7 and later on the player of
+    /// the session, returning `LavalinkError::NoServerSession` if the server didn't send one.
+    /// The shape of the player depends on the server.
+    pub async fn get_remote_player(
+        &self,
+        guild_id: impl Into<GuildId>,
+    ) -> LavalinkResult<serde_json::Value> {
+        let guild_id = guild_id.into();
+
+        let path = match self.session_path().await? {
+            Some(session_path) => format!("{}/players/{}", session_path, guild_id),
+            None => format!("/player/{}", guild_id),
+        };
+
+        self.rest_get(path).await
+    }
+
+    /// Gets the players the server has, by guild.
+    ///
+    /// Andesite has no endpoint listing them, so only the guilds with a node are asked, see
+    /// `get_remote_player()`.
+    pub async fn get_remote_players(&self) -> LavalinkResult<HashMap<GuildId, serde_json::Value>> {
+        if let Some(session_path) = self.session_path().await? {
+            let players = self.rest_get(format!("{}/players", session_path)).await?;
+
+            return Ok(players
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|player| {
+                    let guild_id = player["guildId"].as_str()?.parse().ok()?;
+                    Some((GuildId(guild_id), player.clone()))
+                })
+                .collect());
+        }
+
+        let nodes = self.inner.lock().nodes.clone();
+        let guild_ids = nodes.iter().map(|node| *node.key()).collect::<Vec<_>>();
+        let mut players = HashMap::new();
+
+        for guild_id in guild_ids {
+            match self.get_remote_player(guild_id).await {
+                Ok(player) => {
+                    players.insert(GuildId(guild_id), player);
+                }
+                Err(LavalinkError::ReqwestError(why))
+                    if why.status() == Some(reqwest::StatusCode::NOT_FOUND) => {}
+                Err(why) => return Err(why),
+            }
+        }
+
+        Ok(players)
+    }
+
+    /// The session id sent by lavalink 3.7 and later when the websocket is ready, used by the
+    /// REST endpoints of the session.
+    ///
+    /// None before the websocket is ready, after it disconnects, and on servers that don't send
+    /// one, like Andesite.
+    #[must_use]
+    pub fn session_id(&self) -> Option<String> {
+        self.inner.lock().server_session_id.clone()
+    }
+
+    /// Updates the session of lavalink 3.7 and later, like its resume key and timeout, returning
+    /// the session as the server sees it.
+    ///
+    /// Returns `LavalinkError::NoServerSession` if the server didn't send a session id, which is
+    /// always the case with Andesite; resuming is configured over the websocket there instead,
+    /// see `set_resume_key()` on the builder.
+    pub async fn update_session(
+        &self,
+        update: &SessionUpdate,
+    ) -> LavalinkResult<serde_json::Value> {
+        let path = self
+            .session_path()
+            .await?
+            .ok_or(LavalinkError::NoServerSession)?;
+        let request = self.rest_request(reqwest::Method::PATCH, &path);
+
+        Self::rest_response(request.json(update).send().await?).await
+    }
+
+    /// The path of the session on lavalink 3.7 and later, or None for Andesite.
+    async fn session_path(&self) -> LavalinkResult<Option<String>> {
+        let protocol = match self.protocol() {
+            Some(protocol) => protocol,
+            None => self.detect_protocol().await?,
+        };
+
+        if protocol.flavor == ServerFlavor::Andesite {
+            return Ok(None);
+        }
+
+        let session_id = self
+            .inner
+            .lock()
+            .server_session_id
+            .clone()
+            .ok_or(LavalinkError::NoServerSession)?;
+        let version = protocol
+            .api_version
+            .unwrap_or_else(|| protocol.major_version().unwrap_or(3).max(3));
+
+        Ok(Some(format!("/v{}/sessions/{}", version, session_id)))
+    }
+
+    /// Gets the latest statistics sent by the server.
+    ///
+    /// The client is connected to a single node at a time, the fallback hosts only replace it
+    /// (see `current_host()`), so the statistics aren't routed or aggregated per node.
+    #[must_use]
+    pub fn stats(&self) -> Option<Stats> {
+        self.inner.lock().stats_history.back().cloned()
+    }
+
+    /// Gets the statistics received in about the last hour, oldest first, to look at trends.
+    ///
+    /// How many are kept can be changed with `set_stats_history_size()` on the builder.
+    #[must_use]
+    pub fn stats_history(&self) -> Vec<Stats> {
+        self.inner.lock().stats_history.iter().cloned().collect()
+    }
+
+    /// Gets the statistics received during the last `window` of server uptime, oldest first,
+    /// stopping at the last restart of the server.
+    fn recent_stats(&self, window: Duration) -> Vec<Stats> {
+        let history = self.inner.lock().stats_history.clone();
+
+        let latest_uptime = history.back().map_or(0, |latest| latest.uptime);
+        let window = i64::try_from(window.as_millis()).unwrap_or(i64::MAX);
+
+        let mut previous_uptime = latest_uptime;
+        let mut recent = history
+            .into_iter()
+            .rev()
+            .take_while(|stats| {
+                let is_same_run = stats.uptime <= previous_uptime;
+                previous_uptime = stats.uptime;
+                is_same_run && latest_uptime - stats.uptime <= window
+            })
+            .collect::<Vec<_>>();
+
+        recent.reverse();
+        recent
+    }
+
+    /// Gets the average of the frames lavalink failed to send per minute during the last
+    /// `window`, like 5 minutes, to alert when the server can't keep up.
+    ///
+    /// None if no statistics with frame stats were received in the window.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn average_frame_deficit(&self, window: Duration) -> Option<f64> {
+        let deficits = self
+            .recent_stats(window)
+            .iter()
+            .filter_map(|stats| stats.frame_stats.as_ref().map(|frames| frames.deficit))
+            .collect::<Vec<_>>();
+
+        if deficits.is_empty() {
+            None
+        } else {
+            Some(deficits.iter().sum::<i64>() as f64 / deficits.len() as f64)
+        }
+    }
+
+    /// Gets how many players were created per minute during the last `window`, negative if
+    /// players were destroyed, to scale the servers ahead of the load.
+    ///
+    /// None if less than two statistics were received in the window.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn player_growth_rate(&self, window: Duration) -> Option<f64> {
+        let recent = self.recent_stats(window);
+        let (first, last) = (recent.first()?, recent.last()?);
+
+        let minutes = (last.uptime - first.uptime) as f64 / 60_000.0;
+
+        if minutes <= 0.0 {
+            return None;
+        }
+
+        Some((last.players - first.players) as f64 / minutes)
+    }
+
+    /// Gets the server flavor and version, if they were detected already.
+    #[must_use]
+    pub fn protocol(&self) -> Option<ServerProtocol> {
+        self.inner.lock().protocol.clone()
+    }
+
+    /// Creates a lavalink session on the specified guild.
+    ///
+    /// This also creates a Node and inserts it if it doesn't exist, an existing one keeps its
+    /// queue and settings. The node is not added on loops unless `Play::queue()` is ran.
+    #[cfg(feature = "songbird")]
+    pub async fn create_session_with_songbird(
+        &self,
+        connection_info: &SongbirdConnectionInfo,
+    ) -> LavalinkResult<()> {
+        self.create_session_with_songbird_and_node(connection_info, NodeOptions::default())
+            .await
+    }
+
+    /// Creates a lavalink session on the specified guild from the songbird connection, creating
+    /// its node with the options if it doesn't exist yet.
+    ///
+    /// An existing node keeps its queue and settings, and only gets the new voice connection.
+    #[cfg(feature = "songbird")]
+    pub async fn create_session_with_songbird_and_node(
+        &self,
+        connection_info: &SongbirdConnectionInfo,
+        options: NodeOptions,
+    ) -> LavalinkResult<()> {
+        let guild_id = GuildId::from(connection_info.guild_id);
+        let guild_lock = self.guild_lock(guild_id);
+        let _guard = guild_lock.lock().await;
+
+        let event = crate::model::Event {
+            token: connection_info.token.to_string(),
+            endpoint: connection_info.endpoint.to_string(),
+            guild_id: guild_id.0.to_string(),
+        };
+
+        let payload = crate::model::VoiceUpdate {
+            session_id: connection_info.session_id.to_string(),
+            event,
         };
 
-        let socket: tokio::sync::mpsc::UnboundedSender<(TungsteniteMessage, mpsc::UnboundedSender<()>)>;
+        let socket: SocketSender;
         let nodes: Arc<DashMap<u64, Node>>;
+        let draining: bool;
 
         {
             let client = self.inner.lock();
@@ -397,23 +1509,47 @@ impl LavalinkClient {
                 .clone();
 
             nodes = client.nodes.clone();
+            draining = client.draining;
         }
 
-        crate::model::SendOpcode::VoiceUpdate(payload)
-            .send(
-                connection_info.guild_id,
-                socket
-            )
+        // The nodes are never locked while holding the client mutex.
+        if draining && !nodes.contains_key(&guild_id.0) {
+            return Err(LavalinkError::Draining);
+        }
+
+        crate::model::SendOpcode::VoiceUpdate(payload.clone())
+            .send(guild_id, socket.clone())
             .await?;
 
-        if !nodes.contains_key(&connection_info.guild_id.0) {
-            nodes.insert(connection_info.guild_id.0, Node::default());
+        if let Some(volume) = upsert_node(&nodes, guild_id, payload, options) {
+            crate::model::SendOpcode::Volume(crate::model::Volume { volume })
+                .send(guild_id, socket)
+                .await?;
         }
 
         Ok(())
     }
+
+    /// Creates a lavalink session on the specified guild.
+    ///
+    /// This also creates a Node and inserts it if it doesn't exist, an existing one keeps its
+    /// queue and settings. The node is not added on loops unless `Play::queue()` is ran.
     #[cfg(feature = "discord-gateway")]
     pub async fn create_session(&self, connection_info: &ConnectionInfo) -> LavalinkResult<()> {
+        self.create_session_with_node(connection_info, NodeOptions::default())
+            .await
+    }
+
+    /// Creates a lavalink session on the specified guild, creating its node with the options if
+    /// it doesn't exist yet.
+    ///
+    /// An existing node keeps its queue and settings, and only gets the new voice connection.
+    #[cfg(feature = "discord-gateway")]
+    pub async fn create_session_with_node(
+        &self,
+        connection_info: &ConnectionInfo,
+        options: NodeOptions,
+    ) -> LavalinkResult<()> {
         let token = connection_info
             .token
             .as_ref()
@@ -449,8 +1585,12 @@ impl LavalinkClient {
 
         let payload = crate::model::VoiceUpdate { session_id, event };
 
-        let socket: tokio::sync::mpsc::UnboundedSender<(TungsteniteMessage, mpsc::UnboundedSender<()>)>;
+        let guild_lock = self.guild_lock(connection_info.guild_id.unwrap());
+        let _guard = guild_lock.lock().await;
+
+        let socket: SocketSender;
         let nodes: Arc<DashMap<u64, Node>>;
+        let draining: bool;
 
         {
             let client = self.inner.lock();
@@ -463,23 +1603,46 @@ impl LavalinkClient {
                 .clone();
 
             nodes = client.nodes.clone();
+            draining = client.draining;
         }
 
-        crate::model::SendOpcode::VoiceUpdate(payload)
-            .send(
-                connection_info.guild_id.unwrap(),
-                socket
-            )
+        // The nodes are never locked while holding the client mutex.
+        if draining && !nodes.contains_key(&connection_info.guild_id.unwrap().0) {
+            return Err(LavalinkError::Draining);
+        }
+
+        let guild_id = connection_info.guild_id.unwrap();
+
+        crate::model::SendOpcode::VoiceUpdate(payload.clone())
+            .send(guild_id, socket.clone())
             .await?;
 
-        if nodes.contains_key(&connection_info.guild_id.unwrap().0)
-        {
-            nodes.insert(connection_info.guild_id.unwrap().0, Node::default());
+        if let Some(volume) = upsert_node(&nodes, guild_id, payload, options) {
+            crate::model::SendOpcode::Volume(crate::model::Volume { volume })
+                .send(guild_id, socket)
+                .await?;
         }
 
         Ok(())
     }
 
+    /// Constructor for a partial update of the player, where only the fields that are set are
+    /// changed, see `UpdatePlayer`.
+    #[must_use]
+    pub fn update_player(&self, guild_id: impl Into<GuildId>) -> UpdatePlayer {
+        UpdatePlayer {
+            guild_id: guild_id.into().0,
+            track: None,
+            no_replace: false,
+            position: None,
+            end_time: None,
+            volume: None,
+            paused: None,
+            filters: None,
+            client: self.clone(),
+        }
+    }
+
     /// Constructor for playing a track.
     pub fn play(&self, guild_id: impl Into<GuildId>, track: Track) -> PlayParameters {
         PlayParameters {
@@ -490,6 +1653,9 @@ impl LavalinkClient {
             start: 0,
             finish: 0,
             requester: None,
+            user_data: None,
+            volume: None,
+            filters: None,
         }
     }
 
@@ -499,27 +1665,33 @@ impl LavalinkClient {
     /// This method does not remove the guild from the running event loops, nor does it clear the
     /// Node, this allows for reconnecting without losing data.
     /// If you are having issues with disconnecting and reconnecting the bot to a voice channel,
-    /// remove the guild from the running event loops and reset the nodes.
-    ///
-    /// The running loops and the nodes can be obtained via `LavalinkClient::nodes()` and
-    /// `LavalinkClient::loops()`
+    /// use `destroy_with_options()` to remove the guild from the running event loops and reset the
+    /// nodes.
     ///
     /// ```rust,untested
-    /// lavalink_client.destroy(guild_id).await?;
-    ///
-    /// {
-    ///     let nodes = lavalink_client.nodes().await;
-    ///     nodes.remove(&guild_id.0);
-    ///     
-    ///     let loops = lavalink_client.loops().await;
-    ///     loops.remove(&guild_id.0);
-    /// }
+    /// lavalink_client.destroy_with_options(guild_id, DestroyOptions::full()).await?;
     /// ```
     pub async fn destroy(&self, guild_id: impl Into<GuildId>) -> LavalinkResult<()> {
+        self.destroy_with_options(guild_id, DestroyOptions::default())
+            .await
+    }
+
+    /// Destroys the current player, also clearing the queue, the running loop or the node of the
+    /// guild as set on the options.
+    pub async fn destroy_with_options(
+        &self,
+        guild_id: impl Into<GuildId>,
+        options: DestroyOptions,
+    ) -> LavalinkResult<()> {
         let guild_id = guild_id.into();
+        let guild_lock = self.guild_lock(guild_id);
+        let guard = guild_lock.lock().await;
 
-        let socket: tokio::sync::mpsc::UnboundedSender<(TungsteniteMessage, mpsc::UnboundedSender<()>)>;
+        let socket: SocketSender;
         let nodes: Arc<DashMap<u64, Node>>;
+        let loops: Arc<DashSet<u64>>;
+        let guild_locks: Arc<DashMap<u64, Arc<tokio::sync::Mutex<()>>>>;
+        let event_sequences: Arc<DashMap<GuildId, u64>>;
 
         {
             let client = self.inner.lock();
@@ -532,14 +1704,30 @@ impl LavalinkClient {
                 .clone();
 
             nodes = client.nodes.clone();
+            loops = client.loops.clone();
+            guild_locks = client.guild_locks.clone();
+            event_sequences = client.event_sequences.clone();
         }
 
-        if let Some(mut node) = nodes.get_mut(&guild_id.0) {
+        if options.remove_node {
+            nodes.remove(&guild_id.0);
+        } else if let Some(mut node) = nodes.get_mut(&guild_id.0) {
             node.now_playing = None;
 
-            if !node.queue.is_empty() {
+            if options.clear_queue {
+                node.queue.clear();
+                node.looped_tracks.clear();
+            } else if !node.queue.is_empty() {
                 node.queue.remove(0);
             }
+
+            if options.remove_loop {
+                node.is_on_loops = false;
+            }
+        }
+
+        if options.remove_loop {
+            loops.remove(&guild_id.0);
         }
 
         crate::model::SendOpcode::Destroy
@@ -549,12 +1737,22 @@ impl LavalinkClient {
             )
             .await?;
 
+        // Once released, only the map holds the lock if no other operation is waiting on it.
+        drop(guard);
+        drop(guild_lock);
+        guild_locks.remove_if(&guild_id.0, |_, lock| Arc::strong_count(lock) == 1);
+        event_sequences.remove(&guild_id);
+
         Ok(())
     }
 
     /// Stops the current player.
     pub async fn stop(&self, guild_id: impl Into<GuildId>) -> LavalinkResult<()> {
-        let socket: tokio::sync::mpsc::UnboundedSender<(TungsteniteMessage, mpsc::UnboundedSender<()>)>;
+        let guild_id = guild_id.into();
+        let guild_lock = self.guild_lock(guild_id);
+        let _guard = guild_lock.lock().await;
+
+        let socket: SocketSender;
 
         {
             let client = self.inner.lock();
@@ -577,14 +1775,50 @@ impl LavalinkClient {
         Ok(())
     }
 
+    /// Stops the current player, clears the queue and disables the queue repeat.
+    ///
+    /// The queue is cleared under one node lock before stopping, so the queue loop can't start
+    /// the next track in between.
+    pub async fn stop_and_clear(&self, guild_id: impl Into<GuildId>) -> LavalinkResult<()> {
+        let guild_id = guild_id.into();
+        let guild_lock = self.guild_lock(guild_id);
+        let _guard = guild_lock.lock().await;
+
+        let (nodes, socket) = {
+            let client = self.inner.lock();
+
+            let socket = client
+                .socket_sender
+                .read()
+                .as_ref()
+                .ok_or(LavalinkError::MissingLavalinkSocket)?
+                .clone();
+
+            (client.nodes.clone(), socket)
+        };
+
+        if let Some(mut node) = nodes.get_mut(&guild_id.0) {
+            node.queue.clear();
+            node.now_playing = None;
+            node.repeat_queue = false;
+            node.looped_tracks.clear();
+        }
+
+        crate::model::SendOpcode::Stop
+            .send(guild_id, socket)
+            .await?;
+
+        Ok(())
+    }
+
     /// Skips the current playing track to the next item on the queue.
     ///
     /// If nothing is in the queue, the currently playing track will keep playing.
     /// Check if the queue is empty and run `stop()` if that's the case.
     pub async fn skip(&self, guild_id: impl Into<GuildId>) -> Option<TrackQueue> {
-        let client = self.inner.lock();
+        let nodes = self.inner.lock().nodes.clone();
 
-        if let TryResult::Present(mut node) = client.nodes.try_get_mut(&guild_id.into().0) {
+        if let TryResult::Present(mut node) = nodes.try_get_mut(&guild_id.into().0) {
             node.now_playing = None;
 
             return if node.queue.is_empty() {
@@ -597,10 +1831,80 @@ impl LavalinkClient {
         None
     }
 
+    /// Skips the current playing track, and starts the next one of the queue right away instead
+    /// of on the next queue tick.
+    ///
+    /// Returns the new playing track, or None if the queue finished, in which case the player is
+    /// stopped. The skipped track is kept for the queue repeat, if enabled.
+    pub async fn skip_and_play_next(
+        &self,
+        guild_id: impl Into<GuildId>,
+    ) -> LavalinkResult<Option<TrackQueue>> {
+        let guild_id = guild_id.into();
+        let guild_lock = self.guild_lock(guild_id);
+        let _guard = guild_lock.lock().await;
+
+        self.skip_and_play_next_locked(guild_id).await
+    }
+
+    /// `skip_and_play_next()` for a caller that already holds the guild lock.
+    pub(crate) async fn skip_and_play_next_locked(
+        &self,
+        guild_id: GuildId,
+    ) -> LavalinkResult<Option<TrackQueue>> {
+        let (nodes, socket) = {
+            let client = self.inner.lock();
+
+            let socket = client
+                .socket_sender
+                .read()
+                .as_ref()
+                .ok_or(LavalinkError::MissingLavalinkSocket)?
+                .clone();
+
+            (client.nodes.clone(), socket)
+        };
+
+        let next = {
+            let mut node = nodes
+                .get_mut(&guild_id.0)
+                .ok_or(LavalinkError::NoSessionPresent)?;
+
+            if !node.queue.is_empty() {
+                let skipped = node.queue.remove(0);
+
+                if node.repeat_queue {
+                    node.looped_tracks.push(skipped);
+                }
+            }
+
+            let next = node.queue.first().cloned();
+
+            match &next {
+                Some(track) => set_now_playing(&mut node, track),
+                None => node.now_playing = None,
+            }
+
+            next
+        };
+
+        match next.clone() {
+            Some(track) => play_track_queue(socket, guild_id.0, track).await?,
+            None => {
+                crate::model::SendOpcode::Stop
+                    .send(guild_id, socket)
+                    .await?;
+            }
+        }
+
+        Ok(next)
+    }
+
     /// Sets the pause status.
     pub async fn set_pause(&self, guild_id: impl Into<GuildId>, pause: bool) -> LavalinkResult<()> {
         let guild_id = guild_id.into().0;
-        let payload = crate::model::Pause { pause };
+        let guild_lock = self.guild_lock(guild_id);
+        let _guard = guild_lock.lock().await;
 
         {
             let nodes = self.nodes().await;
@@ -612,7 +1916,39 @@ impl LavalinkClient {
             }
         }
 
-        let socket: tokio::sync::mpsc::UnboundedSender<(TungsteniteMessage, mpsc::UnboundedSender<()>)>;
+        self.send_pause(guild_id, pause).await
+    }
+
+    /// Flips the pause status, returning the new one.
+    ///
+    /// The status is read and changed while holding the guild lock, so two toggles at the same
+    /// time, like from two button presses, pause and resume instead of both pausing.
+    ///
+    /// Returns `LavalinkError::NoSessionPresent` if the guild has no session.
+    pub async fn toggle_pause(&self, guild_id: impl Into<GuildId>) -> LavalinkResult<bool> {
+        let guild_id = guild_id.into().0;
+        let guild_lock = self.guild_lock(guild_id);
+        let _guard = guild_lock.lock().await;
+
+        let pause = {
+            let nodes = self.nodes().await;
+            let mut node = nodes
+                .get_mut(&guild_id)
+                .ok_or(LavalinkError::NoSessionPresent)?;
+            node.is_paused = !node.is_paused;
+            node.is_paused
+        };
+
+        self.send_pause(guild_id, pause).await?;
+
+        Ok(pause)
+    }
+
+    /// Sends the pause status to lavalink, the guild lock must be held by the caller.
+    async fn send_pause(&self, guild_id: u64, pause: bool) -> LavalinkResult<()> {
+        let payload = crate::model::Pause { pause };
+
+        let socket: SocketSender;
 
         {
             let client = self.inner.lock();
@@ -647,11 +1983,15 @@ impl LavalinkClient {
 
     /// Jumps to a specific time in the currently playing track.
     pub async fn seek(&self, guild_id: impl Into<GuildId>, time: Duration) -> LavalinkResult<()> {
-        let payload = crate::model::Seek {
+        let guild_id = guild_id.into();
+        let guild_lock = self.guild_lock(guild_id);
+        let _guard = guild_lock.lock().await;
+
+        let payload = crate::model::Seek {
             position: time.as_millis() as u64,
         };
 
-        let socket: tokio::sync::mpsc::UnboundedSender<(TungsteniteMessage, mpsc::UnboundedSender<()>)>;
+        let socket: SocketSender;
 
         {
             let client = self.inner.lock();
@@ -690,13 +2030,17 @@ impl LavalinkClient {
 
     /// Sets the volume of the player.
     pub async fn volume(&self, guild_id: impl Into<GuildId>, volume: u16) -> LavalinkResult<()> {
-        let good_volume = max(min(volume, 1000), 0);
+        let guild_id = guild_id.into();
+        let guild_lock = self.guild_lock(guild_id);
+        let _guard = guild_lock.lock().await;
+
+        let good_volume = volume.clamp(0, 1000);
 
         let payload = crate::model::Volume {
             volume: good_volume,
         };
 
-        let socket: tokio::sync::mpsc::UnboundedSender<(TungsteniteMessage, mpsc::UnboundedSender<()>)>;
+        let socket: SocketSender;
 
         {
             let client = self.inner.lock();
@@ -725,43 +2069,20 @@ impl LavalinkClient {
     /// - The floating point value is the multiplier for the given band.
     /// - The default value is 0.
     /// - Valid values range from -0.25 to 1.0, where -0.25 means the given band is completely muted, and 0.25 means it is doubled.
+    ///   Invalid values return `LavalinkError::InvalidBand`.
     /// - Modifying the gain could also change the volume of the output.
+    ///
+    /// The equalizer is sent with the other filters, see `update_filters()`. Lavalink before 3.4,
+    /// as detected by `detect_protocol()`, only gets the equalizer with its own op.
     pub async fn equalize_all(
         &self,
         guild_id: impl Into<GuildId>,
         bands: [f64; 15],
     ) -> LavalinkResult<()> {
-        let bands = bands
-            .iter()
-            .enumerate()
-            .map(|(index, i)| crate::model::Band {
-                band: index as u8,
-                gain: *i,
-            })
-            .collect::<Vec<_>>();
-
-        let payload = crate::model::Equalizer { bands };
-
-        let socket: tokio::sync::mpsc::UnboundedSender<(TungsteniteMessage, mpsc::UnboundedSender<()>)>;
-
-        {
-            let client = self.inner.lock();
-
-            socket = client
-                .socket_sender
-                .read()
-                .as_ref()
-                .ok_or(LavalinkError::MissingLavalinkSocket)?
-                .clone();
-        }
-        crate::model::SendOpcode::Equalizer(payload)
-            .send(
-                guild_id,
-                socket
-            )
-            .await?;
+        let bands = Bands::from_gains(bands)?;
 
-        Ok(())
+        self.update_filters(guild_id, |filters| filters.equalizer = Some(bands))
+            .await
     }
 
     /// Equalize a dynamic set of bands, rather than just one or all of them at once.
@@ -770,41 +2091,205 @@ impl LavalinkClient {
     pub async fn equalize_dynamic(
         &self,
         guild_id: impl Into<GuildId>,
-        bands: Vec<Band>,
+        bands: Bands,
+    ) -> LavalinkResult<()> {
+        self.update_filters(guild_id, |filters| {
+            filters
+                .equalizer
+                .get_or_insert_with(Bands::default)
+                .merge(bands);
+        })
+        .await
+    }
+
+    /// Equalizes a specific band.
+    pub async fn equalize_band(
+        &self,
+        guild_id: impl Into<GuildId>,
+        band: crate::model::Band,
+    ) -> LavalinkResult<()> {
+        self.equalize_dynamic(guild_id, Bands::new(vec![band])?)
+            .await
+    }
+
+    /// Equalizes all the bands with a preset, and remembers it on the node.
+    pub async fn equalize_preset(
+        &self,
+        guild_id: impl Into<GuildId>,
+        preset: EqPreset,
     ) -> LavalinkResult<()> {
-        let payload = crate::model::Equalizer { bands };
+        let guild_id = guild_id.into();
+        let bands = Bands::from_gains(preset.gains())?;
 
-        let socket: tokio::sync::mpsc::UnboundedSender<(TungsteniteMessage, mpsc::UnboundedSender<()>)>;
+        // The preset is set under the same guild lock, so no other equalizer change can land
+        // before it.
+        let guild_lock = self.guild_lock(guild_id);
+        let _guard = guild_lock.lock().await;
 
-        {
+        let mut filters = self.filters(guild_id).unwrap_or_default();
+        filters.equalizer = Some(bands);
+
+        self.send_filters(guild_id, filters).await?;
+
+        self.with_node(guild_id, |node| node.eq_preset = Some(preset))
+    }
+
+    /// Gets the equalizer preset applied to the guild with `equalize_preset()`, if the equalizer
+    /// was not changed afterwards.
+    #[must_use]
+    pub fn eq_preset(&self, guild_id: impl Into<GuildId>) -> Option<EqPreset> {
+        let nodes = self.inner.lock().nodes.clone();
+        let preset = nodes.get(&guild_id.into().0)?.eq_preset;
+        preset
+    }
+
+    /// Resets all equalizer levels.
+    pub async fn equalize_reset(&self, guild_id: impl Into<GuildId>) -> LavalinkResult<()> {
+        self.update_filters(guild_id, |filters| filters.equalizer = None)
+            .await
+    }
+
+    /// Gets the lock used to apply the operations on a guild in the order they were issued.
+    ///
+    /// Every method that modifies the player of a guild holds this lock until the message is sent
+    /// to lavalink. The lock isn't reentrant, so calling those methods while holding it deadlocks;
+    /// it can only keep them out while the node is read or changed directly, with `with_node()`
+    /// for example. The lock is dropped when the player is destroyed, unless another operation
+    /// is waiting on it.
+    #[must_use]
+    pub fn guild_lock(&self, guild_id: impl Into<GuildId>) -> Arc<tokio::sync::Mutex<()>> {
+        let guild_locks = self.inner.lock().guild_locks.clone();
+        let guild_lock = guild_locks.entry(guild_id.into().0).or_default().clone();
+        guild_lock
+    }
+
+    /// Obtains an atomic reference to the nodes
+    pub async fn nodes(&self) -> Arc<DashMap<u64, Node>> {
+        let client = self.inner.lock();
+        client.nodes.clone()
+    }
+
+    /// Obtains an atomic reference to the running queue loops
+    ///
+    /// A node `guild_id` is added here the first time [`PlayParameters::queue`] is called.
+    ///
+    /// [`PlayParameters::queue`]: crate::builders::PlayParameters
+    pub async fn loops(&self) -> Arc<DashSet<u64>> {
+        let client = self.inner.lock();
+        client.loops.clone()
+    }
+
+    /// Gets the latest player and track events of every guild, oldest first, up to the
+    /// `event_buffer_size` of the builder.
+    #[must_use]
+    pub fn buffered_events(&self) -> Vec<GuildEvent> {
+        self.inner.lock().event_buffer.iter().cloned().collect()
+    }
+
+    /// Returns a stream of the player and track events of every guild, starting with the
+    /// buffered ones, see `set_event_buffer_size()` on the builder.
+    ///
+    /// Useful for the parts of a bot that start after the client, like a dashboard. The stream
+    /// needs to be pinned before polling it, with `Box::pin()` or `futures::pin_mut!()` for
+    /// example.
+    pub fn events_with_replay(&self) -> impl futures::Stream<Item = GuildEvent> {
+        let (buffered, receiver) = {
             let client = self.inner.lock();
+            (client.event_buffer.clone(), client.guild_events.subscribe())
+        };
 
-            socket = client
-                .socket_sender
-                .read()
-                .as_ref()
-                .ok_or(LavalinkError::MissingLavalinkSocket)?
-                .clone();
+        stream::unfold(
+            (buffered, receiver),
+            |(mut buffered, mut receiver)| async move {
+                if let Some(event) = buffered.pop_front() {
+                    return Some((event, (buffered, receiver)));
+                }
+
+                loop {
+                    match receiver.recv().await {
+                        Ok(event) => return Some((event, (buffered, receiver))),
+                        Err(broadcast::error::RecvError::Lagged(count)) => {
+                            warn!("Events stream skipped {} events.", count);
+                        }
+                        Err(broadcast::error::RecvError::Closed) => return None,
+                    }
+                }
+            },
+        )
+    }
+
+    /// Returns a stream of the player and track events of a single guild.
+    ///
+    /// Only the events received after this method is called will be yielded. The stream needs to
+    /// be pinned before polling it, with `Box::pin()` or `futures::pin_mut!()` for example.
+    pub fn guild_events(
+        &self,
+        guild_id: impl Into<GuildId>,
+    ) -> impl futures::Stream<Item = GuildEvent> {
+        let receiver = self.subscribe_guild_events(guild_id);
+
+        stream::unfold(receiver, |mut receiver| async move {
+            receiver.recv().await.map(|event| (event, receiver))
+        })
+    }
+
+    /// Returns a receiver of the player and track events of a single guild.
+    ///
+    /// Only the events received after this method is called will be yielded. Useful to wait for
+    /// an event inside of a command, like the next `TrackStart` with a timeout.
+    #[must_use]
+    pub fn subscribe_guild_events(&self, guild_id: impl Into<GuildId>) -> GuildEventReceiver {
+        GuildEventReceiver {
+            guild_id: guild_id.into(),
+            receiver: self.inner.lock().guild_events.subscribe(),
         }
-        crate::model::SendOpcode::Equalizer(payload)
-            .send(
-                guild_id,
-                socket
-            )
-            .await?;
+    }
 
-        Ok(())
+    /// Gets the latest player state of the guild, as received from the `playerUpdate` event.
+    #[must_use]
+    pub fn player_state(&self, guild_id: impl Into<GuildId>) -> Option<PlayerUpdateState> {
+        let nodes = self.inner.lock().nodes.clone();
+        let state = nodes.get(&guild_id.into().0)?.player_state;
+        state
     }
 
-    /// Equalizes a specific band.
-    pub async fn equalize_band(
+    /// Sets the audio filters of the player, replacing the current ones.
+    pub async fn set_filters(
         &self,
         guild_id: impl Into<GuildId>,
-        band: crate::model::Band,
+        filters: Filters,
+    ) -> LavalinkResult<()> {
+        let guild_id = guild_id.into();
+        let guild_lock = self.guild_lock(guild_id);
+        let _guard = guild_lock.lock().await;
+
+        self.send_filters(guild_id, filters).await
+    }
+
+    /// Modifies the current audio filters of the player, and sends them.
+    ///
+    /// The closure receives the last filters that were sent, or the default ones.
+    ///
+    /// Lavalink before 3.4 only gets the equalizer, see `ServerProtocol::supports_filters()`.
+    pub async fn update_filters(
+        &self,
+        guild_id: impl Into<GuildId>,
+        f: impl FnOnce(&mut Filters),
     ) -> LavalinkResult<()> {
-        let payload = crate::model::Equalizer { bands: vec![band] };
+        let guild_id = guild_id.into();
+        let guild_lock = self.guild_lock(guild_id);
+        let _guard = guild_lock.lock().await;
 
-        let socket: tokio::sync::mpsc::UnboundedSender<(TungsteniteMessage, mpsc::UnboundedSender<()>)>;
+        let mut filters = self.filters(guild_id).unwrap_or_default();
+        f(&mut filters);
+
+        self.send_filters(guild_id, filters).await
+    }
+
+    async fn send_filters(&self, guild_id: GuildId, filters: Filters) -> LavalinkResult<()> {
+        let socket: SocketSender;
+        let nodes: Arc<DashMap<u64, Node>>;
+        let legacy_equalizer: bool;
 
         {
             let client = self.inner.lock();
@@ -815,102 +2300,1529 @@ impl LavalinkClient {
                 .as_ref()
                 .ok_or(LavalinkError::MissingLavalinkSocket)?
                 .clone();
+
+            nodes = client.nodes.clone();
+            legacy_equalizer = client
+                .protocol
+                .as_ref()
+                .map_or(false, |protocol| !protocol.supports_filters());
         }
 
-        crate::model::SendOpcode::Equalizer(payload)
-            .send(
-                guild_id,
-                socket
-            )
-            .await?;
+        if legacy_equalizer {
+            // Lavalink before 3.4 only has the equalizer, so the other filters are not sent.
+            let bands = match &filters.equalizer {
+                Some(bands) => bands.clone(),
+                None => Bands::from_gains([0.0; 15])?,
+            };
+
+            crate::model::SendOpcode::Equalizer(Equalizer { bands })
+                .send(guild_id, socket)
+                .await?;
+        } else {
+            crate::model::SendOpcode::Filters(Box::new(filters.clone()))
+                .send(guild_id, socket)
+                .await?;
+        }
+
+        if let Some(mut node) = nodes.get_mut(&guild_id.0) {
+            let current_equalizer = node.filters.as_ref().and_then(|f| f.equalizer.as_ref());
+
+            // The equalizer no longer matches the preset.
+            if current_equalizer != filters.equalizer.as_ref() {
+                node.eq_preset = None;
+            }
+
+            node.filters = Some(filters);
+        }
 
         Ok(())
     }
 
-    /// Resets all equalizer levels.
-    pub async fn equalize_reset(&self, guild_id: impl Into<GuildId>) -> LavalinkResult<()> {
-        let bands = (0..=14)
-            .map(|i| crate::model::Band {
-                band: i as u8,
-                gain: 0.,
-            })
-            .collect::<Vec<_>>();
+    /// Enables the karaoke filter, which removes the vocals of the track.
+    ///
+    /// The values that are None use the lavalink defaults: a level and mono level of 1.0, and a
+    /// band of 220 Hz with a width of 100 Hz.
+    pub async fn set_karaoke(
+        &self,
+        guild_id: impl Into<GuildId>,
+        level: Option<f64>,
+        mono_level: Option<f64>,
+        filter_band: Option<f64>,
+        filter_width: Option<f64>,
+    ) -> LavalinkResult<()> {
+        self.update_filters(guild_id, |filters| {
+            filters.karaoke = Some(Karaoke {
+                level,
+                mono_level,
+                filter_band,
+                filter_width,
+            });
+        })
+        .await
+    }
 
-        let payload = crate::model::Equalizer { bands };
+    /// Disables the karaoke filter, keeping the other filters.
+    pub async fn disable_karaoke(&self, guild_id: impl Into<GuildId>) -> LavalinkResult<()> {
+        self.update_filters(guild_id, |filters| filters.karaoke = None)
+            .await
+    }
 
-        let socket: tokio::sync::mpsc::UnboundedSender<(TungsteniteMessage, mpsc::UnboundedSender<()>)>;
+    /// Sets the playback speed, keeping the other timescale values. 1.0 is the normal speed.
+    pub async fn set_speed(&self, guild_id: impl Into<GuildId>, speed: f64) -> LavalinkResult<()> {
+        self.update_filters(guild_id, |filters| {
+            filters
+                .timescale
+                .get_or_insert_with(Timescale::default)
+                .speed = Some(speed);
+        })
+        .await
+    }
 
-        {
-            let client = self.inner.lock();
+    /// Sets the pitch, keeping the other timescale values. 1.0 is the normal pitch.
+    pub async fn set_pitch(&self, guild_id: impl Into<GuildId>, pitch: f64) -> LavalinkResult<()> {
+        self.update_filters(guild_id, |filters| {
+            filters
+                .timescale
+                .get_or_insert_with(Timescale::default)
+                .pitch = Some(pitch);
+        })
+        .await
+    }
 
-            socket = client
-                .socket_sender
-                .read()
-                .as_ref()
-                .ok_or(LavalinkError::MissingLavalinkSocket)?
-                .clone();
-        }
+    /// Sets the rate, which changes both the speed and pitch, keeping the other timescale values.
+    /// 1.0 is the normal rate.
+    pub async fn set_rate(&self, guild_id: impl Into<GuildId>, rate: f64) -> LavalinkResult<()> {
+        self.update_filters(guild_id, |filters| {
+            filters
+                .timescale
+                .get_or_insert_with(Timescale::default)
+                .rate = Some(rate);
+        })
+        .await
+    }
 
-        crate::model::SendOpcode::Equalizer(payload)
-            .send(
-                guild_id,
-                socket,
-            )
-            .await?;
+    /// Speeds up the track and raises its pitch.
+    pub async fn nightcore(&self, guild_id: impl Into<GuildId>) -> LavalinkResult<()> {
+        self.update_filters(guild_id, |filters| {
+            filters.timescale = Some(Timescale::nightcore());
+        })
+        .await
+    }
 
-        Ok(())
+    /// Slows down the track and lowers its pitch.
+    pub async fn vaporwave(&self, guild_id: impl Into<GuildId>) -> LavalinkResult<()> {
+        self.update_filters(guild_id, |filters| {
+            filters.timescale = Some(Timescale::vaporwave());
+        })
+        .await
     }
 
-    /// Obtains an atomic reference to the nodes
-    pub async fn nodes(&self) -> Arc<DashMap<u64, Node>> {
-        let client = self.inner.lock();
-        client.nodes.clone()
+    /// Disables the timescale filter, going back to the normal speed, pitch and rate.
+    pub async fn reset_timescale(&self, guild_id: impl Into<GuildId>) -> LavalinkResult<()> {
+        self.update_filters(guild_id, |filters| filters.timescale = None)
+            .await
     }
 
-    /// Obtains an atomic reference to the running queue loops
-    ///
-    /// A node `guild_id` is added here the first time [`PlayParameters::queue`] is called.
+    /// Gets the last filters sent to the player of the guild.
+    #[must_use]
+    pub fn filters(&self, guild_id: impl Into<GuildId>) -> Option<Filters> {
+        let nodes = self.inner.lock().nodes.clone();
+        let filters = nodes.get(&guild_id.into().0)?.filters.clone();
+        filters
+    }
+
+    /// Sets if a recommended track should be queued when the queue of the guild finishes.
+    pub fn set_autoplay(&self, guild_id: impl Into<GuildId>, autoplay: bool) -> LavalinkResult<()> {
+        self.with_node(guild_id, |node| node.autoplay = autoplay)
+    }
+
+    /// Sets if the finished tracks of the guild are added back to the queue once it's drained.
     ///
-    /// [`PlayParameters::queue`]: crate::builders::PlayParameters
-    pub async fn loops(&self) -> Arc<DashSet<u64>> {
-        let client = self.inner.lock();
-        client.loops.clone()
+    /// The `queue_looped` event is dispatched every time the queue starts again.
+    pub fn set_repeat_queue(
+        &self,
+        guild_id: impl Into<GuildId>,
+        repeat_queue: bool,
+    ) -> LavalinkResult<()> {
+        self.with_node(guild_id, |node| {
+            node.repeat_queue = repeat_queue;
+
+            if !repeat_queue {
+                node.looped_tracks.clear();
+            }
+        })
     }
 
-    /// Gets the discord gateway data.
+    /// Sets if the queue loop starts the next track of the guild when the current one finishes.
     ///
-    /// Note that the Mutex is from parking lot and it cannot be used across awaits.
-    #[cfg(feature = "discord-gateway")]
-    #[must_use]
-    pub fn discord_gateway_data(&self) -> Arc<Mutex<DiscordGatewayData>> {
-        self.inner.lock().discord_gateway_data.clone()
+    /// When disabled, the player stops at the end of the current track, and the queue continues
+    /// once it's enabled again. The next track is also not started while the player is paused.
+    pub fn set_auto_advance(
+        &self,
+        guild_id: impl Into<GuildId>,
+        auto_advance: bool,
+    ) -> LavalinkResult<()> {
+        self.with_node(guild_id, |node| node.auto_advance = auto_advance)
     }
 
-    /// Gets the list of voice connections from the discord gateway.
-    #[cfg(feature = "discord-gateway")]
+    /// Sets the limits checked when a track is queued on the guild.
+    pub fn set_queue_policy(
+        &self,
+        guild_id: impl Into<GuildId>,
+        queue_policy: QueuePolicy,
+    ) -> LavalinkResult<()> {
+        self.with_node(guild_id, |node| node.queue_policy = queue_policy)
+    }
+
+    /// Sets where new tracks are inserted on the queue of the guild.
+    pub fn set_queue_mode(
+        &self,
+        guild_id: impl Into<GuildId>,
+        queue_mode: QueueMode,
+    ) -> LavalinkResult<()> {
+        self.with_node(guild_id, |node| node.queue_mode = queue_mode)
+    }
+
+    /// Gets an owned copy of the node of the guild.
+    ///
+    /// Unlike `nodes()`, this doesn't hold a lock on the node, so it can be kept across awaits
+    /// without blocking the queue loop.
     #[must_use]
-    pub fn discord_gateway_connections(&self) -> Arc<DashMap<GuildId, ConnectionInfo>> {
-        self.inner
-            .lock()
-            .discord_gateway_data
-            .lock()
-            .connections
-            .clone()
+    pub fn node_snapshot(&self, guild_id: impl Into<GuildId>) -> Option<NodeSnapshot> {
+        let nodes = self.inner.lock().nodes.clone();
+        let snapshot = nodes
+            .get(&guild_id.into().0)
+            .map(|node| NodeSnapshot::from(&*node));
+        snapshot
     }
 
-    #[cfg(feature = "discord-gateway")]
-    /// Joins the voice channel via the discord gateway.
-    pub async fn join(
+    /// Runs the closure with a mutable reference to the node of the guild, releasing the node
+    /// before returning.
+    ///
+    /// The closure is synchronous, so the node can't be held across awaits by accident.
+    ///
+    /// Returns `LavalinkError::NoSessionPresent` if the guild has no node.
+    pub fn with_node<T>(
         &self,
         guild_id: impl Into<GuildId>,
-        channel_id: impl Into<ChannelId>,
-    ) -> LavalinkResult<ConnectionInfo> {
-        crate::voice::join(self, guild_id, channel_id).await
+        f: impl FnOnce(&mut Node) -> T,
+    ) -> LavalinkResult<T> {
+        let nodes = self.inner.lock().nodes.clone();
+        let mut node = nodes
+            .get_mut(&guild_id.into().0)
+            .ok_or(LavalinkError::NoSessionPresent)?;
+
+        Ok(f(node.value_mut()))
     }
 
-    #[cfg(feature = "discord-gateway")]
-    /// Joins the voice channel from the guild using the discord gateway.
-    pub async fn leave(&self, guild_id: impl Into<GuildId>) -> LavalinkResult<()> {
-        crate::voice::leave(self, guild_id).await
+    /// Checks if the player of the guild is paused.
+    #[must_use]
+    pub fn is_paused(&self, guild_id: impl Into<GuildId>) -> bool {
+        let nodes = self.inner.lock().nodes.clone();
+        let paused = nodes
+            .get(&guild_id.into().0)
+            .map_or(false, |node| node.is_paused);
+        paused
+    }
+
+    /// Checks if the guild has a track playing, and it's not paused.
+    #[must_use]
+    pub fn is_playing(&self, guild_id: impl Into<GuildId>) -> bool {
+        let nodes = self.inner.lock().nodes.clone();
+        let playing = nodes
+            .get(&guild_id.into().0)
+            .map_or(false, |node| node.now_playing.is_some() && !node.is_paused);
+        playing
+    }
+
+    /// Gets the number of tracks on the queue of the guild, including the one currently playing.
+    #[must_use]
+    pub fn queue_len(&self, guild_id: impl Into<GuildId>) -> usize {
+        let nodes = self.inner.lock().nodes.clone();
+        let len = nodes
+            .get(&guild_id.into().0)
+            .map_or(0, |node| node.queue.len());
+        len
+    }
+
+    /// Gets the position of the currently playing track.
+    ///
+    /// The position is estimated from the latest player state, accounting for the time that has
+    /// passed since it was received if the player is not paused.
+    #[must_use]
+    pub fn position(&self, guild_id: impl Into<GuildId>) -> Option<Duration> {
+        let nodes = self.inner.lock().nodes.clone();
+        let node = nodes.get(&guild_id.into().0)?;
+
+        let state = node.player_state?;
+        let length = node.now_playing.as_ref()?.track.info.as_ref().map(|i| i.length);
+
+        let position = if node.is_paused {
+            state.position()
+        } else {
+            state.position() + state.age()
+        };
+
+        drop(node);
+
+        Some(match length {
+            Some(length) => min(position, Duration::from_millis(length)),
+            None => position,
+        })
+    }
+
+    /// Renders the now playing line of the guild with a progress bar of `width` characters, see
+    /// `model::formatting::now_playing()`.
+    #[must_use]
+    pub fn now_playing_bar(&self, guild_id: impl Into<GuildId>, width: usize) -> Option<String> {
+        let guild_id = guild_id.into();
+        let position = self.position(guild_id)?;
+
+        let nodes = self.inner.lock().nodes.clone();
+        let info = nodes
+            .get(&guild_id.0)?
+            .now_playing
+            .as_ref()?
+            .track
+            .info
+            .clone()?;
+
+        Some(model::formatting::now_playing(&info, position, width))
+    }
+
+    /// Gets the time left until the currently playing track ends.
+    ///
+    /// Returns None if nothing is playing, or the track is a stream.
+    #[must_use]
+    pub fn remaining(&self, guild_id: impl Into<GuildId>) -> Option<Duration> {
+        let guild_id = guild_id.into();
+        let position = self.position(guild_id)?;
+        let nodes = self.inner.lock().nodes.clone();
+
+        let end = {
+            let node = nodes.get(&guild_id.0)?;
+            let track = node.now_playing.as_ref()?;
+            let info = track.track.info.as_ref()?;
+
+            if info.is_stream {
+                return None;
+            }
+
+            track.end_time.unwrap_or(info.length).min(info.length)
+        };
+
+        Some(
+            Duration::from_millis(end)
+                .checked_sub(position)
+                .unwrap_or_default(),
+        )
+    }
+
+    /// Checks if lavalink is connected to the voice gateway of the guild.
+    #[must_use]
+    pub fn is_connected(&self, guild_id: impl Into<GuildId>) -> bool {
+        self.player_state(guild_id)
+            .map_or(false, |state| state.is_connected())
+    }
+
+    /// Returns the shard that handles the guild, according to the configured shard count.
+    #[must_use]
+    pub fn shard_for_guild(&self, guild_id: impl Into<GuildId>) -> u64 {
+        let shard_count = self.inner.lock().shard_count.max(1);
+
+        (guild_id.into().0 >> 22) % shard_count
+    }
+
+    /// Checks if the shard that received the events of a guild is the one lavalink expects.
+    ///
+    /// Call this with the shard id that got the voice events of the guild. If the configured shard
+    /// count is wrong, lavalink can fail to connect to the voice channels silently; this logs a
+    /// warning and triggers the `shard_mismatch` event instead. Returns false on a mismatch.
+    pub async fn check_guild_shard(&self, guild_id: impl Into<GuildId>, shard_id: u64) -> bool {
+        let guild_id = guild_id.into();
+        let expected_shard_id = self.shard_for_guild(guild_id);
+
+        if expected_shard_id == shard_id {
+            return true;
+        }
+
+        let (handler, shard_count) = {
+            let client = self.inner.lock();
+            (client.handler.clone(), client.shard_count)
+        };
+
+        warn!(
+            "Guild {} was received on shard {}, but shard {} was expected with {} shards. Is the shard count correct?",
+            guild_id.0, shard_id, expected_shard_id, shard_count
+        );
+
+        handler
+            .shard_mismatch(
+                self.clone(),
+                ShardMismatch {
+                    guild_id,
+                    shard_id,
+                    expected_shard_id,
+                    shard_count,
+                },
+            )
+            .await;
+
+        false
+    }
+
+    /// Starts draining the client: sessions for new guilds are rejected with
+    /// `LavalinkError::Draining`, while the existing players keep playing their queues.
+    ///
+    /// The `drained` event triggers once no players are active, see `drain_report()`.
+    pub fn start_drain(&self) {
+        {
+            let mut client = self.inner.lock();
+            client.draining = true;
+            client.drain_notified = false;
+        }
+
+        crate::event_loops::check_drained(self);
+    }
+
+    /// Stops draining the client, accepting new players again.
+    ///
+    /// The `drained` event triggers again on the next `start_drain()`.
+    pub fn cancel_drain(&self) {
+        let mut client = self.inner.lock();
+        client.draining = false;
+        client.drain_notified = false;
+    }
+
+    /// Checks if the client is draining.
+    #[must_use]
+    pub fn is_draining(&self) -> bool {
+        self.inner.lock().draining
+    }
+
+    /// Returns the players that are still active, with an estimate of the time left to finish
+    /// their queues.
+    #[must_use]
+    pub fn drain_report(&self) -> Vec<DrainingPlayer> {
+        let nodes = self.inner.lock().nodes.clone();
+
+        let active = nodes
+            .iter()
+            .filter(|node| node.now_playing.is_some() || !node.queue.is_empty())
+            .map(|node| (*node.key(), node.now_playing.clone(), node.queue.clone()))
+            .collect::<Vec<_>>();
+
+        active
+            .into_iter()
+            .map(|(guild_id, now_playing, queue)| {
+                let position = self.position(guild_id).unwrap_or_default();
+
+                let remaining = queue.iter().enumerate().try_fold(
+                    Duration::default(),
+                    |total, (idx, track)| {
+                        let info = track.track.info.as_ref()?;
+
+                        if info.is_stream {
+                            return None;
+                        }
+
+                        let end = track.end_time.unwrap_or(info.length).min(info.length);
+                        let start = if idx == 0 && now_playing.is_some() {
+                            position.as_millis() as u64
+                        } else {
+                            track.start_time
+                        };
+
+                        Some(total + Duration::from_millis(end.saturating_sub(start)))
+                    },
+                );
+
+                DrainingPlayer {
+                    guild_id: GuildId(guild_id),
+                    now_playing,
+                    queue_len: queue.len(),
+                    remaining,
+                }
+            })
+            .collect()
+    }
+
+    /// Creates a weak handle to the client, that doesn't keep it alive.
+    #[must_use]
+    pub fn downgrade(&self) -> WeakLavalinkClient {
+        WeakLavalinkClient {
+            inner: Arc::downgrade(&self.inner),
+        }
+    }
+
+    /// Stops the event loops and queue loops, and closes the websockets.
+    ///
+    /// The client can't be used to play anything afterwards, and it's freed once the last
+    /// `LavalinkClient` is dropped.
+    pub async fn shutdown(&self) -> LavalinkResult<()> {
+        let (socket, loops) = {
+            let mut client = self.inner.lock();
+            client.is_shutdown = true;
+            client.queue_notify.notify_one();
+
+            let socket = client.socket_sender.write().take();
+            (socket, client.loops.clone())
+        };
+
+        loops.clear();
+
+        // The gateway data is only created once used, so there's nothing to shut down before.
+        #[cfg(feature = "discord-gateway")]
+        {
+            let discord_gateway_data = self.inner.lock().discord_gateway_data.clone();
+
+            if let Some(data) = discord_gateway_data {
+                drop(data.lock().sender.send("shutdown".to_string()));
+            }
+        }
+
+        if let Some(socket) = socket {
+            socket
+                .send(async_tungstenite::tungstenite::Message::Close(None))
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Shuts down the client like `shutdown()`, and waits for the event loops and background
+    /// tasks to end, so the client can be built again without leaking them.
+    ///
+    /// The tasks that don't end within 5 seconds are aborted.
+    pub async fn close(&self) -> LavalinkResult<()> {
+        let result = self.shutdown().await;
+
+        let (tasks, guild_dispatchers) = {
+            let mut client = self.inner.lock();
+            (
+                std::mem::take(&mut client.tasks),
+                client.guild_dispatchers.clone(),
+            )
+        };
+
+        guild_dispatchers.clear();
+
+        for mut task in tasks {
+            if tokio::time::timeout(CLOSE_TIMEOUT, &mut task)
+                .await
+                .is_err()
+            {
+                task.abort();
+                drop(task.await);
+            }
+        }
+
+        result
+    }
+
+    /// The lavalink host currently in use, which is a fallback one if connecting to the primary
+    /// host failed.
+    #[must_use]
+    pub fn current_host(&self) -> String {
+        self.inner.lock().current_host().to_string()
+    }
+
+    /// Checks if `shutdown()` was called.
+    #[must_use]
+    pub fn is_shutdown(&self) -> bool {
+        self.inner.lock().is_shutdown
+    }
+
+    /// Updates the shard count sent to lavalink, and reconnects to the lavalink websocket so it
+    /// takes effect.
+    pub async fn set_shard_count(&self, shard_count: u64) -> LavalinkResult<()> {
+        {
+            let mut client = self.inner.lock();
+            client
+                .headers
+                .insert("Num-Shards", shard_count.to_string().parse()?);
+            client.shard_count = shard_count;
+
+            #[cfg(feature = "discord-gateway")]
+            if let Some(data) = &client.discord_gateway_data {
+                data.lock().shard_count = shard_count;
+            }
+        }
+
+        self.reconnect().await
+    }
+
+    /// Adds a lavalink host at runtime, tried after the current ones when connecting fails.
+    ///
+    /// The host uses the same port and SSL as the other ones, a scheme or trailing slash is
+    /// ignored.
+    pub fn add_host(&self, host: impl Into<String>) -> LavalinkResult<()> {
+        let host = builders::normalize_host("hosts", &host.into())?;
+
+        let mut client = self.inner.lock();
+
+        if !client.hosts.contains(&host) {
+            client.hosts.push(host);
+        }
+
+        Ok(())
+    }
+
+    /// Removes a lavalink host at runtime, like to take a server down for maintenance.
+    ///
+    /// If the host is the one in use, the client switches to the next one and reconnects; the
+    /// players are not moved to the new server. Returns false if the host was not found.
+    pub async fn remove_host(&self, host: &str) -> LavalinkResult<bool> {
+        let host = builders::normalize_host("hosts", host)?;
+
+        let socket = {
+            let mut client = self.inner.lock();
+            let index = client.hosts.iter().position(|i| *i == host);
+
+            if index.is_some() && client.hosts.len() == 1 {
+                return Err(LavalinkError::InvalidConfig(
+                    "hosts",
+                    "the last host can't be removed".to_string(),
+                ));
+            }
+
+            match index.map(|index| (index, index.cmp(&client.host_index))) {
+                None => return Ok(false),
+                Some((index, Ordering::Less)) => {
+                    client.hosts.remove(index);
+                    client.host_index -= 1;
+                    None
+                }
+                Some((index, Ordering::Equal)) => {
+                    client.hosts.remove(index);
+                    // Goes back one, so the next host is the one that took its place.
+                    client.host_index = index.checked_sub(1).unwrap_or(client.hosts.len() - 1);
+                    client.use_next_host();
+                    client.socket_sender.read().clone()
+                }
+                Some((index, Ordering::Greater)) => {
+                    client.hosts.remove(index);
+                    None
+                }
+            }
+        };
+
+        // Without a socket, the event loop is already reconnecting and uses the new host.
+        if let Some(socket) = socket {
+            socket
+                .send(async_tungstenite::tungstenite::Message::Close(None))
+                .await?;
+        }
+
+        Ok(true)
+    }
+
+    /// Updates the lavalink password, and reconnects to the lavalink websocket so it takes effect.
+    ///
+    /// The REST requests use the new password right away.
+    pub async fn set_password(&self, password: impl ToString) -> LavalinkResult<()> {
+        let password = password.to_string().trim().to_string();
+
+        if password.is_empty() {
+            return Err(LavalinkError::InvalidConfig(
+                "password",
+                "the password is empty".to_string(),
+            ));
+        }
+
+        {
+            let mut client = self.inner.lock();
+            client.headers.insert("Authorization", password.parse()?);
+        }
+
+        self.reconnect().await
+    }
+
+    /// Updates the bot token, and reconnects to the discord gateway with it if it's running.
+    ///
+    /// The gateway identifies again instead of resuming the session.
+    #[cfg(feature = "discord-gateway")]
+    pub fn set_bot_token(&self, bot_token: impl Into<String>) -> LavalinkResult<()> {
+        let bot_token: String = bot_token.into();
+        let bot_token = bot_token.trim().to_string();
+
+        if bot_token.is_empty() {
+            return Err(LavalinkError::InvalidConfig(
+                "bot_token",
+                "the bot token is empty".to_string(),
+            ));
+        }
+
+        let authorization = format!("Bot {}", bot_token).parse()?;
+
+        let discord_gateway_data = {
+            let mut client = self.inner.lock();
+            client.gateway_config.bot_token.clone_from(&bot_token);
+            client.discord_gateway_data.clone()
+        };
+
+        // The gateway data is created with the new token if it wasn't used yet.
+        if let Some(data) = discord_gateway_data {
+            let mut data = data.lock();
+            data.bot_token = bot_token;
+            data.headers.insert("Authorization", authorization);
+
+            // Fails if the gateway is not running, which is fine.
+            drop(data.sender.send("rotate_token".to_string()));
+        }
+
+        Ok(())
+    }
+
+    /// Closes the lavalink websocket, so the event loop connects again with the current headers.
+    pub async fn reconnect(&self) -> LavalinkResult<()> {
+        let socket = self
+            .inner
+            .lock()
+            .socket_sender
+            .read()
+            .clone()
+            .ok_or(LavalinkError::MissingLavalinkSocket)?;
+
+        socket
+            .send(async_tungstenite::tungstenite::Message::Close(None))
+            .await
+    }
+
+    /// Gets the discord gateway data.
+    ///
+    /// Note that the Mutex is from parking lot and it cannot be used across awaits.
+    ///
+    /// The data is created the first time it's used, so clients that never start the discord
+    /// gateway don't allocate it.
+    #[cfg(feature = "discord-gateway")]
+    #[must_use]
+    pub fn discord_gateway_data(&self) -> Arc<Mutex<DiscordGatewayData>> {
+        let mut client = self.inner.lock();
+
+        if let Some(data) = &client.discord_gateway_data {
+            return data.clone();
+        }
+
+        let data = Arc::new(Mutex::new(DiscordGatewayData::new(
+            &client.gateway_config,
+            client.shard_count,
+        )));
+        client.discord_gateway_data = Some(data.clone());
+
+        data
+    }
+
+    /// Gets the list of voice connections from the discord gateway.
+    #[cfg(feature = "discord-gateway")]
+    #[must_use]
+    pub fn discord_gateway_connections(&self) -> Arc<DashMap<GuildId, ConnectionInfo>> {
+        self.discord_gateway_data().lock().connections.clone()
+    }
+
+    #[cfg(feature = "discord-gateway")]
+    /// Joins the voice channel via the discord gateway, or the configured `VoiceGateway`.
+    ///
+    /// Returns `LavalinkError::JoinTimedOut` if discord didn't send the voice connection after
+    /// every attempt, leaving the channel and forgetting the partial connection, or
+    /// `LavalinkError::MissingPermissions` if the permission check of the builder failed.
+    pub async fn join(
+        &self,
+        guild_id: impl Into<GuildId>,
+        channel_id: impl Into<ChannelId>,
+    ) -> LavalinkResult<ConnectionInfo> {
+        crate::voice::join(self, guild_id, channel_id).await
+    }
+
+    #[cfg(feature = "discord-gateway")]
+    /// Updates the presence of the bot on the discord gateway.
+    ///
+    /// The presence is also stored, so it's used again when the gateway reconnects.
+    pub fn set_presence(&self, presence: Presence) {
+        let payload = GatewayPayload::new(3, &presence).to_json();
+
+        self.discord_gateway_data().lock().presence = Some(presence);
+
+        crate::gateway::call_discord_gateway(self, payload);
+    }
+
+    #[cfg(feature = "discord-gateway")]
+    /// Joins the voice channel from the guild using the discord gateway.
+    pub async fn leave(&self, guild_id: impl Into<GuildId>) -> LavalinkResult<()> {
+        crate::voice::leave(self, guild_id).await
+    }
+
+    #[cfg(feature = "discord-gateway")]
+    /// Gets the voice states of the members in the voice channel, including the bot, as tracked
+    /// from the discord gateway.
+    ///
+    /// Useful to count the listeners for vote skips, or to know if the bot was left alone.
+    #[must_use]
+    pub fn voice_channel_members(
+        &self,
+        guild_id: impl Into<GuildId>,
+        channel_id: impl Into<ChannelId>,
+    ) -> Vec<MemberVoiceState> {
+        let channel_id = channel_id.into();
+        let voice_states = self.discord_gateway_data().lock().voice_states.clone();
+
+        let members = voice_states
+            .get(&guild_id.into())
+            .map_or_else(Vec::new, |guild| {
+                guild
+                    .values()
+                    .filter(|state| state.channel_id == channel_id)
+                    .copied()
+                    .collect()
+            });
+        members
+    }
+
+    #[cfg(feature = "discord-gateway")]
+    /// Returns a stream of the dispatch events of the discord gateway other than the voice ones,
+    /// like `READY` or `GUILD_CREATE`, for bots that don't open a gateway connection of their own.
+    ///
+    /// Only the events received after this method is called will be yielded, and the events are
+    /// only parsed while there's a stream to forward them to. The stream needs to be pinned before
+    /// polling it, with `Box::pin()` or `futures::pin_mut!()` for example.
+    pub fn gateway_events(&self) -> impl futures::Stream<Item = DiscordGatewayEvent> {
+        let receiver = self.discord_gateway_data().lock().events.subscribe();
+
+        stream::unfold(receiver, |mut receiver| async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(event) => return Some((event, receiver)),
+                    Err(broadcast::error::RecvError::Lagged(count)) => {
+                        warn!("Gateway events stream skipped {} events.", count);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        })
+    }
+
+    #[cfg(feature = "discord-gateway")]
+    /// Makes the bot a speaker of the stage channel it's in, or requests to speak if it lacks the
+    /// `MUTE_MEMBERS` permission.
+    ///
+    /// Done automatically when joining a stage channel, unless `auto_stage_speaker` is disabled.
+    pub async fn become_stage_speaker(
+        &self,
+        guild_id: impl Into<GuildId>,
+        channel_id: impl Into<ChannelId>,
+    ) -> LavalinkResult<()> {
+        crate::voice::become_stage_speaker(self, guild_id, channel_id).await
+    }
+
+    #[cfg(feature = "discord-gateway")]
+    /// Server mutes or unmutes a member of the guild through the discord REST API, using the bot
+    /// token of the gateway.
+    ///
+    /// The bot needs the `MUTE_MEMBERS` permission, and the member must be in a voice channel.
+    pub async fn server_mute(
+        &self,
+        guild_id: impl Into<GuildId>,
+        user_id: impl Into<UserId>,
+        mute: bool,
+    ) -> LavalinkResult<()> {
+        crate::voice::server_mute(self, guild_id, user_id, mute).await
+    }
+
+    #[cfg(feature = "discord-gateway")]
+    /// Server deafens or undeafens a member of the guild through the discord REST API, using the
+    /// bot token of the gateway.
+    ///
+    /// The bot needs the `DEAFEN_MEMBERS` permission, and the member must be in a voice channel.
+    pub async fn server_deafen(
+        &self,
+        guild_id: impl Into<GuildId>,
+        user_id: impl Into<UserId>,
+        deafen: bool,
+    ) -> LavalinkResult<()> {
+        crate::voice::server_deafen(self, guild_id, user_id, deafen).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_tungstenite::tungstenite::Message as TungsteniteMessage;
+    use futures::{SinkExt, StreamExt};
+    use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
+    use std::sync::mpsc as std_mpsc;
+    use std::thread;
+
+    struct Handler;
+
+    #[async_trait]
+    impl LavalinkEventHandler for Handler {}
+
+    /// Gets a builder that doesn't start the discord gateway.
+    fn test_builder() -> LavalinkClientBuilder {
+        #[cfg(feature = "discord-gateway")]
+        {
+            let mut builder = LavalinkClient::builder(1_u64, "token");
+            builder.set_start_gateway(false);
+            builder
+        }
+        #[cfg(not(feature = "discord-gateway"))]
+        {
+            LavalinkClient::builder(1_u64)
+        }
+    }
+
+    /// Builds a client with a node for guild 1 and a writer that acknowledges the messages
+    /// without a websocket, returning the messages written.
+    async fn client_with_fake_socket() -> (LavalinkClient, mpsc::UnboundedReceiver<String>) {
+        let client = test_builder()
+            .set_port(9)
+            .build(Handler)
+            .await
+            .unwrap();
+
+        let (sender, mut receiver) = mpsc::unbounded_channel::<(
+            TungsteniteMessage,
+            mpsc::UnboundedSender<LavalinkResult<()>>,
+        )>();
+        let (written_tx, written_rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Some((message, written)) = receiver.recv().await {
+                let _ = written_tx.send(message.to_string());
+                let _ = written.send(Ok(()));
+            }
+        });
+
+        let nodes = {
+            let client_lock = client.inner.lock();
+            *client_lock.socket_sender.write() = Some(SocketSender {
+                sender,
+                state: Arc::default(),
+            });
+            client_lock.nodes.clone()
+        };
+        nodes.insert(1, NodeOptions::default().into_node(GuildId(1)));
+
+        (client, written_rx)
+    }
+
+    /// Gets the last message written, as json.
+    fn last_written(written: &mut mpsc::UnboundedReceiver<String>) -> serde_json::Value {
+        let mut last = None;
+
+        while let Ok(message) = written.try_recv() {
+            last = Some(message);
+        }
+
+        serde_json::from_str(&last.expect("nothing was written")).unwrap()
+    }
+
+    /// Hands the server end of an in-memory stream to the test on every connection.
+    struct DuplexConnector(mpsc::UnboundedSender<tokio::io::DuplexStream>);
+
+    #[async_trait]
+    impl connector::Connector for DuplexConnector {
+        async fn connect(
+            &self,
+            _host: &str,
+            _port: u16,
+        ) -> std::io::Result<Box<dyn connector::Transport>> {
+            let (client, server) = tokio::io::duplex(64 * 1024);
+            let _ = self.0.send(server);
+
+            Ok(Box::new(async_tungstenite::tokio::TokioAdapter::new(
+                client,
+            )))
+        }
+    }
+
+    #[tokio::test]
+    async fn connector_runs_the_websocket_without_a_network() {
+        let (connections_tx, mut connections) = mpsc::unbounded_channel();
+        let client = test_builder()
+            .set_connector(DuplexConnector(connections_tx))
+            .build(Handler)
+            .await
+            .unwrap();
+
+        let stream = tokio::time::timeout(Duration::from_secs(5), connections.recv())
+            .await
+            .expect("the client didn't connect")
+            .unwrap();
+        let mut server = async_tungstenite::tokio::accept_async(stream)
+            .await
+            .unwrap();
+
+        let nodes = client.inner.lock().nodes.clone();
+        nodes.insert(1, NodeOptions::default().into_node(GuildId(1)));
+
+        server
+            .send(TungsteniteMessage::text(
+                r#"{"op":"ready","resumed":false,"sessionId":"session"}"#,
+            ))
+            .await
+            .unwrap();
+        server
+            .send(TungsteniteMessage::text(
+                r#"{"op":"playerUpdate","guildId":"1","state":{"time":1,"position":2000,"connected":true}}"#,
+            ))
+            .await
+            .unwrap();
+
+        // The events are handled by the event loop, which set the writer up before reading.
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while client.player_state(1_u64).is_none() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("the player update wasn't handled");
+
+        assert_eq!(client.session_id().as_deref(), Some("session"));
+        assert_eq!(
+            client.player_state(1_u64).map(|state| state.position),
+            Some(2000)
+        );
+
+        client.stop(1_u64).await.unwrap();
+
+        let stop = loop {
+            let message = tokio::time::timeout(Duration::from_secs(5), server.next())
+                .await
+                .expect("the client didn't write")
+                .unwrap()
+                .unwrap();
+
+            if let TungsteniteMessage::Text(text) = message {
+                break serde_json::from_str::<serde_json::Value>(&text).unwrap();
+            }
+        };
+
+        assert_eq!(stop, serde_json::json!({ "op": "stop", "guildId": "1" }));
+    }
+
+    #[tokio::test]
+    async fn filters_keep_the_equalizer() {
+        let (client, mut written) = client_with_fake_socket().await;
+
+        client.equalize_preset(1_u64, EqPreset::Bass).await.unwrap();
+        client
+            .set_karaoke(1_u64, Some(1.0), None, None, None)
+            .await
+            .unwrap();
+
+        let payload = last_written(&mut written);
+        let expected = Bands::from_gains(EqPreset::Bass.gains()).unwrap();
+
+        assert_eq!(payload["op"], "filters");
+        assert_eq!(payload["karaoke"], serde_json::json!({ "level": 1.0 }));
+        assert_eq!(
+            payload["equalizer"],
+            serde_json::to_value(&expected).unwrap()
+        );
+        assert_eq!(client.eq_preset(1_u64), Some(EqPreset::Bass));
+
+        client
+            .equalize_band(1_u64, Band { band: 0, gain: 0.5 })
+            .await
+            .unwrap();
+
+        let payload = last_written(&mut written);
+
+        assert_eq!(
+            payload["equalizer"][0],
+            serde_json::json!({ "band": 0, "gain": 0.5 })
+        );
+        assert_eq!(payload["equalizer"].as_array().unwrap().len(), 15);
+        assert!(payload["karaoke"].is_object());
+        assert_eq!(client.eq_preset(1_u64), None);
+    }
+
+    #[tokio::test]
+    async fn update_player_falls_back_to_the_opcodes_without_a_session() {
+        let (client, mut written) = client_with_fake_socket().await;
+
+        client
+            .update_player(1_u64)
+            .volume(50)
+            .paused(true)
+            .send()
+            .await
+            .unwrap();
+
+        let messages = std::iter::from_fn(|| written.try_recv().ok())
+            .map(|message| serde_json::from_str::<serde_json::Value>(&message).unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            messages,
+            vec![
+                serde_json::json!({ "op": "volume", "guildId": "1", "volume": 50 }),
+                serde_json::json!({ "op": "pause", "guildId": "1", "pause": true }),
+            ]
+        );
+
+        let nodes = client.inner.lock().nodes.clone();
+        let node = nodes.get(&1).unwrap();
+        assert_eq!(node.volume, 50);
+        assert!(node.is_paused);
+    }
+
+    #[test]
+    fn filters_are_supported_since_lavalink_3_4() {
+        let lavalink = |version: Option<&str>| ServerProtocol {
+            flavor: ServerFlavor::Lavalink,
+            version: version.map(ToString::to_string),
+            api_version: None,
+        };
+
+        assert!(!lavalink(None).supports_filters());
+        assert!(!lavalink(Some("3.3.2.5")).supports_filters());
+        assert!(lavalink(Some("3.4")).supports_filters());
+        assert!(lavalink(Some("3.7.8")).supports_filters());
+        assert!(lavalink(Some("4.0.0")).supports_filters());
+        assert!(lavalink(Some("a1b2c3-SNAPSHOT")).supports_filters());
+    }
+
+    #[tokio::test]
+    async fn equalizer_uses_its_own_op_before_lavalink_3_4() {
+        let (client, mut written) = client_with_fake_socket().await;
+        client.inner.lock().protocol = Some(ServerProtocol {
+            flavor: ServerFlavor::Lavalink,
+            version: Some("3.3.2.5".to_string()),
+            api_version: None,
+        });
+
+        client
+            .equalize_band(1_u64, Band { band: 2, gain: 0.5 })
+            .await
+            .unwrap();
+        client.equalize_reset(1_u64).await.unwrap();
+
+        let messages = std::iter::from_fn(|| written.try_recv().ok())
+            .map(|message| serde_json::from_str::<serde_json::Value>(&message).unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(messages.len(), 2);
+        assert_eq!(
+            messages[0],
+            serde_json::json!({
+                "op": "equalizer",
+                "guildId": "1",
+                "bands": [{ "band": 2, "gain": 0.5 }],
+            })
+        );
+        assert_eq!(messages[1]["op"], "equalizer");
+        assert_eq!(messages[1]["bands"].as_array().unwrap().len(), 15);
+    }
+
+    #[tokio::test]
+    async fn update_player_sets_the_playing_track() {
+        let (client, mut written) = client_with_fake_socket().await;
+        let track = |name: &str| Track {
+            track: name.to_string(),
+            info: None,
+        };
+        let now_playing = |client: &LavalinkClient| {
+            client
+                .with_node(1_u64, |node| {
+                    node.now_playing
+                        .as_ref()
+                        .map(|now_playing| now_playing.track.track.clone())
+                })
+                .unwrap()
+        };
+
+        client
+            .update_player(1_u64)
+            .track(track("first"))
+            .position(Duration::from_secs(2))
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(
+            next_play(&mut written).await,
+            ("1".to_string(), "first".to_string())
+        );
+        assert_eq!(now_playing(&client).as_deref(), Some("first"));
+        assert_eq!(
+            client
+                .with_node(1_u64, |node| node.now_playing.as_ref().unwrap().start_time)
+                .unwrap(),
+            2000
+        );
+
+        // A track that doesn't replace the playing one is ignored by lavalink.
+        client
+            .update_player(1_u64)
+            .track(track("second"))
+            .no_replace(true)
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(now_playing(&client).as_deref(), Some("first"));
+    }
+
+    /// Waits for the next `play` written, returning its guild and track.
+    async fn next_play(written: &mut mpsc::UnboundedReceiver<String>) -> (String, String) {
+        let play = async {
+            loop {
+                let message = written.recv().await.unwrap();
+                let payload = serde_json::from_str::<serde_json::Value>(&message).unwrap();
+
+                if payload["op"] == "play" {
+                    return (
+                        payload["guildId"].as_str().unwrap().to_string(),
+                        payload["track"].as_str().unwrap().to_string(),
+                    );
+                }
+            }
+        };
+
+        tokio::time::timeout(Duration::from_secs(5), play)
+            .await
+            .expect("no track was played")
+    }
+
+    #[tokio::test]
+    async fn queue_scheduler_skips_busy_guilds() {
+        let (client, mut written) = client_with_fake_socket().await;
+        let (nodes, loops, queue_notify) = {
+            let client_lock = client.inner.lock();
+            (
+                client_lock.nodes.clone(),
+                client_lock.loops.clone(),
+                client_lock.queue_notify.clone(),
+            )
+        };
+
+        let track = |name: &str| TrackQueue {
+            track: Track {
+                track: name.to_string(),
+                info: None,
+            },
+            ..TrackQueue::default()
+        };
+
+        nodes.insert(2, NodeOptions::default().into_node(GuildId(2)));
+        nodes.get_mut(&1).unwrap().queue = vec![track("busy")];
+        nodes.get_mut(&2).unwrap().queue = vec![track("first"), track("second")];
+
+        // Guild 1 stays busy with another operation for the whole test.
+        let busy_lock = client.guild_lock(1_u64);
+        let _busy = busy_lock.lock().await;
+
+        loops.insert(1);
+        loops.insert(2);
+        queue_notify.notify_one();
+
+        assert_eq!(
+            next_play(&mut written).await,
+            ("2".to_string(), "first".to_string())
+        );
+
+        {
+            let mut node = nodes.get_mut(&2).unwrap();
+            node.queue.remove(0);
+            node.now_playing = None;
+        }
+        queue_notify.notify_one();
+
+        assert_eq!(
+            next_play(&mut written).await,
+            ("2".to_string(), "second".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn skip_to_keeps_the_playing_track_on_index_0() {
+        let (client, mut written) = client_with_fake_socket().await;
+        let track = |name: &str| TrackQueue {
+            track: Track {
+                track: name.to_string(),
+                info: None,
+            },
+            ..TrackQueue::default()
+        };
+
+        client
+            .with_node(1_u64, |node| {
+                node.queue = vec![track("first"), track("second"), track("third")];
+                node.now_playing = Some(track("first"));
+            })
+            .unwrap();
+        let player = client.get_player_context(1_u64).unwrap();
+
+        player.skip_to(0).await.unwrap();
+        assert!(written.try_recv().is_err());
+        assert_eq!(player.queue_len(), 3);
+
+        player.skip_to(2).await.unwrap();
+        assert_eq!(
+            next_play(&mut written).await,
+            ("1".to_string(), "third".to_string())
+        );
+        assert_eq!(player.queue_len(), 1);
+
+        assert!(player.skip_to(1).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn timescale_setters_keep_the_other_values() {
+        let (client, mut written) = client_with_fake_socket().await;
+
+        client.equalize_preset(1_u64, EqPreset::Rock).await.unwrap();
+        client.set_speed(1_u64, 1.5).await.unwrap();
+        client.set_pitch(1_u64, 1.2).await.unwrap();
+
+        let payload = last_written(&mut written);
+
+        assert_eq!(
+            payload["timescale"],
+            serde_json::json!({ "speed": 1.5, "pitch": 1.2 })
+        );
+        assert_eq!(payload["equalizer"].as_array().unwrap().len(), 15);
+        assert_eq!(client.eq_preset(1_u64), Some(EqPreset::Rock));
+    }
+
+    #[tokio::test]
+    async fn destroy_forgets_the_guild_lock_and_event_sequence() {
+        let (client, _written) = client_with_fake_socket().await;
+        let (guild_locks, event_sequences) = {
+            let inner = client.inner.lock();
+            (inner.guild_locks.clone(), inner.event_sequences.clone())
+        };
+
+        event_sequences.insert(GuildId(1), 3);
+        client.destroy(1_u64).await.unwrap();
+
+        assert!(!guild_locks.contains_key(&1));
+        assert!(!event_sequences.contains_key(&GuildId(1)));
+
+        // An operation holding the lock keeps it in the map.
+        let guild_lock = client.guild_lock(1_u64);
+        client.destroy(1_u64).await.unwrap();
+
+        assert!(Arc::ptr_eq(&guild_lock, &client.guild_lock(1_u64)));
+    }
+
+    #[tokio::test]
+    async fn hosts_are_normalized_at_runtime() {
+        let client = test_builder()
+            .set_port(9)
+            .build(Handler)
+            .await
+            .unwrap();
+
+        client.add_host("http://node2/").unwrap();
+        client.add_host("node2").unwrap();
+        client.add_host("[::1]").unwrap();
+        assert!(client.add_host("node3/path").is_err());
+
+        assert_eq!(
+            client.inner.lock().hosts,
+            vec!["localhost".to_string(), "node2".to_string(), "::1".to_string()]
+        );
+
+        assert!(client.remove_host("ws://[::1]").await.unwrap());
+        assert!(!client.remove_host("node3").await.unwrap());
+        assert!(client.remove_host("node2/path").await.is_err());
+
+        // Without a websocket to close, removing the current host only switches to the next one.
+        assert!(client.remove_host("localhost").await.unwrap());
+        assert_eq!(client.current_host(), "node2");
+        assert!(client.remove_host("node2").await.is_err());
+    }
+
+    #[test]
+    fn tracks_with_limit_skip_the_extra_tracks() {
+        let tracks = Tracks {
+            playlist_info: None,
+            load_type: "SEARCH_RESULT".to_string(),
+            tracks: (0..5)
+                .map(|idx| Track {
+                    track: format!("track-{}", idx),
+                    info: Some(Info::default()),
+                })
+                .collect(),
+            exception: None,
+        };
+        let json = serde_json::to_vec(&tracks).unwrap();
+
+        let limited = Tracks::from_json_with_limit(&json, 2).unwrap();
+
+        assert_eq!(limited.load_type, "SEARCH_RESULT");
+        assert_eq!(limited.tracks, tracks.tracks[..2]);
+        assert_eq!(Tracks::from_json_with_limit(&json, 10).unwrap(), tracks);
+
+        // The skipped tracks still have to be valid json.
+        let invalid = br#"{"loadType":"SEARCH_RESULT","tracks":[{"track":"a"},{"track":]}"#;
+        assert!(Tracks::from_json_with_limit(invalid, 1).is_err());
+    }
+
+    #[test]
+    fn decode_cache_evicts_the_least_recently_used() {
+        let mut cache = DecodeCache::new(2, None);
+        let info = |title: &str| Info {
+            title: title.to_string(),
+            ..Info::default()
+        };
+
+        cache.insert("first".to_string(), info("first"));
+        cache.insert("second".to_string(), info("second"));
+
+        // Using the first track makes the second one the least recently used.
+        assert_eq!(cache.get("first"), Some(info("first")));
+        cache.insert("third".to_string(), info("third"));
+
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get("second"), None);
+        assert_eq!(cache.get("first"), Some(info("first")));
+        assert_eq!(cache.get("third"), Some(info("third")));
+
+        // Replacing a cached track doesn't evict another one.
+        cache.insert("first".to_string(), info("replaced"));
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get("third"), Some(info("third")));
+        assert_eq!(cache.get("first"), Some(info("replaced")));
+
+        assert_eq!((cache.hits, cache.misses), (5, 1));
+    }
+
+    #[test]
+    fn load_cache_evicts_the_oldest_results() {
+        let load_cache = DashMap::new();
+        let ttl = Duration::from_secs(60);
+
+        for identifier in &["first", "second", "third"] {
+            cache_load(
+                &load_cache,
+                identifier.to_string(),
+                Tracks::default(),
+                ttl,
+                2,
+            );
+            // Keeps the load times apart, for the oldest one to be the first.
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        assert_eq!(load_cache.len(), 2);
+        assert!(!load_cache.contains_key("first"));
+        assert!(load_cache.contains_key("second"));
+        assert!(load_cache.contains_key("third"));
+
+        // Loading a cached identifier again replaces it instead of evicting another one.
+        cache_load(&load_cache, "third".to_string(), Tracks::default(), ttl, 2);
+        assert!(load_cache.contains_key("second"));
+
+        cache_load(&load_cache, "fourth".to_string(), Tracks::default(), ttl, 0);
+        assert!(!load_cache.contains_key("fourth"));
+    }
+
+    #[test]
+    fn event_ids_parse_from_strings_and_numbers() {
+        let player_update = |guild_id: &str| {
+            serde_json::from_str::<PlayerUpdate>(&format!(
+                r#"{{"op":"playerUpdate","guildId":{},"state":{{"time":1,"position":2,"connected":true}}}}"#,
+                guild_id
+            ))
+        };
+
+        assert_eq!(player_update(r#""12""#).unwrap().guild_id, GuildId(12));
+        assert_eq!(player_update("12").unwrap().guild_id, GuildId(12));
+        assert!(player_update(r#""guild""#).is_err());
+
+        let event =
+            serde_json::from_str::<PayloadHeader>(r#"{"op":"event","type":"TrackStartEvent"}"#)
+                .unwrap();
+
+        assert_eq!(event.op, "event");
+        assert_eq!(event.event_type.as_deref(), Some("TrackStartEvent"));
+    }
+
+    /// Runs `operation` many times while another thread locks the node of guild 1 and then the
+    /// client, like a `with_node()` closure calling the client. Locking the client and then the
+    /// nodes anywhere in `operation` deadlocks this.
+    fn assert_no_deadlock_with_node_users(
+        operation: impl Fn(LavalinkClient) -> BoxFuture<'static, ()> + Send + 'static,
+    ) {
+        let (done_tx, done_rx) = std_mpsc::channel();
+
+        thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap();
+
+            runtime.block_on(async {
+                let (client, _written) = client_with_fake_socket().await;
+                let nodes = client.inner.lock().nodes.clone();
+
+                let stop = Arc::new(AtomicBool::new(false));
+                let node_user = {
+                    let client = client.clone();
+                    let stop = stop.clone();
+                    thread::spawn(move || {
+                        while !stop.load(AtomicOrdering::SeqCst) {
+                            let node = nodes.get_mut(&1).unwrap();
+                            // Holds the node long enough for the client mutex to be taken.
+                            thread::sleep(Duration::from_micros(50));
+                            let _ = client.is_shutdown();
+                            drop(node);
+                            thread::sleep(Duration::from_micros(50));
+                        }
+                    })
+                };
+
+                for _ in 0..2_000 {
+                    operation(client.clone()).await;
+                }
+
+                stop.store(true, AtomicOrdering::SeqCst);
+                node_user.join().unwrap();
+            });
+
+            let _ = done_tx.send(());
+        });
+
+        // A deadlocked thread can't be joined, so it's left behind and the test fails instead.
+        assert!(
+            done_rx.recv_timeout(Duration::from_secs(30)).is_ok(),
+            "the operation deadlocked with the node users"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "discord-gateway")]
+    fn create_session_does_not_deadlock_with_node_users() {
+        let connection_info = ConnectionInfo {
+            guild_id: Some(GuildId(1)),
+            channel_id: Some(ChannelId(2)),
+            endpoint: Some("endpoint".to_string()),
+            token: Some("token".to_string()),
+            session_id: Some("session".to_string()),
+        };
+
+        assert_no_deadlock_with_node_users(move |client| {
+            let connection_info = connection_info.clone();
+
+            Box::pin(async move {
+                // The draining check is the one that looked at the nodes under the client mutex.
+                client.start_drain();
+                client.create_session(&connection_info).await.unwrap();
+            })
+        });
+    }
+
+    #[test]
+    fn skip_and_stop_do_not_deadlock_with_node_users() {
+        assert_no_deadlock_with_node_users(|client| {
+            Box::pin(async move {
+                drop(client.skip(1_u64).await);
+                client.stop(1_u64).await.unwrap();
+                client.stop_and_clear(1_u64).await.unwrap();
+            })
+        });
     }
 }
@@ -1,13 +1,25 @@
 // oh god, this module looks terrible
 
-use crate::error::{LavalinkResult, LavalinkError};
+use crate::error::{LavalinkError, LavalinkResult, QueueRejected};
 
+/// Text formatting of durations, progress bars and queues
+pub mod formatting;
+/// The payloads sent to the lavalink websocket
+pub mod outgoing;
+use outgoing::{NoData, PlayerPayload};
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt;
-use std::num::ParseIntError;
+use std::marker::PhantomData;
+use std::convert::TryFrom;
+use std::num::{NonZeroU64, ParseIntError, TryFromIntError};
 use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use tokio::sync::mpsc;
+use tokio::sync::{broadcast, broadcast::error::RecvError, mpsc};
 
 use typemap_rev::TypeMap;
 
@@ -18,9 +30,17 @@ use serenity_dep::model::id::{
 
 #[cfg(feature = "twilight")]
 use twilight_model::id::{
-    ChannelId as TwilightChannelId, GuildId as TwilightGuildId, UserId as TwilightUserId,
+    marker::{ChannelMarker, GuildMarker, UserMarker},
+    Id,
 };
 
+#[cfg(feature = "twilight")]
+type TwilightGuildId = Id<GuildMarker>;
+#[cfg(feature = "twilight")]
+type TwilightUserId = Id<UserMarker>;
+#[cfg(feature = "twilight")]
+type TwilightChannelId = Id<ChannelMarker>;
+
 #[cfg(feature = "songbird")]
 use songbird_dep::id::{
     ChannelId as SongbirdChannelId, GuildId as SongbirdGuildId, UserId as SongbirdUserId,
@@ -28,22 +48,10 @@ use songbird_dep::id::{
 
 use serde::{Deserialize, Serialize};
 use serde_aux::prelude::*;
-use serde_json::{json, Value};
+use serde_json::{value::RawValue, Value};
 
 use async_tungstenite::tungstenite::Message as TungsteniteMessage;
-use parking_lot::RwLock;
-
-fn merge(a: &mut Value, b: Value) {
-    match (a, b) {
-        (a @ &mut Value::Object(_), Value::Object(b)) => {
-            let a = a.as_object_mut().unwrap();
-            for (k, v) in b {
-                merge(a.entry(k).or_insert(Value::Null), v);
-            }
-        }
-        (a, b) => *a = b,
-    }
-}
+use parking_lot::{Mutex, RwLock};
 
 // thanks twilight for this :P
 /// The type of event that something is.
@@ -67,6 +75,8 @@ pub enum SendOpcode {
     VoiceUpdate(VoiceUpdate),
     /// Set the volume of a player.
     Volume(Volume),
+    /// Set the audio filters of a player.
+    Filters(Box<Filters>),
 }
 
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
@@ -77,6 +87,9 @@ pub struct Play {
     pub start_time: u64,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub end_time: Option<u64>,
+    /// The volume the track starts with.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub volume: Option<u16>,
 }
 
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
@@ -116,7 +129,7 @@ pub struct Pause {
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Equalizer {
-    pub bands: Vec<Band>,
+    pub bands: Bands,
 }
 
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
@@ -126,6 +139,322 @@ pub struct Band {
     pub gain: f64,
 }
 
+/// The audio filters of a player.
+///
+/// Every filter that is None is disabled. Sending the filters overrides all of them, including
+/// the equalizer, which the `equalize_*` methods of the client also send with the filters.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Filters {
+    /// Multiplier of the volume, from 0.0 to 5.0.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub volume: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub equalizer: Option<Bands>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub karaoke: Option<Karaoke>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timescale: Option<Timescale>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tremolo: Option<Tremolo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vibrato: Option<Vibrato>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rotation: Option<Rotation>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub distortion: Option<Distortion>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channel_mix: Option<ChannelMix>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub low_pass: Option<LowPass>,
+}
+
+/// Removes the vocals of a track, by cancelling the frequencies of the band.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Karaoke {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub level: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mono_level: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filter_band: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filter_width: Option<f64>,
+}
+
+/// Changes the speed, pitch and rate of a track. 1.0 is the default of each.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Timescale {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub speed: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pitch: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rate: Option<f64>,
+}
+
+impl Timescale {
+    /// A faster speed with a higher pitch.
+    #[must_use]
+    pub fn nightcore() -> Self {
+        Self {
+            speed: Some(1.2),
+            pitch: Some(1.2),
+            rate: Some(1.0),
+        }
+    }
+
+    /// A slower speed with a lower pitch.
+    #[must_use]
+    pub fn vaporwave() -> Self {
+        Self {
+            speed: Some(0.85),
+            pitch: Some(0.8),
+            rate: Some(1.0),
+        }
+    }
+}
+
+/// Oscillates the volume.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Tremolo {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frequency: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub depth: Option<f64>,
+}
+
+/// Oscillates the pitch.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Vibrato {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frequency: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub depth: Option<f64>,
+}
+
+/// Rotates the audio around the stereo channels, also known as audio panning.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Rotation {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rotation_hz: Option<f64>,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Distortion {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sin_offset: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sin_scale: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cos_offset: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cos_scale: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tan_offset: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tan_scale: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offset: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scale: Option<f64>,
+}
+
+/// Mixes the stereo channels, 1.0 to themselves and 0.0 to the other by default.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChannelMix {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub left_to_left: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub left_to_right: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub right_to_left: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub right_to_right: Option<f64>,
+}
+
+/// Suppresses the higher frequencies.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LowPass {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub smoothing: Option<f64>,
+}
+
+/// The equalizer presets, see the `EQ_*` constants.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub enum EqPreset {
+    Base,
+    Boost,
+    Metal,
+    Piano,
+    Jazz,
+    Pop,
+    Electronic,
+    Rock,
+    Bass,
+    Treble,
+}
+
+impl EqPreset {
+    /// Every preset.
+    pub const ALL: [EqPreset; 10] = [
+        Self::Base,
+        Self::Boost,
+        Self::Metal,
+        Self::Piano,
+        Self::Jazz,
+        Self::Pop,
+        Self::Electronic,
+        Self::Rock,
+        Self::Bass,
+        Self::Treble,
+    ];
+
+    /// The gains of the 15 bands of the preset.
+    #[must_use]
+    pub fn gains(self) -> [f64; 15] {
+        match self {
+            Self::Base => crate::EQ_BASE,
+            Self::Boost => crate::EQ_BOOST,
+            Self::Metal => crate::EQ_METAL,
+            Self::Piano => crate::EQ_PIANO,
+            Self::Jazz => crate::EQ_JAZZ,
+            Self::Pop => crate::EQ_POP,
+            Self::Electronic => crate::EQ_ELECTRONIC,
+            Self::Rock => crate::EQ_ROCK,
+            Self::Bass => crate::EQ_BASS,
+            Self::Treble => crate::EQ_TREBLE,
+        }
+    }
+
+    /// Interpolates the gains of the presets, weighting each one by the weight at the same index.
+    ///
+    /// Presets without a weight are ignored. If the weights add up to 0, the gains are all 0.
+    #[must_use]
+    pub fn blend(presets: &[EqPreset], weights: &[f64]) -> [f64; 15] {
+        let mut gains = [0.0; 15];
+        let total: f64 = weights.iter().take(presets.len()).sum();
+
+        if total == 0.0 {
+            return gains;
+        }
+
+        for (preset, weight) in presets.iter().zip(weights) {
+            for (gain, preset_gain) in gains.iter_mut().zip(preset.gains().iter()) {
+                *gain += preset_gain * weight / total;
+            }
+        }
+
+        gains
+    }
+}
+
+impl fmt::Display for EqPreset {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Self::Base => "Base",
+            Self::Boost => "Boost",
+            Self::Metal => "Metal",
+            Self::Piano => "Piano",
+            Self::Jazz => "Jazz",
+            Self::Pop => "Pop",
+            Self::Electronic => "Electronic",
+            Self::Rock => "Rock",
+            Self::Bass => "Bass",
+            Self::Treble => "Treble",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+/// A set of equalizer bands, validated to only contain valid band indices and gains.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(try_from = "Vec<Band>", into = "Vec<Band>")]
+pub struct Bands(Vec<Band>);
+
+impl Bands {
+    /// The number of bands the equalizer has, numbered from 0.
+    pub const COUNT: u8 = 15;
+    /// The lowest gain, which mutes the band.
+    pub const MIN_GAIN: f64 = -0.25;
+    /// The highest gain.
+    pub const MAX_GAIN: f64 = 1.0;
+
+    /// Validates the bands, returning an error with the first invalid one.
+    pub fn new(bands: impl IntoIterator<Item = Band>) -> LavalinkResult<Self> {
+        let bands = bands.into_iter().collect::<Vec<_>>();
+
+        if let Some(band) = bands.iter().find(|band| {
+            band.band >= Self::COUNT || !(Self::MIN_GAIN..=Self::MAX_GAIN).contains(&band.gain)
+        }) {
+            return Err(LavalinkError::InvalidBand(band.clone()));
+        }
+
+        Ok(Self(bands))
+    }
+
+    /// Clamps the gains to the valid range, and ignores the bands with an invalid index.
+    #[must_use]
+    pub fn clamped(bands: impl IntoIterator<Item = Band>) -> Self {
+        Self(
+            bands
+                .into_iter()
+                .filter(|band| band.band < Self::COUNT)
+                .map(|band| Band {
+                    band: band.band,
+                    gain: band.gain.clamp(Self::MIN_GAIN, Self::MAX_GAIN),
+                })
+                .collect(),
+        )
+    }
+
+    /// Validates the gains of every band, in order.
+    pub fn from_gains(gains: [f64; 15]) -> LavalinkResult<Self> {
+        Self::new(gains.iter().enumerate().map(|(index, gain)| Band {
+            band: index as u8,
+            gain: *gain,
+        }))
+    }
+
+    #[must_use]
+    pub fn as_slice(&self) -> &[Band] {
+        &self.0
+    }
+
+    /// Sets the gains of `bands`, keeping the gains of the other bands.
+    pub fn merge(&mut self, bands: Bands) {
+        for band in bands.0 {
+            match self.0.iter_mut().find(|current| current.band == band.band) {
+                Some(current) => current.gain = band.gain,
+                None => self.0.push(band),
+            }
+        }
+    }
+}
+
+impl TryFrom<Vec<Band>> for Bands {
+    type Error = LavalinkError;
+
+    fn try_from(bands: Vec<Band>) -> LavalinkResult<Self> {
+        Self::new(bands)
+    }
+}
+
+impl From<Bands> for Vec<Band> {
+    fn from(bands: Bands) -> Self {
+        bands.0
+    }
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Default, Hash, Serialize, Deserialize)]
 pub struct GuildId(pub u64);
 
@@ -145,7 +474,7 @@ impl From<SerenityGuildId> for GuildId {
 #[cfg(feature = "twilight")]
 impl From<TwilightGuildId> for GuildId {
     fn from(guild_id: TwilightGuildId) -> GuildId {
-        GuildId(guild_id.0)
+        GuildId(guild_id.get())
     }
 }
 
@@ -178,7 +507,7 @@ impl From<SerenityUserId> for UserId {
 #[cfg(feature = "twilight")]
 impl From<TwilightUserId> for UserId {
     fn from(user_id: TwilightUserId) -> UserId {
-        UserId(user_id.0)
+        UserId(user_id.get())
     }
 }
 
@@ -211,7 +540,7 @@ impl From<SerenityChannelId> for ChannelId {
 #[cfg(feature = "twilight")]
 impl From<TwilightChannelId> for ChannelId {
     fn from(channel_id: TwilightChannelId) -> ChannelId {
-        ChannelId(channel_id.0)
+        ChannelId(channel_id.get())
     }
 }
 
@@ -245,6 +574,7 @@ impl fmt::Display for UserId {
         write!(f, "{}", self.0)
     }
 }
+
 impl fmt::Display for GuildId {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}", self.0)
@@ -275,6 +605,65 @@ impl FromStr for GuildId {
     }
 }
 
+/// Implements the conversions from an id to the library ids, and between an id and `NonZeroU64`.
+macro_rules! id_conversions {
+    ($id:ident, $serenity:ident, $twilight:ident, $songbird:ident) => {
+        impl From<$id> for u64 {
+            fn from(id: $id) -> u64 {
+                id.0
+            }
+        }
+
+        impl From<NonZeroU64> for $id {
+            fn from(id: NonZeroU64) -> $id {
+                $id(id.get())
+            }
+        }
+
+        /// Fails if the id is 0, which discord never uses.
+        impl TryFrom<$id> for NonZeroU64 {
+            type Error = TryFromIntError;
+
+            fn try_from(id: $id) -> Result<Self, Self::Error> {
+                NonZeroU64::try_from(id.0)
+            }
+        }
+
+        #[cfg(feature = "serenity")]
+        impl From<$id> for $serenity {
+            fn from(id: $id) -> $serenity {
+                $serenity(id.0)
+            }
+        }
+
+        /// Fails if the id is 0, which twilight doesn't allow.
+        #[cfg(feature = "twilight")]
+        impl TryFrom<$id> for $twilight {
+            type Error = TryFromIntError;
+
+            fn try_from(id: $id) -> Result<Self, Self::Error> {
+                NonZeroU64::try_from(id.0).map($twilight::from)
+            }
+        }
+
+        #[cfg(feature = "songbird")]
+        impl From<$id> for $songbird {
+            fn from(id: $id) -> $songbird {
+                $songbird(id.0)
+            }
+        }
+    };
+}
+
+id_conversions!(GuildId, SerenityGuildId, TwilightGuildId, SongbirdGuildId);
+id_conversions!(UserId, SerenityUserId, TwilightUserId, SongbirdUserId);
+id_conversions!(
+    ChannelId,
+    SerenityChannelId,
+    TwilightChannelId,
+    SongbirdChannelId
+);
+
 impl GuildId {
     #[inline]
     #[cfg(feature = "serenity")]
@@ -283,11 +672,14 @@ impl GuildId {
         SerenityGuildId(self.0)
     }
 
+    /// Converts the id to the twilight one.
+    ///
+    /// Panics if the id is 0, which discord never uses.
     #[inline]
     #[cfg(feature = "twilight")]
     #[must_use]
     pub fn to_twilight(&self) -> TwilightGuildId {
-        TwilightGuildId(self.0)
+        TwilightGuildId::new(self.0)
     }
 
     #[inline]
@@ -311,11 +703,14 @@ impl UserId {
         SerenityUserId(self.0)
     }
 
+    /// Converts the id to the twilight one.
+    ///
+    /// Panics if the id is 0, which discord never uses.
     #[inline]
     #[cfg(feature = "twilight")]
     #[must_use]
     pub fn to_twilight(&self) -> TwilightUserId {
-        TwilightUserId(self.0)
+        TwilightUserId::new(self.0)
     }
 
     #[inline]
@@ -339,11 +734,14 @@ impl ChannelId {
         SerenityChannelId(self.0)
     }
 
+    /// Converts the id to the twilight one.
+    ///
+    /// Panics if the id is 0, which discord never uses.
     #[inline]
     #[cfg(feature = "twilight")]
     #[must_use]
     pub fn to_twilight(&self) -> TwilightChannelId {
-        TwilightChannelId(self.0)
+        TwilightChannelId::new(self.0)
     }
 
     #[inline]
@@ -359,85 +757,139 @@ impl ChannelId {
     }
 }
 
-impl SendOpcode {
-    pub async fn send(
-        &self,
-        guild_id: impl Into<GuildId>,
-        socket: tokio::sync::mpsc::UnboundedSender<(TungsteniteMessage, mpsc::UnboundedSender<()>)>,
-    ) -> LavalinkResult<()> {
-        let value = match self {
-            Self::Destroy | Self::Stop => {
-                json!({
-                    "op" : self,
-                    "guildId" : &guild_id.into().0.to_string()
-                })
-            }
-            Self::Seek(data) => {
-                let mut x = json!({
-                    "op" : "seek",
-                    "guildId" : &guild_id.into().0.to_string(),
-                });
-                merge(&mut x, serde_json::to_value(data).unwrap());
-                x
-            }
-            Self::Pause(data) => {
-                let mut x = json!({
-                    "op" : "pause",
-                    "guildId" : &guild_id.into().0.to_string(),
-                });
-                merge(&mut x, serde_json::to_value(data).unwrap());
-                x
-            }
-            Self::Play(data) => {
-                let mut x = json!({
-                    "op" : "play",
-                    "guildId" : &guild_id.into().0.to_string(),
-                });
-                merge(&mut x, serde_json::to_value(data).unwrap());
-                x
-            }
-            Self::VoiceUpdate(data) => {
-                let mut x = json!({
-                    "op" : "voiceUpdate",
-                    "guildId" : &guild_id.into().0.to_string(),
-                });
-                merge(&mut x, serde_json::to_value(data).unwrap());
-                x
-            }
-            Self::Volume(data) => {
-                let mut x = json!({
-                    "op" : "volume",
-                    "guildId" : &guild_id.into().0.to_string(),
-                });
-                merge(&mut x, serde_json::to_value(data).unwrap());
-                x
-            }
-            Self::Equalizer(data) => {
-                let mut x = json!({
-                    "op" : "equalizer",
-                    "guildId" : &guild_id.into().0.to_string(),
-                });
-                merge(&mut x, serde_json::to_value(data).unwrap());
-                x
-            }
-        };
+/// State of the task that writes the messages to the lavalink websocket.
+#[derive(Debug, Default)]
+pub struct WriterState {
+    pub alive: AtomicBool,
+    /// Messages sent to the writer that have not been written yet.
+    pub queue_depth: AtomicUsize,
+    pub last_write: Mutex<Option<Instant>>,
+}
+
+impl WriterState {
+    /// Takes a snapshot of the current state.
+    #[must_use]
+    pub fn diagnostics(&self) -> WriterDiagnostics {
+        WriterDiagnostics {
+            writer_alive: self.alive.load(Ordering::SeqCst),
+            queue_depth: self.queue_depth.load(Ordering::SeqCst),
+            since_last_write: self.last_write.lock().map(|i| i.elapsed()),
+        }
+    }
+}
+
+/// Snapshot of the websocket writer state, returned with the errors from sending messages to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WriterDiagnostics {
+    /// If the writer task is still running.
+    pub writer_alive: bool,
+    /// Messages sent to the writer that have not been written yet.
+    pub queue_depth: usize,
+    /// Time since the last message was written successfully, if any was.
+    pub since_last_write: Option<Duration>,
+}
+
+impl fmt::Display for WriterDiagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "writer alive: {}, queue depth: {}, last write: ",
+            self.writer_alive, self.queue_depth
+        )?;
+
+        match self.since_last_write {
+            Some(elapsed) => write!(f, "{:?} ago", elapsed),
+            None => write!(f, "never"),
+        }
+    }
+}
+
+/// The sender half of the channel to the lavalink websocket writer.
+///
+/// Every message is sent along with a sender, used by the writer to acknowledge it was written,
+/// or to report why it couldn't be.
+#[derive(Debug, Clone)]
+pub struct SocketSender {
+    pub sender: mpsc::UnboundedSender<(
+        TungsteniteMessage,
+        mpsc::UnboundedSender<LavalinkResult<()>>,
+    )>,
+    pub state: Arc<WriterState>,
+}
 
-        let payload = serde_json::to_string(&value).unwrap();
+impl SocketSender {
+    /// Sends the message to the writer, waiting until it has been written.
+    ///
+    /// Returns `LavalinkError::ErrorWebsocketPayload` if the websocket failed to write it.
+    pub async fn send(&self, message: TungsteniteMessage) -> LavalinkResult<()> {
         let (tx, mut rx) = mpsc::unbounded_channel();
 
-        {
-            if let Err(why) = socket.send((TungsteniteMessage::text(&payload), tx)) {
-                return Err(why.into());
-            };
+        self.state.queue_depth.fetch_add(1, Ordering::SeqCst);
+
+        if self.sender.send((message, tx)).is_err() {
+            self.state.queue_depth.fetch_sub(1, Ordering::SeqCst);
+
+            return Err(LavalinkError::WriterChannelClosed(Box::new(
+                self.state.diagnostics(),
+            )));
         }
 
-        rx.recv().await.ok_or(LavalinkError::ChannelSendError)?;
+        rx.recv().await.unwrap_or_else(|| {
+            Err(LavalinkError::WriterChannelClosed(Box::new(
+                self.state.diagnostics(),
+            )))
+        })
+    }
+}
 
-        Ok(())
+impl SendOpcode {
+    /// The name of the opcode, as sent to lavalink.
+    #[must_use]
+    pub fn op(&self) -> &'static str {
+        match self {
+            Self::Destroy => "destroy",
+            Self::Equalizer(_) => "equalizer",
+            Self::Pause(_) => "pause",
+            Self::Play(_) => "play",
+            Self::Seek(_) => "seek",
+            Self::Stop => "stop",
+            Self::VoiceUpdate(_) => "voiceUpdate",
+            Self::Volume(_) => "volume",
+            Self::Filters(_) => "filters",
+        }
+    }
+
+    /// Serializes the opcode for the guild to the JSON sent to lavalink.
+    #[must_use]
+    pub fn to_json(&self, guild_id: impl Into<GuildId>) -> String {
+        let guild_id = guild_id.into();
+        let op = self.op();
+
+        match self {
+            Self::Destroy | Self::Stop => PlayerPayload::new(op, guild_id, &NoData {}).to_json(),
+            Self::Equalizer(data) => PlayerPayload::new(op, guild_id, data).to_json(),
+            Self::Pause(data) => PlayerPayload::new(op, guild_id, data).to_json(),
+            Self::Play(data) => PlayerPayload::new(op, guild_id, data).to_json(),
+            Self::Seek(data) => PlayerPayload::new(op, guild_id, data).to_json(),
+            Self::VoiceUpdate(data) => PlayerPayload::new(op, guild_id, data).to_json(),
+            Self::Volume(data) => PlayerPayload::new(op, guild_id, data).to_json(),
+            Self::Filters(data) => PlayerPayload::new(op, guild_id, data).to_json(),
+        }
+    }
+
+    pub async fn send(
+        &self,
+        guild_id: impl Into<GuildId>,
+        socket: SocketSender,
+    ) -> LavalinkResult<()> {
+        let payload = self.to_json(guild_id);
+
+        socket.send(TungsteniteMessage::text(payload)).await
     }
 }
 
 #[derive(Clone, Serialize, Deserialize)]
+#[allow(clippy::struct_excessive_bools)]
 pub struct Node {
     pub guild: GuildId,
 
@@ -447,6 +899,31 @@ pub struct Node {
     pub queue: Vec<TrackQueue>,
     /// Check used to know if the loop is on Client.loops
     pub is_on_loops: bool,
+    /// The identifier of the last track that was retried after a `COMMON` exception, until a
+    /// different track starts.
+    pub retried_track: Option<String>,
+    /// If finished tracks should be added back to the queue once it's drained.
+    pub repeat_queue: bool,
+    /// The tracks that finished playing, to be added back to the queue if `repeat_queue` is
+    /// enabled.
+    pub looped_tracks: Vec<TrackQueue>,
+    /// The last voice update sent to lavalink, used to resume the voice connection.
+    pub voice_update: Option<VoiceUpdate>,
+    /// The latest state received from the `playerUpdate` event.
+    pub player_state: Option<PlayerUpdateState>,
+    /// The last filters sent to lavalink.
+    pub filters: Option<Filters>,
+    /// If a recommended track should be queued when the queue finishes.
+    pub autoplay: bool,
+    /// If the queue loop starts the next track when nothing is playing. Disable it to stop at the
+    /// end of the current track.
+    pub auto_advance: bool,
+    /// The limits checked when a track is queued.
+    pub queue_policy: QueuePolicy,
+    /// Where new tracks are inserted on the queue.
+    pub queue_mode: QueueMode,
+    /// The equalizer preset applied with `equalize_preset()`.
+    pub eq_preset: Option<EqPreset>,
     /// Use this to store whatever information you wish that's guild specific, such as invocation
     /// channel id's, for example.
     #[serde(skip)]
@@ -462,61 +939,689 @@ impl Default for Node {
             volume: 100,
             queue: vec![],
             is_on_loops: false,
+            retried_track: None,
+            repeat_queue: false,
+            looped_tracks: vec![],
+            voice_update: None,
+            player_state: None,
+            filters: None,
+            autoplay: false,
+            auto_advance: true,
+            queue_policy: QueuePolicy::default(),
+            queue_mode: QueueMode::default(),
+            eq_preset: None,
             data: Arc::new(RwLock::new(TypeMap::new())),
         }
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Default, Deserialize, Serialize)]
-pub struct TrackQueue {
-    pub track: Track,
-    pub start_time: u64,
-    pub end_time: Option<u64>,
-    pub requester: Option<UserId>,
+/// The limits of the queue of a guild, checked by `PlayParameters::queue()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct QueuePolicy {
+    /// The maximum number of tracks on the queue, including the one playing.
+    pub max_length: Option<usize>,
+    /// The maximum duration of a track. Streams are not checked.
+    pub max_track_duration: Option<Duration>,
+    /// If tracks that are already on the queue are rejected, compared by their identifier.
+    pub reject_duplicates: bool,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Default)]
-pub struct Tracks {
-    #[serde(rename = "playlistInfo")]
-    pub playlist_info: Option<PlaylistInfo>,
+impl QueuePolicy {
+    /// Checks if the track can be added to the queue.
+    pub fn check(&self, queue: &[TrackQueue], track: &TrackQueue) -> Result<(), QueueRejected> {
+        if let Some(max_length) = self.max_length {
+            if queue.len() >= max_length {
+                return Err(QueueRejected::QueueFull(max_length));
+            }
+        }
 
-    /// This field will be an Enum in the future, but for now only `LOAD_FAILED` and `TRACK_LOADED`
-    /// are known if you need to handle them.
-    #[serde(rename = "loadType")]
-    pub load_type: String,
+        let info = track.track.info.as_ref();
 
-    #[serde(default = "Vec::new")]
-    pub tracks: Vec<Track>,
-}
+        if let (Some(max_duration), Some(info)) = (self.max_track_duration, info) {
+            if !info.is_stream && Duration::from_millis(info.length) > max_duration {
+                return Err(QueueRejected::TrackTooLong(max_duration));
+            }
+        }
 
-#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Default)]
-pub struct PlaylistInfo {
-    #[serde(rename = "selectedTrack")]
-    pub selected_track: Option<i64>,
+        if self.reject_duplicates {
+            let identifier = |track: &TrackQueue| {
+                track
+                    .track
+                    .info
+                    .as_ref()
+                    .map_or_else(|| track.track.track.clone(), |i| i.identifier.clone())
+            };
 
-    pub name: Option<String>,
+            let new_identifier = identifier(track);
+
+            if queue.iter().any(|queued| identifier(queued) == new_identifier) {
+                return Err(QueueRejected::Duplicate(new_identifier));
+            }
+        }
+
+        Ok(())
+    }
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Default)]
-pub struct Track {
-    pub track: String,
-    pub info: Option<Info>,
+/// The initial settings of the node of a guild, for `create_session_with_node()`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct NodeOptions {
+    /// The initial volume, from 0 to 1000. Lavalink's default of 100 is kept if None.
+    pub volume: Option<u16>,
+    /// If finished tracks should be added back to the queue once it's drained.
+    pub repeat_queue: bool,
+    /// If a recommended track should be queued when the queue finishes.
+    pub autoplay: bool,
+    /// The limits checked when a track is queued.
+    pub queue_policy: QueuePolicy,
+    /// Where new tracks are inserted on the queue.
+    pub queue_mode: QueueMode,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Default)]
-pub struct Info {
-    #[serde(rename = "isSeekable")]
-    pub is_seekable: bool,
+impl NodeOptions {
+    /// Creates the node of the guild with these settings.
+    #[must_use]
+    pub fn into_node(self, guild_id: GuildId) -> Node {
+        Node {
+            guild: guild_id,
+            volume: self.volume.map_or(100, |volume| volume.min(1000)),
+            repeat_queue: self.repeat_queue,
+            autoplay: self.autoplay,
+            queue_policy: self.queue_policy,
+            queue_mode: self.queue_mode,
+            ..Node::default()
+        }
+    }
+}
 
-    #[serde(rename = "isStream")]
-    pub is_stream: bool,
+/// What `destroy_with_options()` removes besides the player. Nothing by default, like
+/// `destroy()`, so the session can be created again without losing the queue.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DestroyOptions {
+    /// Clears the whole queue, instead of only the playing track.
+    pub clear_queue: bool,
+    /// Removes the guild from the running loops.
+    pub remove_loop: bool,
+    /// Removes the node of the guild, along with its queue and settings.
+    pub remove_node: bool,
+}
 
-    pub identifier: String,
-    pub author: String,
-    pub length: u64,
+impl DestroyOptions {
+    /// Removes everything, for when the guild is done playing.
+    #[must_use]
+    pub fn full() -> Self {
+        Self {
+            clear_queue: true,
+            remove_loop: true,
+            remove_node: true,
+        }
+    }
+}
+
+/// Where new tracks are inserted on the queue of a guild.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum QueueMode {
+    /// At the end of the queue.
+    Fifo,
+    /// Interleaved by requester, so every requester gets a track played in turns.
+    RoundRobin,
+}
+
+impl Default for QueueMode {
+    fn default() -> Self {
+        Self::Fifo
+    }
+}
+
+impl QueueMode {
+    /// Gets the index the track should be inserted at, ignoring the first `skip` tracks, like the
+    /// one currently playing.
+    #[must_use]
+    pub fn insert_position(self, queue: &[TrackQueue], track: &TrackQueue, skip: usize) -> usize {
+        match self {
+            Self::Fifo => queue.len(),
+            Self::RoundRobin => {
+                let skip = skip.min(queue.len());
+                let mut rounds = HashMap::new();
+
+                let new_round = queue[skip..]
+                    .iter()
+                    .filter(|queued| queued.requester == track.requester)
+                    .count();
+
+                let mut position = skip;
+
+                for (idx, queued) in queue.iter().enumerate().skip(skip) {
+                    let round = rounds.entry(queued.requester).or_insert(0_usize);
+
+                    if *round <= new_round {
+                        position = idx + 1;
+                    }
+
+                    *round += 1;
+                }
+
+                position
+            }
+        }
+    }
+}
+
+/// A least recently used cache of the decoded tracks, see `LavalinkClient::decode_track()`.
+#[derive(Debug, Default)]
+pub struct DecodeCache {
+    /// The decoded tracks, with when they were decoded and when they were last used.
+    entries: HashMap<String, (Instant, u64, Info)>,
+    /// The tracks by when they were last used, to find the least recently used one.
+    recency: BTreeMap<u64, String>,
+    /// The maximum number of tracks cached, disabled if 0.
+    pub capacity: usize,
+    /// How long a track stays cached, forever if None.
+    pub ttl: Option<Duration>,
+    pub hits: u64,
+    pub misses: u64,
+    uses: u64,
+}
+
+impl DecodeCache {
+    #[must_use]
+    pub fn new(capacity: usize, ttl: Option<Duration>) -> Self {
+        Self {
+            capacity,
+            ttl,
+            ..Self::default()
+        }
+    }
+
+    /// The number of tracks cached, including the expired ones not evicted yet.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Gets the cached information of the track, counting the hit or miss.
+    pub fn get(&mut self, track: &str) -> Option<Info> {
+        self.uses += 1;
+
+        let entry = if let Some(entry) = self.entries.get(track) {
+            entry
+        } else {
+            self.misses += 1;
+            return None;
+        };
+
+        if self.ttl.map_or(false, |ttl| entry.0.elapsed() >= ttl) {
+            self.remove(track);
+            self.misses += 1;
+            return None;
+        }
+
+        self.hits += 1;
+
+        let entry = self.entries.get_mut(track)?;
+        self.recency.remove(&entry.1);
+        entry.1 = self.uses;
+        self.recency.insert(self.uses, track.to_string());
+
+        Some(entry.2.clone())
+    }
+
+    /// Caches the information of the track, evicting the least recently used one if full.
+    pub fn insert(&mut self, track: String, info: Info) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        self.remove(&track);
+
+        if self.entries.len() >= self.capacity {
+            let oldest = self.recency.keys().next().copied();
+
+            if let Some(oldest) = oldest.and_then(|last_use| self.recency.remove(&last_use)) {
+                self.entries.remove(&oldest);
+            }
+        }
+
+        self.uses += 1;
+        self.recency.insert(self.uses, track.clone());
+        self.entries.insert(track, (Instant::now(), self.uses, info));
+    }
+
+    /// Removes the track from the cache.
+    fn remove(&mut self, track: &str) {
+        if let Some((_, last_use, _)) = self.entries.remove(track) {
+            self.recency.remove(&last_use);
+        }
+    }
+
+    /// The share of lookups that were cached, from 0.0 to 1.0.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn hit_rate(&self) -> f64 {
+        let total = self.hits + self.misses;
+
+        if total == 0 {
+            0.0
+        } else {
+            self.hits as f64 / total as f64
+        }
+    }
+}
+
+/// The metrics of the `DecodeCache`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct DecodeCacheStats {
+    pub len: usize,
+    pub capacity: usize,
+    pub hits: u64,
+    pub misses: u64,
+    pub hit_rate: f64,
+}
+
+impl From<&DecodeCache> for DecodeCacheStats {
+    fn from(cache: &DecodeCache) -> Self {
+        Self {
+            len: cache.len(),
+            capacity: cache.capacity,
+            hits: cache.hits,
+            misses: cache.misses,
+            hit_rate: cache.hit_rate(),
+        }
+    }
+}
+
+/// An owned copy of the state of a `Node`, safe to keep across awaits.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct NodeSnapshot {
+    pub guild: GuildId,
+    pub now_playing: Option<TrackQueue>,
+    pub is_paused: bool,
+    pub volume: u16,
+    pub queue: Vec<TrackQueue>,
+    pub is_on_loops: bool,
+    pub repeat_queue: bool,
+    pub player_state: Option<PlayerUpdateState>,
+    pub filters: Option<Filters>,
+    pub autoplay: bool,
+    pub auto_advance: bool,
+    pub queue_policy: QueuePolicy,
+    pub queue_mode: QueueMode,
+    pub eq_preset: Option<EqPreset>,
+}
+
+impl From<&Node> for NodeSnapshot {
+    fn from(node: &Node) -> Self {
+        Self {
+            guild: node.guild,
+            now_playing: node.now_playing.clone(),
+            is_paused: node.is_paused,
+            volume: node.volume,
+            queue: node.queue.clone(),
+            is_on_loops: node.is_on_loops,
+            repeat_queue: node.repeat_queue,
+            player_state: node.player_state,
+            filters: node.filters.clone(),
+            autoplay: node.autoplay,
+            auto_advance: node.auto_advance,
+            queue_policy: node.queue_policy,
+            queue_mode: node.queue_mode,
+            eq_preset: node.eq_preset,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Default, Deserialize, Serialize)]
+pub struct TrackQueue {
+    pub track: Track,
+    pub start_time: u64,
+    pub end_time: Option<u64>,
+    pub requester: Option<UserId>,
+    /// Arbitrary data attached to the track when it was queued, like the channel it was requested
+    /// from.
+    #[serde(default)]
+    pub user_data: Option<Value>,
+    /// The volume the track starts with.
+    #[serde(default)]
+    pub volume: Option<u16>,
+    /// The filters applied when the track starts.
+    #[serde(default)]
+    pub filters: Option<Filters>,
+}
+
+impl TrackQueue {
+    /// Deserializes the user data attached to the track.
+    ///
+    /// Returns None if there's no user data, or it's not of the requested type.
+    #[must_use]
+    pub fn user_data_as<T: serde::de::DeserializeOwned>(&self) -> Option<T> {
+        serde_json::from_value(self.user_data.clone()?).ok()
+    }
+
+    /// Gets the information of the track.
+    ///
+    /// With the `decode-tracks-locally` feature, the track is decoded if the information is
+    /// missing; otherwise, use `LavalinkClient::decode_track()` to get it.
+    #[must_use]
+    pub fn info(&self) -> Option<Info> {
+        #[cfg(feature = "decode-tracks-locally")]
+        if self.track.info.is_none() {
+            return crate::decoder::decode_track(&self.track.track);
+        }
+
+        self.track.info.clone()
+    }
+}
+
+/// The kind of audio server the client is connected to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ServerFlavor {
+    Lavalink,
+    Andesite,
+}
+
+/// The server flavor and version, as detected by `LavalinkClient::detect_protocol()`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServerProtocol {
+    pub flavor: ServerFlavor,
+    /// The version reported by the server, None if it's too old to report it.
+    pub version: Option<String>,
+    /// The version of the versioned REST API of lavalink, 4 if `/v4/info` answered and 3 if
+    /// `/v3/info` did. None for Andesite and lavalink before 3.7, which don't have one.
+    #[serde(default)]
+    pub api_version: Option<u64>,
+}
+
+impl ServerProtocol {
+    /// The major version of the server, if it was reported.
+    #[must_use]
+    pub fn major_version(&self) -> Option<u64> {
+        self.version.as_ref()?.split('.').next()?.trim().parse().ok()
+    }
+
+    /// Checks if the server has the `filters` op, added in lavalink 3.4.
+    ///
+    /// Older lavalink servers only have the `equalizer` op. A version that can't be parsed, like
+    /// the one of a development build, is assumed to be recent.
+    #[must_use]
+    pub fn supports_filters(&self) -> bool {
+        match (self.flavor, &self.version) {
+            (ServerFlavor::Andesite, _) => true,
+            // Lavalink only reports its version since 3.3.
+            (ServerFlavor::Lavalink, None) => false,
+            (ServerFlavor::Lavalink, Some(version)) => {
+                let mut parts = version.split('.').map(|part| part.trim().parse::<u64>());
+
+                match (parts.next(), parts.next()) {
+                    (Some(Ok(major)), Some(Ok(minor))) => (major, minor) >= (3, 4),
+                    _ => true,
+                }
+            }
+        }
+    }
+
+    /// Checks if the server speaks the v3 websocket protocol this client implements.
+    ///
+    /// Andesite and lavalink before v4 do, lavalink v4 and later don't. Only the REST session
+    /// endpoints follow `api_version`, the v4 websocket protocol isn't implemented.
+    #[must_use]
+    pub fn is_supported(&self) -> bool {
+        match self.flavor {
+            ServerFlavor::Andesite => true,
+            ServerFlavor::Lavalink => {
+                self.api_version.map_or(true, |version| version < 4)
+                    && self.major_version().map_or(true, |major| major < 4)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Default)]
+pub struct Tracks {
+    #[serde(rename = "playlistInfo")]
+    pub playlist_info: Option<PlaylistInfo>,
+
+    /// This field will be an Enum in the future, but for now only `LOAD_FAILED` and `TRACK_LOADED`
+    /// are known if you need to handle them.
+    #[serde(rename = "loadType")]
+    pub load_type: String,
+
+    #[serde(default = "Vec::new")]
+    pub tracks: Vec<Track>,
+
+    /// Why the tracks failed to load, present when `load_type` is `LOAD_FAILED`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exception: Option<Exception>,
+}
+
+/// A `loadtracks` response with the tracks left as raw json, see `Tracks::from_json_with_limit()`.
+#[derive(Deserialize)]
+struct RawTracks<'a> {
+    #[serde(rename = "playlistInfo")]
+    playlist_info: Option<PlaylistInfo>,
+    #[serde(rename = "loadType")]
+    load_type: String,
+    #[serde(default, borrow)]
+    tracks: Vec<&'a RawValue>,
+    #[serde(default)]
+    exception: Option<Exception>,
+}
+
+impl Tracks {
+    /// Parses a `loadtracks` response, deserializing only the first `limit` tracks.
+    ///
+    /// The other tracks are only checked to be valid json, without allocating them.
+    pub fn from_json_with_limit(json: &[u8], limit: usize) -> serde_json::Result<Self> {
+        let raw = serde_json::from_slice::<RawTracks>(json)?;
+
+        Ok(Self {
+            playlist_info: raw.playlist_info,
+            load_type: raw.load_type,
+            tracks: raw
+                .tracks
+                .into_iter()
+                .take(limit)
+                .map(|track| serde_json::from_str(track.get()))
+                .collect::<serde_json::Result<_>>()?,
+            exception: raw.exception,
+        })
+    }
+
+    /// The result of the load, typed by `load_type`.
+    #[must_use]
+    pub fn data(&self) -> TrackLoadData {
+        match self.load_type.as_str() {
+            "TRACK_LOADED" => self
+                .tracks
+                .first()
+                .cloned()
+                .map_or(TrackLoadData::Empty, TrackLoadData::Track),
+            "PLAYLIST_LOADED" => TrackLoadData::Playlist(
+                self.playlist_info.clone().unwrap_or_default(),
+                self.tracks.clone(),
+            ),
+            "SEARCH_RESULT" => TrackLoadData::Search(self.tracks.clone()),
+            "LOAD_FAILED" => self
+                .exception
+                .clone()
+                .map_or(TrackLoadData::Empty, TrackLoadData::Error),
+            _ => TrackLoadData::Empty,
+        }
+    }
+
+    /// The tracks of the page, counting from 0, with `per_page` tracks per page.
+    ///
+    /// Empty if the page is past the last one.
+    #[must_use]
+    pub fn page(&self, page: usize, per_page: usize) -> &[Track] {
+        let start = page.saturating_mul(per_page).min(self.tracks.len());
+        let end = start.saturating_add(per_page).min(self.tracks.len());
+
+        &self.tracks[start..end]
+    }
+
+    /// How many pages there are with `per_page` tracks per page.
+    #[must_use]
+    pub fn page_count(&self, per_page: usize) -> usize {
+        if per_page == 0 {
+            return 0;
+        }
+
+        (self.tracks.len() + per_page - 1) / per_page
+    }
+
+    /// The sum of the length of every track, ignoring streams.
+    #[must_use]
+    pub fn total_duration(&self) -> Duration {
+        Duration::from_millis(
+            self.tracks
+                .iter()
+                .filter_map(|track| track.info.as_ref())
+                .filter(|info| !info.is_stream)
+                .map(|info| info.length)
+                .sum(),
+        )
+    }
+
+    /// The source the tracks were loaded from, like `youtube` or `soundcloud`.
+    #[must_use]
+    pub fn source(&self) -> Option<&str> {
+        self.tracks
+            .iter()
+            .find_map(|track| track.info.as_ref()?.source_name.as_deref())
+    }
+
+    /// The selected track of the playlist, if any.
+    #[must_use]
+    pub fn selected_track(&self) -> Option<&Track> {
+        let index = self.playlist_info.as_ref()?.selected_track?;
+        self.tracks.get(usize::try_from(index).ok()?)
+    }
+}
+
+/// The typed result of loading tracks.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TrackLoadData {
+    /// A single track was loaded from an URL.
+    Track(Track),
+    /// A playlist was loaded, with every track in it.
+    Playlist(PlaylistInfo, Vec<Track>),
+    /// A search returned these tracks.
+    Search(Vec<Track>),
+    /// Nothing matched the query.
+    Empty,
+    /// The tracks failed to load.
+    Error(Exception),
+}
+
+/// The search engines supported by lavalink and its common source plugins.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SearchEngines {
+    YouTube,
+    YouTubeMusic,
+    SoundCloud,
+    /// Requires the `LavaSrc` plugin.
+    Spotify,
+    /// Requires the `LavaSrc` plugin.
+    AppleMusic,
+    /// Requires the `LavaSrc` plugin.
+    Deezer,
+    /// Requires the `LavaSrc` plugin.
+    YandexMusic,
+}
+
+impl SearchEngines {
+    /// The identifier prefix lavalink uses for this search engine.
+    #[must_use]
+    pub fn prefix(self) -> &'static str {
+        match self {
+            Self::YouTube => "ytsearch",
+            Self::YouTubeMusic => "ytmsearch",
+            Self::SoundCloud => "scsearch",
+            Self::Spotify => "spsearch",
+            Self::AppleMusic => "amsearch",
+            Self::Deezer => "dzsearch",
+            Self::YandexMusic => "ymsearch",
+        }
+    }
+
+    /// Formats the query as a search with this engine.
+    #[must_use]
+    pub fn to_query(self, query: &str) -> String {
+        format!("{}:{}", self.prefix(), query)
+    }
+}
+
+impl fmt::Display for SearchEngines {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.prefix())
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Default)]
+pub struct PlaylistInfo {
+    /// Index of the selected track, -1 if none was.
+    #[serde(rename = "selectedTrack")]
+    pub selected_track: Option<i64>,
+
+    pub name: Option<String>,
+
+    /// Provided by some plugins.
+    pub author: Option<String>,
+    /// Provided by some plugins.
+    #[serde(rename = "artworkUrl")]
+    pub artwork_url: Option<String>,
+    /// Provided by some plugins.
+    pub url: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Default)]
+pub struct Track {
+    pub track: String,
+    pub info: Option<Info>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Default)]
+pub struct Info {
+    #[serde(rename = "isSeekable")]
+    pub is_seekable: bool,
+
+    #[serde(rename = "isStream")]
+    pub is_stream: bool,
+
+    pub identifier: String,
+    pub author: String,
+    pub length: u64,
     pub position: u64,
     pub title: String,
     pub uri: String,
+
+    /// Only sent by lavalink 3.4 and newer.
+    #[serde(rename = "sourceName")]
+    pub source_name: Option<String>,
+}
+
+impl Info {
+    /// The query used to find tracks similar to this one, for autoplay.
+    ///
+    /// Uses the spotify recommendations of `LavaSrc` for spotify tracks, the youtube mix for youtube
+    /// tracks, and a youtube search of the author and title for everything else.
+    #[must_use]
+    pub fn recommendation_query(&self) -> String {
+        match self.source_name.as_deref() {
+            Some("spotify") => format!("sprec:seed_tracks={}", self.identifier),
+            Some("youtube") => format!(
+                "https://www.youtube.com/watch?v={0}&list=RD{0}",
+                self.identifier
+            ),
+            _ => format!("ytsearch:{} {}", self.author, self.title),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -555,6 +1660,40 @@ pub struct FrameStats {
     pub nulled: i64,
 }
 
+/// When the frame stats of consecutive `stats` events are bad enough to trigger the
+/// `audio_degraded` event.
+///
+/// The frame stats are the average of the frames per player over the last minute, out of the
+/// 3000 frames sent each minute.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AudioDegradedThresholds {
+    /// The frames lavalink failed to send, above which the audio is degraded.
+    pub max_deficit: i64,
+    /// The frames sent empty because the track couldn't keep up, above which the audio is
+    /// degraded.
+    pub max_nulled: i64,
+    /// How many consecutive `stats` events must be degraded to trigger the event.
+    pub intervals: usize,
+}
+
+impl Default for AudioDegradedThresholds {
+    fn default() -> Self {
+        Self {
+            max_deficit: 150,
+            max_nulled: 150,
+            intervals: 2,
+        }
+    }
+}
+
+impl AudioDegradedThresholds {
+    /// If the frame stats are over any of the thresholds.
+    #[must_use]
+    pub fn is_degraded(&self, frame_stats: &FrameStats) -> bool {
+        frame_stats.deficit > self.max_deficit || frame_stats.nulled > self.max_nulled
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Memory {
     pub reservable: i64,
@@ -569,6 +1708,66 @@ pub struct State {
     pub time: i64,
 }
 
+/// The state of a player, as sent by the `playerUpdate` event.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PlayerUpdateState {
+    /// Unix timestamp in milliseconds of when the state was sent.
+    pub time: i64,
+    /// Position of the current track in milliseconds.
+    pub position: i64,
+    /// If lavalink is connected to the voice gateway.
+    #[serde(default)]
+    pub connected: bool,
+    /// Ping of the voice connection in milliseconds, -1 if not connected.
+    #[serde(default = "default_ping")]
+    pub ping: i64,
+}
+
+fn default_ping() -> i64 {
+    -1
+}
+
+impl PlayerUpdateState {
+    /// The position of the current track at the time the state was sent.
+    #[must_use]
+    pub fn position(&self) -> Duration {
+        Duration::from_millis(u64::try_from(self.position).unwrap_or_default())
+    }
+
+    /// If lavalink is connected to the discord voice gateway.
+    #[must_use]
+    pub fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    /// The latency between lavalink and the discord voice gateway, if it's connected.
+    #[must_use]
+    pub fn latency(&self) -> Option<Duration> {
+        if self.connected {
+            u64::try_from(self.ping).ok().map(Duration::from_millis)
+        } else {
+            None
+        }
+    }
+
+    /// How long ago the state was sent.
+    #[must_use]
+    pub fn age(&self) -> Duration {
+        self.age_at(SystemTime::now())
+    }
+
+    /// How long before `at` the state was sent.
+    #[must_use]
+    pub fn age_at(&self, at: SystemTime) -> Duration {
+        let at = at
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+
+        Duration::from_millis(at.saturating_sub(u64::try_from(self.time).unwrap_or_default()))
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct GatewayEvent {
     pub op: String,
@@ -576,6 +1775,48 @@ pub struct GatewayEvent {
     pub event_type: Option<String>,
 }
 
+/// The op and event type of a lavalink payload, borrowed from the message to dispatch it.
+#[derive(Debug, Deserialize)]
+pub(crate) struct PayloadHeader<'a> {
+    #[serde(borrow)]
+    pub op: Cow<'a, str>,
+    #[serde(borrow, rename = "type")]
+    pub event_type: Option<Cow<'a, str>>,
+}
+
+/// Deserializes an id sent as a string or a number, parsing the string in place instead of
+/// copying it first like `deserialize_number_from_string`.
+fn deserialize_id<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: FromStr + From<u64>,
+    T::Err: fmt::Display,
+{
+    struct IdVisitor<T>(PhantomData<T>);
+
+    impl<'de, T> serde::de::Visitor<'de> for IdVisitor<T>
+    where
+        T: FromStr + From<u64>,
+        T::Err: fmt::Display,
+    {
+        type Value = T;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("an id as a string or a number")
+        }
+
+        fn visit_u64<E: serde::de::Error>(self, value: u64) -> Result<T, E> {
+            Ok(T::from(value))
+        }
+
+        fn visit_str<E: serde::de::Error>(self, value: &str) -> Result<T, E> {
+            value.parse().map_err(E::custom)
+        }
+    }
+
+    deserializer.deserialize_any(IdVisitor(PhantomData))
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Stats {
     #[serde(rename = "playingPlayers")]
@@ -589,13 +1830,35 @@ pub struct Stats {
     pub uptime: i64,
 }
 
+/// When and in which order the client received a lavalink event, to order and deduplicate the
+/// events after reconnections.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EventMeta {
+    /// When the event was received.
+    pub received_at: SystemTime,
+    /// Increases by one with every event received for the guild, starting at 1.
+    pub sequence: u64,
+}
+
+impl Default for EventMeta {
+    fn default() -> Self {
+        Self {
+            received_at: UNIX_EPOCH,
+            sequence: 0,
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct PlayerUpdate {
     pub op: String,
-    pub state: State,
+    pub state: PlayerUpdateState,
     #[serde(rename = "guildId")]
-    #[serde(deserialize_with = "deserialize_number_from_string")]
+    #[serde(deserialize_with = "deserialize_id")]
     pub guild_id: GuildId,
+    /// When and in which order the client received the event.
+    #[serde(skip)]
+    pub meta: EventMeta,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -604,9 +1867,15 @@ pub struct TrackStart {
     #[serde(rename = "type")]
     pub track_start_type: String,
     pub track: String,
+    /// The queued track this event refers to, resolved from the node.
+    #[serde(skip)]
+    pub track_queue: Option<TrackQueue>,
     #[serde(rename = "guildId")]
-    #[serde(deserialize_with = "deserialize_number_from_string")]
+    #[serde(deserialize_with = "deserialize_id")]
     pub guild_id: GuildId,
+    /// When and in which order the client received the event.
+    #[serde(skip)]
+    pub meta: EventMeta,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -615,14 +1884,182 @@ pub struct WebSocketClosed {
     #[serde(rename = "type")]
     pub websocket_closed_type: String,
     #[serde(rename = "userId")]
-    #[serde(deserialize_with = "deserialize_number_from_string")]
+    #[serde(deserialize_with = "deserialize_id")]
     pub user_id: UserId,
     #[serde(rename = "guildId")]
-    #[serde(deserialize_with = "deserialize_number_from_string")]
+    #[serde(deserialize_with = "deserialize_id")]
     pub guild_id: GuildId,
     pub code: u64,
     #[serde(rename = "byRemote")]
     pub by_remote: bool,
+    /// When and in which order the client received the event.
+    #[serde(skip)]
+    pub meta: EventMeta,
+}
+
+impl WebSocketClosed {
+    /// If the voice connection can be resumed by sending the voice update again, like when the
+    /// discord voice server crashes (4015).
+    #[must_use]
+    pub fn is_resumable(&self) -> bool {
+        matches!(self.code, 1006 | 4015)
+    }
+
+    /// If discord invalidated the voice session, needing to join the voice channel again.
+    ///
+    /// This is the case with 4006 (session no longer valid), 4009 (session timed out) and 4014
+    /// (disconnected, because of a kick or the channel being deleted).
+    #[must_use]
+    pub fn is_session_invalidated(&self) -> bool {
+        matches!(self.code, 4006 | 4009 | 4014)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct VoiceSessionInvalidated {
+    pub guild_id: GuildId,
+    /// The close code of the voice websocket.
+    pub code: u64,
+}
+
+/// Emitted when the frame stats of lavalink stayed over the `AudioDegradedThresholds` for the
+/// configured number of consecutive `stats` events.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AudioDegraded {
+    /// The lavalink host with the degraded audio.
+    pub host: String,
+    /// The frame stats of the latest `stats` event.
+    pub frame_stats: FrameStats,
+    /// How many consecutive `stats` events were degraded.
+    pub intervals: usize,
+}
+
+/// Emitted when the websocket connection to lavalink is established.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Connected {
+    /// The lavalink host the websocket connected to.
+    pub host: String,
+    /// If lavalink resumed the previous session, keeping its players.
+    pub resumed: bool,
+}
+
+/// The player of a guild as the server sees it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RemotePlayerState {
+    pub playing: bool,
+    pub paused: bool,
+    /// The volume, None if the server didn't report it.
+    pub volume: Option<u16>,
+}
+
+impl RemotePlayerState {
+    /// Reads the state from a player of lavalink 3.7 and later, or Andesite.
+    #[must_use]
+    pub fn from_value(player: &serde_json::Value) -> Self {
+        // Lavalink has the track, Andesite only has a position while playing.
+        let playing = !player["track"].is_null() || !player["position"].is_null();
+
+        Self {
+            playing,
+            paused: player["paused"].as_bool().unwrap_or(false),
+            volume: player["volume"].as_u64().map(|volume| volume as u16),
+        }
+    }
+}
+
+/// A difference between the node of a guild and its player on the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StateDrift {
+    /// The server is playing a track, but the node has nothing playing.
+    ServerPlaying,
+    /// The node has a track playing, but the server doesn't.
+    ServerIdle,
+    /// The paused state is different, with the one of the server.
+    Paused(bool),
+    /// The volume is different, with the one of the server.
+    Volume(u16),
+}
+
+/// Emitted when the drift check finds the same differences between a node and its player on
+/// the server twice in a row.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct StateDriftDetected {
+    pub guild_id: GuildId,
+    pub drift: Vec<StateDrift>,
+    /// If the node was updated to match the server, see `set_correct_drift()` on the builder.
+    pub corrected: bool,
+}
+
+/// Emitted for every guild still playing on the server when the session is resumed, once its
+/// node was updated to match the player of the server.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PlayerResumed {
+    pub guild_id: GuildId,
+    /// The track the server is playing.
+    pub track: Track,
+    /// The state of the player, None if the server didn't report it.
+    pub state: Option<PlayerUpdateState>,
+    pub paused: bool,
+    /// The volume, None if the server didn't report it.
+    pub volume: Option<u16>,
+}
+
+/// Sent by lavalink 3.7 and later when the websocket connects, with the id of the session for
+/// the REST endpoints.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Ready {
+    pub resumed: bool,
+    #[serde(rename = "sessionId")]
+    pub session_id: String,
+}
+
+/// Emitted when an event handler panics, with the name of the handler method and the panic
+/// message.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct EventError {
+    /// The guild of the event, or None for the events not tied to a guild, like `stats`.
+    pub guild_id: Option<GuildId>,
+    pub event_name: String,
+    pub message: String,
+}
+
+/// A message from the lavalink websocket with an op or event type this library doesn't model,
+/// like the events of lavalink plugins.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct UnknownEvent {
+    /// The session id sent by lavalink 3.7 and later, see `Ready`.
+    pub session_id: Option<String>,
+    pub data: serde_json::Value,
+}
+
+/// Emitted when the bot is moved to another voice channel of the guild.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SelfMoved {
+    pub guild_id: GuildId,
+    pub old_channel: ChannelId,
+    pub new_channel: ChannelId,
+}
+
+/// Emitted when the bot leaves, or is disconnected from, the voice channel of the guild.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SelfDisconnected {
+    pub guild_id: GuildId,
+    /// The channel the bot was in.
+    pub channel_id: ChannelId,
+}
+
+/// Emitted when the bot is server muted or unmuted in the guild.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SelfServerMuted {
+    pub guild_id: GuildId,
+    pub muted: bool,
+}
+
+/// Emitted when the bot becomes a speaker of the stage channel it was suppressed in.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct StageSpeakerGranted {
+    pub guild_id: GuildId,
+    pub channel_id: ChannelId,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -632,11 +2069,14 @@ pub struct PlayerDestroyed {
     pub player_destroyed_type: String,
     pub cleanup: bool,
     #[serde(rename = "guildId")]
-    #[serde(deserialize_with = "deserialize_number_from_string")]
+    #[serde(deserialize_with = "deserialize_id")]
     pub guild_id: GuildId,
     #[serde(rename = "userId")]
-    #[serde(deserialize_with = "deserialize_number_from_string")]
+    #[serde(deserialize_with = "deserialize_id")]
     pub user_id: UserId,
+    /// When and in which order the client received the event.
+    #[serde(skip)]
+    pub meta: EventMeta,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -646,9 +2086,15 @@ pub struct TrackFinish {
     #[serde(rename = "type")]
     pub track_finish_type: String,
     pub track: String,
+    /// The queued track this event refers to, resolved from the node.
+    #[serde(skip)]
+    pub track_queue: Option<TrackQueue>,
     #[serde(rename = "guildId")]
-    #[serde(deserialize_with = "deserialize_number_from_string")]
+    #[serde(deserialize_with = "deserialize_id")]
     pub guild_id: GuildId,
+    /// When and in which order the client received the event.
+    #[serde(skip)]
+    pub meta: EventMeta,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -658,19 +2104,45 @@ pub struct TrackException {
     #[serde(rename = "type")]
     pub track_exception_type: String,
     pub track: String,
+    /// The queued track this event refers to, resolved from the node.
+    #[serde(skip)]
+    pub track_queue: Option<TrackQueue>,
+    /// Deprecated by lavalink, use `exception.message` instead.
+    #[serde(default)]
     pub error: String,
     #[serde(rename = "guildId")]
-    #[serde(deserialize_with = "deserialize_number_from_string")]
+    #[serde(deserialize_with = "deserialize_id")]
     pub guild_id: GuildId,
+    /// When and in which order the client received the event.
+    #[serde(skip)]
+    pub meta: EventMeta,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct Exception {
-    pub severity: String,
+    pub severity: ExceptionSeverity,
+    #[serde(default, deserialize_with = "deserialize_default_from_null")]
     pub cause: String,
+    #[serde(default, deserialize_with = "deserialize_default_from_null")]
     pub message: String,
 }
 
+/// How bad an exception is, as reported by lavaplayer.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum ExceptionSeverity {
+    /// The cause is known and expected, like an unavailable video.
+    Common,
+    /// The cause might not be exactly known, but is possibly caused by outside factors, like the
+    /// source returning an unexpected response.
+    Suspicious,
+    /// The probable cause is an issue with lavaplayer or lavalink.
+    Fault,
+    /// A severity added by a newer server, that this client doesn't know of.
+    #[serde(other)]
+    Unknown,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct TrackStuck {
     pub op: String,
@@ -680,10 +2152,191 @@ pub struct TrackStuck {
     pub track_stuck_type: String,
     pub track: String,
     #[serde(rename = "guildId")]
-    #[serde(deserialize_with = "deserialize_number_from_string")]
+    #[serde(deserialize_with = "deserialize_id")]
+    pub guild_id: GuildId,
+    /// When and in which order the client received the event.
+    #[serde(skip)]
+    pub meta: EventMeta,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct QueueTrackAdded {
+    pub guild_id: GuildId,
+    pub track: TrackQueue,
+    /// The index of the track in the queue. 0 means it will be played right away.
+    pub position: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct QueueFinished {
     pub guild_id: GuildId,
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct QueueLooped {
+    pub guild_id: GuildId,
+    /// The number of tracks added back to the queue.
+    pub track_count: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AutoplayTrackAdded {
+    pub guild_id: GuildId,
+    /// The recommended track that was queued.
+    pub track: TrackQueue,
+}
+
+/// Emitted when a guild is handled by a shard that doesn't match the configured shard count.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ShardMismatch {
+    pub guild_id: GuildId,
+    /// The shard that received the events of the guild.
+    pub shard_id: u64,
+    /// The shard the guild should be on, according to the configured shard count.
+    pub expected_shard_id: u64,
+    /// The shard count lavalink was told about.
+    pub shard_count: u64,
+}
+
+/// A player that is still active while the client is draining.
+#[derive(Debug, Clone)]
+pub struct DrainingPlayer {
+    pub guild_id: GuildId,
+    pub now_playing: Option<TrackQueue>,
+    /// The number of tracks left on the queue, including the current one.
+    pub queue_len: usize,
+    /// The estimated time until the player finishes the queue, or None if it contains a stream.
+    pub remaining: Option<Duration>,
+}
+
+/// What to do when a track gets stuck while playing.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum TrackStuckPolicy {
+    /// Only trigger the `track_stuck` event.
+    Nothing,
+    /// Skip to the next track of the queue, or stop the player if the queue is empty.
+    Skip,
+    /// Play the current track again from the last known position.
+    Replay,
+}
+
+impl Default for TrackStuckPolicy {
+    fn default() -> Self {
+        Self::Nothing
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TrackStuckRecovered {
+    pub guild_id: GuildId,
+    /// The track that got stuck.
+    pub track: String,
+    /// The policy that was applied to recover from it.
+    pub policy: TrackStuckPolicy,
+}
+
+/// A player or track event that belongs to a single guild.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum GuildEvent {
+    PlayerUpdate(PlayerUpdate),
+    TrackStart(TrackStart),
+    TrackFinish(TrackFinish),
+    TrackException(TrackException),
+    TrackStuck(TrackStuck),
+    WebSocketClosed(WebSocketClosed),
+    PlayerDestroyed(PlayerDestroyed),
+}
+
+impl GuildEvent {
+    /// When and in which order the client received the event.
+    #[must_use]
+    pub fn meta(&self) -> EventMeta {
+        match self {
+            Self::PlayerUpdate(e) => e.meta,
+            Self::TrackStart(e) => e.meta,
+            Self::TrackFinish(e) => e.meta,
+            Self::TrackException(e) => e.meta,
+            Self::TrackStuck(e) => e.meta,
+            Self::WebSocketClosed(e) => e.meta,
+            Self::PlayerDestroyed(e) => e.meta,
+        }
+    }
+
+    /// The guild the event belongs to.
+    #[must_use]
+    pub fn guild_id(&self) -> GuildId {
+        match self {
+            Self::PlayerUpdate(e) => e.guild_id,
+            Self::TrackStart(e) => e.guild_id,
+            Self::TrackFinish(e) => e.guild_id,
+            Self::TrackException(e) => e.guild_id,
+            Self::TrackStuck(e) => e.guild_id,
+            Self::WebSocketClosed(e) => e.guild_id,
+            Self::PlayerDestroyed(e) => e.guild_id,
+        }
+    }
+}
+
+/// A receiver of the player and track events of a single guild, see
+/// `LavalinkClient::subscribe_guild_events()`.
+pub struct GuildEventReceiver {
+    pub guild_id: GuildId,
+    pub receiver: broadcast::Receiver<GuildEvent>,
+}
+
+impl GuildEventReceiver {
+    /// Waits for the next event of the guild.
+    ///
+    /// Returns None if the client was dropped.
+    pub async fn recv(&mut self) -> Option<GuildEvent> {
+        loop {
+            match self.receiver.recv().await {
+                Ok(event) if event.guild_id() == self.guild_id => return Some(event),
+                Ok(_) => (),
+                Err(RecvError::Lagged(count)) => {
+                    warn!(
+                        "Guild {} events receiver skipped {} events.",
+                        self.guild_id, count
+                    );
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+
+    /// Waits for the next event of the guild, for up to `timeout`.
+    ///
+    /// Returns None if the timeout elapsed, or the client was dropped.
+    pub async fn recv_timeout(&mut self, timeout: Duration) -> Option<GuildEvent> {
+        tokio::time::timeout(timeout, self.recv())
+            .await
+            .ok()
+            .flatten()
+    }
+
+    /// Waits for the next event of the guild that matches the predicate, for up to `timeout`.
+    ///
+    /// Returns None if the timeout elapsed, or the client was dropped.
+    pub async fn wait_for(
+        &mut self,
+        timeout: Duration,
+        mut predicate: impl FnMut(&GuildEvent) -> bool,
+    ) -> Option<GuildEvent> {
+        let wait = async {
+            while let Some(event) = self.recv().await {
+                if predicate(&event) {
+                    return Some(event);
+                }
+            }
+
+            None
+        };
+
+        tokio::time::timeout(timeout, wait).await.ok().flatten()
+    }
+}
+
 #[cfg(feature = "discord-gateway")]
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct ConnectionInfo {
@@ -705,6 +2358,7 @@ pub struct EventVoiceServerUpdate {
 
 #[cfg(feature = "discord-gateway")]
 #[derive(Debug, Serialize, Deserialize, Clone)]
+#[allow(clippy::struct_excessive_bools)]
 pub struct EventVoiceStateUpdate {
     #[serde(deserialize_with = "deserialize_number_from_string")]
     pub guild_id: GuildId,
@@ -713,6 +2367,210 @@ pub struct EventVoiceStateUpdate {
     #[serde(deserialize_with = "deserialize_number_from_string")]
     pub user_id: UserId,
     pub session_id: String,
+    #[serde(default)]
+    pub mute: bool,
+    #[serde(default)]
+    pub deaf: bool,
+    #[serde(default)]
+    pub self_mute: bool,
+    #[serde(default)]
+    pub self_deaf: bool,
+    #[serde(default)]
+    pub suppress: bool,
+}
+
+#[cfg(feature = "discord-gateway")]
+impl EventVoiceStateUpdate {
+    /// The voice state of the member, None if they left the voice channels.
+    #[must_use]
+    pub fn member_voice_state(&self) -> Option<MemberVoiceState> {
+        Some(MemberVoiceState {
+            user_id: self.user_id,
+            channel_id: self.channel_id?,
+            mute: self.mute,
+            deaf: self.deaf,
+            self_mute: self.self_mute,
+            self_deaf: self.self_deaf,
+            suppress: self.suppress,
+        })
+    }
+}
+
+/// The voice state of a guild member in a voice channel, tracked from the discord gateway.
+#[cfg(feature = "discord-gateway")]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct MemberVoiceState {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub user_id: UserId,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub channel_id: ChannelId,
+    /// Muted by the server.
+    #[serde(default)]
+    pub mute: bool,
+    /// Deafened by the server.
+    #[serde(default)]
+    pub deaf: bool,
+    #[serde(default)]
+    pub self_mute: bool,
+    #[serde(default)]
+    pub self_deaf: bool,
+    /// Suppressed in a stage channel, so the member is in the audience instead of speaking.
+    #[serde(default)]
+    pub suppress: bool,
+}
+
+#[cfg(feature = "discord-gateway")]
+impl MemberVoiceState {
+    /// If the member can hear the channel, not being deafened by themselves or the server.
+    #[must_use]
+    pub fn is_listening(&self) -> bool {
+        !self.deaf && !self.self_deaf
+    }
+}
+
+/// The voice states of a guild, sent when the guild becomes available.
+#[cfg(feature = "discord-gateway")]
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct EventGuildCreate {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub id: GuildId,
+    #[serde(default)]
+    pub voice_states: Vec<MemberVoiceState>,
+}
+
+#[cfg(feature = "discord-gateway")]
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct EventGuildDelete {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub id: GuildId,
+}
+
+/// A dispatch event of the discord gateway, see `LavalinkClient::gateway_events()`.
+#[cfg(feature = "discord-gateway")]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct DiscordGatewayEvent {
+    /// The name of the event, like `READY` or `GUILD_CREATE`.
+    #[serde(rename = "t")]
+    pub name: String,
+    /// The sequence number of the event.
+    #[serde(rename = "s")]
+    pub sequence: Option<usize>,
+    /// The unparsed data of the event.
+    #[serde(rename = "d")]
+    pub data: Value,
+}
+
+/// The presence of the bot, sent by the discord gateway.
+#[cfg(feature = "discord-gateway")]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Presence {
+    /// Unix time in milliseconds of when the client went idle.
+    pub since: Option<u64>,
+    pub activities: Vec<Activity>,
+    /// One of `online`, `dnd`, `idle`, `invisible` or `offline`.
+    pub status: String,
+    pub afk: bool,
+}
+
+#[cfg(feature = "discord-gateway")]
+impl Presence {
+    /// An online presence with the activity.
+    #[must_use]
+    pub fn online(activity: Option<Activity>) -> Self {
+        Self {
+            since: None,
+            activities: activity.into_iter().collect(),
+            status: "online".to_string(),
+            afk: false,
+        }
+    }
+}
+
+/// The intents the discord gateway identifies with by default, `GUILDS` and `GUILD_VOICE_STATES`.
+#[cfg(feature = "discord-gateway")]
+pub const DEFAULT_GATEWAY_INTENTS: u64 = 1 | GUILD_VOICE_STATES_INTENT;
+
+/// The `GUILD_VOICE_STATES` intent, required to join voice channels.
+#[cfg(feature = "discord-gateway")]
+pub const GUILD_VOICE_STATES_INTENT: u64 = 1 << 7;
+
+/// The connection properties the discord gateway identifies with.
+#[cfg(feature = "discord-gateway")]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct IdentifyProperties {
+    #[serde(rename = "$os")]
+    pub os: String,
+    #[serde(rename = "$browser")]
+    pub browser: String,
+    #[serde(rename = "$device")]
+    pub device: String,
+}
+
+#[cfg(feature = "discord-gateway")]
+impl Default for IdentifyProperties {
+    fn default() -> Self {
+        Self {
+            os: std::env::consts::OS.to_string(),
+            browser: "lavalink-rs".to_string(),
+            device: "lavalink-rs".to_string(),
+        }
+    }
+}
+
+#[cfg(feature = "discord-gateway")]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Activity {
+    pub name: String,
+    /// 0 is playing, 1 is streaming, 2 is listening, 3 is watching and 5 is competing.
+    #[serde(rename = "type")]
+    pub activity_type: u8,
+    /// Stream URL, only used with the streaming type.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+}
+
+#[cfg(feature = "discord-gateway")]
+impl Activity {
+    /// "Playing {name}"
+    #[must_use]
+    pub fn playing(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            activity_type: 0,
+            url: None,
+        }
+    }
+
+    /// "Listening to {name}"
+    #[must_use]
+    pub fn listening(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            activity_type: 2,
+            url: None,
+        }
+    }
+
+    /// "Watching {name}"
+    #[must_use]
+    pub fn watching(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            activity_type: 3,
+            url: None,
+        }
+    }
+
+    /// "Competing in {name}"
+    #[must_use]
+    pub fn competing(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            activity_type: 5,
+            url: None,
+        }
+    }
 }
 
 #[cfg(feature = "discord-gateway")]
@@ -0,0 +1,181 @@
+use super::{Info, TrackQueue};
+
+use std::convert::TryFrom;
+use std::time::Duration;
+
+/// Formats the duration like a media player, as `m:ss`, or `h:mm:ss` if it's an hour or longer.
+#[must_use]
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    let (hours, minutes, seconds) = (secs / 3600, secs / 60 % 60, secs % 60);
+
+    if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, minutes, seconds)
+    } else {
+        format!("{}:{:02}", minutes, seconds)
+    }
+}
+
+/// Renders a text progress bar of `width` characters, like `━━━━●─────`.
+#[must_use]
+pub fn progress_bar(position: Duration, length: Duration, width: usize) -> String {
+    if width == 0 {
+        return String::new();
+    }
+
+    let filled = if length.as_millis() == 0 {
+        0
+    } else {
+        let filled = position.as_millis() * (width as u128) / length.as_millis();
+        usize::try_from(filled).unwrap_or(width).min(width - 1)
+    };
+
+    let mut bar = "━".repeat(filled);
+    bar.push('●');
+    bar.push_str(&"─".repeat(width - filled - 1));
+    bar
+}
+
+/// Renders the now playing line of a track, like `Title - Author` followed by the progress bar
+/// and `1:23 / 3:45`, or `LIVE` for streams.
+///
+/// The position can be taken from `LavalinkClient::position()`.
+#[must_use]
+pub fn now_playing(info: &Info, position: Duration, width: usize) -> String {
+    if info.is_stream {
+        return format!(
+            "{} - {}\n{} LIVE",
+            info.title,
+            info.author,
+            "─".repeat(width)
+        );
+    }
+
+    let length = Duration::from_millis(info.length);
+
+    format!(
+        "{} - {}\n{} {} / {}",
+        info.title,
+        info.author,
+        progress_bar(position, length, width),
+        format_duration(position.min(length)),
+        format_duration(length),
+    )
+}
+
+/// Renders a page of the queue, counting from 0, with `per_page` tracks per page, one numbered
+/// line per track like `1. Title - Author [3:45]`.
+///
+/// Empty if the page is past the last one.
+#[must_use]
+pub fn queue_page(queue: &[TrackQueue], page: usize, per_page: usize) -> String {
+    let start = page.saturating_mul(per_page);
+    let mut rendered = String::new();
+
+    for (index, track) in queue.iter().enumerate().skip(start).take(per_page) {
+        let line = match &track.track.info {
+            Some(info) if info.is_stream => {
+                format!("{}. {} - {} [LIVE]", index + 1, info.title, info.author)
+            }
+            Some(info) => format!(
+                "{}. {} - {} [{}]",
+                index + 1,
+                info.title,
+                info.author,
+                format_duration(Duration::from_millis(info.length)),
+            ),
+            None => format!("{}. Unknown track", index + 1),
+        };
+
+        if !rendered.is_empty() {
+            rendered.push('\n');
+        }
+
+        rendered.push_str(&line);
+    }
+
+    rendered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Track;
+
+    fn info(title: &str, length: u64, is_stream: bool) -> Info {
+        Info {
+            title: title.to_string(),
+            author: "Author".to_string(),
+            length,
+            is_stream,
+            ..Info::default()
+        }
+    }
+
+    fn queued(info: Option<Info>) -> TrackQueue {
+        TrackQueue {
+            track: Track {
+                track: String::new(),
+                info,
+            },
+            ..TrackQueue::default()
+        }
+    }
+
+    #[test]
+    fn format_duration_rolls_over_to_hours() {
+        assert_eq!(format_duration(Duration::from_secs(0)), "0:00");
+        assert_eq!(format_duration(Duration::from_secs(59)), "0:59");
+        assert_eq!(format_duration(Duration::from_secs(3599)), "59:59");
+        assert_eq!(format_duration(Duration::from_secs(60 * 60)), "1:00:00");
+        assert_eq!(format_duration(Duration::from_secs(36_061)), "10:01:01");
+    }
+
+    #[test]
+    fn progress_bar_handles_the_edges() {
+        let length = Duration::from_secs(100);
+
+        assert_eq!(progress_bar(Duration::from_secs(0), length, 5), "●────");
+        assert_eq!(progress_bar(Duration::from_secs(50), length, 4), "━━●─");
+        // The position can be past the length, the bar stays full.
+        assert_eq!(progress_bar(Duration::from_secs(150), length, 4), "━━━●");
+        assert_eq!(
+            progress_bar(Duration::from_secs(10), Duration::from_secs(0), 3),
+            "●──"
+        );
+        assert_eq!(progress_bar(Duration::from_secs(10), length, 0), "");
+    }
+
+    #[test]
+    fn now_playing_renders_tracks_and_streams() {
+        assert_eq!(
+            now_playing(&info("Song", 225_000, false), Duration::from_secs(83), 4),
+            "Song - Author\n━●── 1:23 / 3:45"
+        );
+        assert_eq!(
+            now_playing(&info("Song", 225_000, false), Duration::from_secs(5 * 60), 4),
+            "Song - Author\n━━━● 3:45 / 3:45"
+        );
+        assert_eq!(
+            now_playing(&info("Radio", 0, true), Duration::from_secs(83), 4),
+            "Radio - Author\n──── LIVE"
+        );
+    }
+
+    #[test]
+    fn queue_page_ends_with_a_partial_page() {
+        let queue = vec![
+            queued(Some(info("First", 60_000, false))),
+            queued(Some(info("Radio", 0, true))),
+            queued(None),
+        ];
+
+        assert_eq!(
+            queue_page(&queue, 0, 2),
+            "1. First - Author [1:00]\n2. Radio - Author [LIVE]"
+        );
+        assert_eq!(queue_page(&queue, 1, 2), "3. Unknown track");
+        assert_eq!(queue_page(&queue, 2, 2), "");
+        assert_eq!(queue_page(&[], 0, 2), "");
+    }
+}
@@ -0,0 +1,542 @@
+#[cfg(feature = "discord-gateway")]
+use super::{ChannelId, IdentifyProperties, Presence};
+use super::{Filters, GuildId};
+
+use serde::Serialize;
+
+/// A payload for the player of a guild: the op, the guild and the fields of the opcode.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlayerPayload<'a, T: Serialize> {
+    pub op: &'static str,
+    pub guild_id: String,
+    #[serde(flatten)]
+    pub data: &'a T,
+}
+
+impl<'a, T: Serialize> PlayerPayload<'a, T> {
+    #[must_use]
+    pub fn new(op: &'static str, guild_id: GuildId, data: &'a T) -> Self {
+        Self {
+            op,
+            guild_id: guild_id.0.to_string(),
+            data,
+        }
+    }
+
+    /// Serializes the payload to the JSON sent to lavalink.
+    #[must_use]
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("The payload cannot be serialized.")
+    }
+}
+
+/// The fields of the opcodes that have none, like `stop` and `destroy`.
+#[derive(Debug, Serialize)]
+pub struct NoData {}
+
+/// Configures the key and timeout used to resume the lavalink session.
+#[derive(Debug, Serialize)]
+pub struct ConfigureResuming<'a> {
+    pub op: &'static str,
+    pub key: &'a str,
+    /// The timeout, in seconds.
+    pub timeout: u64,
+}
+
+impl<'a> ConfigureResuming<'a> {
+    #[must_use]
+    pub fn new(key: &'a str, timeout: u64) -> Self {
+        Self {
+            op: "configureResuming",
+            key,
+            timeout,
+        }
+    }
+
+    /// Serializes the payload to the JSON sent to lavalink.
+    #[must_use]
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("The payload cannot be serialized.")
+    }
+}
+
+/// The body of the session update of lavalink 3.7 and later, configuring how the session is
+/// resumed. The fields left to None aren't changed.
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionUpdate {
+    /// The resume key of lavalink 3.7 and later 3.x versions.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resuming_key: Option<String>,
+    /// Whether lavalink v4 keeps the session to be resumed by its id.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resuming: Option<bool>,
+    /// The timeout, in seconds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u64>,
+}
+
+/// The body of the player update of lavalink 3.7 and later. The fields left to None aren't
+/// changed.
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdatePlayerBody<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encoded_track: Option<&'a str>,
+    /// The position, in milliseconds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub position: Option<u64>,
+    /// The time the track ends at, in milliseconds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end_time: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub volume: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub paused: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filters: Option<&'a Filters>,
+}
+
+/// A payload of the discord gateway: the opcode and its data.
+#[cfg(feature = "discord-gateway")]
+#[derive(Debug, Serialize)]
+pub struct GatewayPayload<T: Serialize> {
+    pub op: u8,
+    pub d: T,
+}
+
+#[cfg(feature = "discord-gateway")]
+impl<T: Serialize> GatewayPayload<T> {
+    #[must_use]
+    pub fn new(op: u8, d: T) -> Self {
+        Self { op, d }
+    }
+
+    /// Serializes the payload to the JSON sent to discord.
+    #[must_use]
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("The payload cannot be serialized.")
+    }
+}
+
+/// The data of the identify opcode, sent to start a new gateway session.
+#[cfg(feature = "discord-gateway")]
+#[derive(Debug, Serialize)]
+pub struct Identify<'a> {
+    pub large_threshold: u64,
+    pub token: &'a str,
+    pub intents: u64,
+    pub v: &'static str,
+    pub properties: &'a IdentifyProperties,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub presence: Option<&'a Presence>,
+}
+
+/// The data of the resume opcode, sent to continue a gateway session after reconnecting.
+#[cfg(feature = "discord-gateway")]
+#[derive(Debug, Serialize)]
+pub struct Resume<'a> {
+    pub token: &'a str,
+    pub session_id: &'a str,
+    pub seq: usize,
+}
+
+/// The data of the voice state update opcode, sent to join or leave a voice channel.
+#[cfg(feature = "discord-gateway")]
+#[derive(Debug, Serialize)]
+pub struct VoiceStateUpdate {
+    pub guild_id: String,
+    pub channel_id: Option<String>,
+    pub self_mute: bool,
+    pub self_deaf: bool,
+}
+
+#[cfg(feature = "discord-gateway")]
+impl VoiceStateUpdate {
+    /// Joins the channel deafened, or leaves the voice channel of the guild if it's None.
+    #[must_use]
+    pub fn new(guild_id: GuildId, channel_id: Option<ChannelId>) -> Self {
+        Self {
+            guild_id: guild_id.0.to_string(),
+            channel_id: channel_id.map(|channel_id| channel_id.0.to_string()),
+            self_mute: false,
+            self_deaf: true,
+        }
+    }
+}
+
+/// The body of the discord REST request modifying a member of a guild.
+#[cfg(feature = "discord-gateway")]
+#[derive(Debug, Default, Serialize)]
+pub struct ModifyMember {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mute: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deaf: Option<bool>,
+}
+
+/// The body of the discord REST request modifying the voice state of the bot in a stage channel.
+#[cfg(feature = "discord-gateway")]
+#[derive(Debug, Serialize)]
+pub struct ModifyVoiceState<'a> {
+    pub channel_id: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub suppress: Option<bool>,
+    /// An ISO 8601 timestamp, to request to speak.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_to_speak_timestamp: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{
+        Band, Bands, Equalizer, Event, Pause, Play, Seek, SendOpcode, Timescale, VoiceUpdate,
+        Volume,
+    };
+    use serde_json::json;
+
+    #[test]
+    fn session_update_skips_unset_fields() {
+        let update = SessionUpdate {
+            resuming_key: Some("key".to_string()),
+            timeout: Some(60),
+            ..SessionUpdate::default()
+        };
+
+        assert_eq!(
+            serde_json::to_value(&update).unwrap(),
+            json!({ "resumingKey": "key", "timeout": 60 })
+        );
+        assert_eq!(
+            serde_json::to_value(SessionUpdate::default()).unwrap(),
+            json!({})
+        );
+    }
+
+    #[test]
+    fn update_player_body_skips_unset_fields() {
+        let body = UpdatePlayerBody {
+            paused: Some(true),
+            ..UpdatePlayerBody::default()
+        };
+
+        assert_eq!(
+            serde_json::to_value(&body).unwrap(),
+            json!({ "paused": true })
+        );
+
+        let body = UpdatePlayerBody {
+            encoded_track: Some("QAAA"),
+            position: Some(1_000),
+            volume: Some(50),
+            ..UpdatePlayerBody::default()
+        };
+
+        assert_eq!(
+            serde_json::to_value(&body).unwrap(),
+            json!({ "encodedTrack": "QAAA", "position": 1000, "volume": 50 })
+        );
+    }
+
+    #[test]
+    fn player_payload_flattens_the_data() {
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Volume {
+            volume: u16,
+        }
+
+        let payload = PlayerPayload::new("volume", GuildId(123), &Volume { volume: 50 });
+        let value: serde_json::Value = serde_json::from_str(&payload.to_json()).unwrap();
+
+        assert_eq!(
+            value,
+            json!({ "op": "volume", "guildId": "123", "volume": 50 })
+        );
+    }
+
+    #[test]
+    fn filters_skip_unset_fields() {
+        let filters = crate::model::Filters {
+            karaoke: Some(crate::model::Karaoke {
+                level: Some(0.5),
+                ..crate::model::Karaoke::default()
+            }),
+            timescale: Some(crate::model::Timescale {
+                speed: Some(1.5),
+                ..crate::model::Timescale::default()
+            }),
+            ..crate::model::Filters::default()
+        };
+        let payload = PlayerPayload::new("filters", GuildId(123), &filters);
+        let value: serde_json::Value = serde_json::from_str(&payload.to_json()).unwrap();
+
+        assert_eq!(
+            value,
+            json!({
+                "op": "filters",
+                "guildId": "123",
+                "karaoke": { "level": 0.5 },
+                "timescale": { "speed": 1.5 },
+            })
+        );
+    }
+
+    #[test]
+    fn player_payload_without_data() {
+        let payload = PlayerPayload::new("stop", GuildId(123), &NoData {});
+
+        assert_eq!(payload.to_json(), r#"{"op":"stop","guildId":"123"}"#);
+    }
+
+    #[test]
+    fn send_opcode_destroy() {
+        assert_eq!(
+            SendOpcode::Destroy.to_json(GuildId(1)),
+            r#"{"op":"destroy","guildId":"1"}"#
+        );
+    }
+
+    #[test]
+    fn send_opcode_stop() {
+        assert_eq!(
+            SendOpcode::Stop.to_json(GuildId(1)),
+            r#"{"op":"stop","guildId":"1"}"#
+        );
+    }
+
+    #[test]
+    fn send_opcode_equalizer() {
+        let bands = Bands::new(vec![Band {
+            band: 0,
+            gain: 0.25,
+        }])
+        .unwrap();
+
+        assert_eq!(
+            SendOpcode::Equalizer(Equalizer { bands }).to_json(GuildId(1)),
+            r#"{"op":"equalizer","guildId":"1","bands":[{"band":0,"gain":0.25}]}"#
+        );
+    }
+
+    #[test]
+    fn send_opcode_pause() {
+        assert_eq!(
+            SendOpcode::Pause(Pause { pause: true }).to_json(GuildId(1)),
+            r#"{"op":"pause","guildId":"1","pause":true}"#
+        );
+    }
+
+    #[test]
+    fn send_opcode_play() {
+        let play = Play {
+            track: "QAAA".to_string(),
+            no_replace: false,
+            start_time: 0,
+            end_time: None,
+            volume: None,
+        };
+
+        assert_eq!(
+            SendOpcode::Play(play.clone()).to_json(GuildId(1)),
+            r#"{"op":"play","guildId":"1","track":"QAAA","noReplace":false,"startTime":0}"#
+        );
+
+        let play = Play {
+            no_replace: true,
+            start_time: 1_000,
+            end_time: Some(2_000),
+            volume: Some(50),
+            ..play
+        };
+
+        assert_eq!(
+            SendOpcode::Play(play).to_json(GuildId(1)),
+            r#"{"op":"play","guildId":"1","track":"QAAA","noReplace":true,"startTime":1000,"endTime":2000,"volume":50}"#
+        );
+    }
+
+    #[test]
+    fn send_opcode_seek() {
+        assert_eq!(
+            SendOpcode::Seek(Seek { position: 1_000 }).to_json(GuildId(1)),
+            r#"{"op":"seek","guildId":"1","position":1000}"#
+        );
+    }
+
+    #[test]
+    fn send_opcode_voice_update() {
+        let voice_update = VoiceUpdate {
+            session_id: "session".to_string(),
+            event: Event {
+                token: "token".to_string(),
+                endpoint: "endpoint".to_string(),
+                guild_id: "1".to_string(),
+            },
+        };
+
+        assert_eq!(
+            SendOpcode::VoiceUpdate(voice_update).to_json(GuildId(1)),
+            r#"{"op":"voiceUpdate","guildId":"1","sessionId":"session","event":{"token":"token","endpoint":"endpoint","guildId":"1"}}"#
+        );
+    }
+
+    #[test]
+    fn send_opcode_volume() {
+        assert_eq!(
+            SendOpcode::Volume(Volume { volume: 50 }).to_json(GuildId(1)),
+            r#"{"op":"volume","guildId":"1","volume":50}"#
+        );
+    }
+
+    #[test]
+    fn send_opcode_filters() {
+        let filters = Filters {
+            volume: Some(1.5),
+            timescale: Some(Timescale {
+                speed: Some(1.2),
+                ..Timescale::default()
+            }),
+            ..Filters::default()
+        };
+
+        assert_eq!(
+            SendOpcode::Filters(Box::new(filters)).to_json(GuildId(1)),
+            r#"{"op":"filters","guildId":"1","volume":1.5,"timescale":{"speed":1.2}}"#
+        );
+    }
+
+    #[test]
+    fn configure_resuming() {
+        assert_eq!(
+            ConfigureResuming::new("key", 60).to_json(),
+            r#"{"op":"configureResuming","key":"key","timeout":60}"#
+        );
+    }
+
+    #[cfg(feature = "discord-gateway")]
+    #[test]
+    fn heartbeat() {
+        assert_eq!(
+            GatewayPayload::new(1, 42_usize).to_json(),
+            r#"{"op":1,"d":42}"#
+        );
+    }
+
+    #[cfg(feature = "discord-gateway")]
+    #[test]
+    fn identify() {
+        let properties = IdentifyProperties {
+            os: "linux".to_string(),
+            browser: "lavalink-rs".to_string(),
+            device: "lavalink-rs".to_string(),
+        };
+        let mut identify = Identify {
+            large_threshold: 250,
+            token: "token",
+            intents: 129,
+            v: "v9",
+            properties: &properties,
+            presence: None,
+        };
+
+        let value: serde_json::Value =
+            serde_json::from_str(&GatewayPayload::new(2, &identify).to_json()).unwrap();
+
+        assert_eq!(
+            value,
+            json!({
+                "op": 2,
+                "d": {
+                    "large_threshold": 250,
+                    "token": "token",
+                    "intents": 129,
+                    "v": "v9",
+                    "properties": {
+                        "$os": "linux",
+                        "$browser": "lavalink-rs",
+                        "$device": "lavalink-rs",
+                    },
+                },
+            })
+        );
+
+        let presence = Presence {
+            since: None,
+            activities: Vec::new(),
+            status: "idle".to_string(),
+            afk: true,
+        };
+        identify.presence = Some(&presence);
+
+        let value: serde_json::Value =
+            serde_json::from_str(&GatewayPayload::new(2, &identify).to_json()).unwrap();
+
+        assert_eq!(
+            value["d"]["presence"],
+            json!({ "since": null, "activities": [], "status": "idle", "afk": true })
+        );
+    }
+
+    #[cfg(feature = "discord-gateway")]
+    #[test]
+    fn resume() {
+        let resume = Resume {
+            token: "token",
+            session_id: "session",
+            seq: 7,
+        };
+
+        assert_eq!(
+            GatewayPayload::new(6, resume).to_json(),
+            r#"{"op":6,"d":{"token":"token","session_id":"session","seq":7}}"#
+        );
+    }
+
+    #[cfg(feature = "discord-gateway")]
+    #[test]
+    fn voice_state_update() {
+        let join = VoiceStateUpdate::new(GuildId(1), Some(ChannelId(2)));
+        let leave = VoiceStateUpdate::new(GuildId(1), None);
+
+        assert_eq!(
+            GatewayPayload::new(4, join).to_json(),
+            r#"{"op":4,"d":{"guild_id":"1","channel_id":"2","self_mute":false,"self_deaf":true}}"#
+        );
+        assert_eq!(
+            GatewayPayload::new(4, leave).to_json(),
+            r#"{"op":4,"d":{"guild_id":"1","channel_id":null,"self_mute":false,"self_deaf":true}}"#
+        );
+    }
+
+    #[cfg(feature = "discord-gateway")]
+    #[test]
+    fn discord_rest_bodies() {
+        let mute = ModifyMember {
+            mute: Some(true),
+            ..ModifyMember::default()
+        };
+        let speak = ModifyVoiceState {
+            channel_id: "2",
+            suppress: Some(false),
+            request_to_speak_timestamp: None,
+        };
+        let request = ModifyVoiceState {
+            channel_id: "2",
+            suppress: None,
+            request_to_speak_timestamp: Some("2021-05-04T13:37:00Z".to_string()),
+        };
+
+        assert_eq!(serde_json::to_string(&mute).unwrap(), r#"{"mute":true}"#);
+        assert_eq!(
+            serde_json::to_string(&speak).unwrap(),
+            r#"{"channel_id":"2","suppress":false}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&request).unwrap(),
+            r#"{"channel_id":"2","request_to_speak_timestamp":"2021-05-04T13:37:00Z"}"#
+        );
+    }
+}
@@ -0,0 +1,109 @@
+use crate::builders::LavalinkClientBuilder;
+use crate::error::LavalinkResult;
+use crate::gateway::LavalinkEventHandler;
+use crate::LavalinkClient;
+
+use poise_dep::Context;
+use serenity_dep::model::gateway::Ready;
+
+/// Implemented by the poise user data to give access to the lavalink client.
+pub trait LavalinkData {
+    fn lavalink(&self) -> &LavalinkClient;
+}
+
+impl LavalinkData for LavalinkClient {
+    fn lavalink(&self) -> &LavalinkClient {
+        self
+    }
+}
+
+/// Extension for the poise context, to get the lavalink client with `ctx.lavalink()`.
+pub trait LavalinkContextExt {
+    fn lavalink(&self) -> &LavalinkClient;
+}
+
+impl<U: LavalinkData + Send + Sync, E> LavalinkContextExt for Context<'_, U, E> {
+    fn lavalink(&self) -> &LavalinkClient {
+        self.data().lavalink()
+    }
+}
+
+/// Builds the client with the bot ID and shard count from the ready event, to be used on the
+/// poise framework setup.
+///
+/// ```rust,untested
+/// .setup(move |_ctx, ready, _framework| {
+///     Box::pin(async move {
+///         let lavalink = setup_lavalink(&mut LavalinkClient::builder(0), ready, LavalinkHandler).await?;
+///         Ok(Data { lavalink })
+///     })
+/// })
+/// ```
+pub async fn setup_lavalink(
+    builder: &mut LavalinkClientBuilder,
+    ready: &Ready,
+    handler: impl LavalinkEventHandler + Send + Sync + 'static,
+) -> LavalinkResult<LavalinkClient> {
+    builder.set_bot_id(ready.user.id);
+
+    if let Some([_, shard_count]) = ready.shard {
+        builder.set_shard_count(shard_count);
+    }
+
+    builder.build(handler).await
+}
+
+/// Check that passes if a lavalink session exists for the guild.
+pub async fn player_exists<U: LavalinkData + Send + Sync, E>(
+    ctx: Context<'_, U, E>,
+) -> Result<bool, E> {
+    let guild_id = match ctx.guild_id() {
+        Some(guild_id) => guild_id,
+        None => return Ok(false),
+    };
+
+    Ok(ctx.lavalink().nodes().await.contains_key(&guild_id.0))
+}
+
+/// Check that passes if the author is in a voice channel of the guild.
+// Poise checks have to be async, even when they don't await anything.
+#[allow(clippy::unused_async)]
+pub async fn user_in_voice_channel<U: LavalinkData + Send + Sync, E>(
+    ctx: Context<'_, U, E>,
+) -> Result<bool, E> {
+    let guild = match ctx.guild() {
+        Some(guild) => guild,
+        None => return Ok(false),
+    };
+
+    Ok(guild
+        .voice_states
+        .get(&ctx.author().id)
+        .and_then(|voice_state| voice_state.channel_id)
+        .is_some())
+}
+
+/// Check that passes if the author is in the same voice channel as the bot.
+// Poise checks have to be async, even when they don't await anything.
+#[allow(clippy::unused_async)]
+pub async fn user_in_same_voice_channel<U: LavalinkData + Send + Sync, E>(
+    ctx: Context<'_, U, E>,
+) -> Result<bool, E> {
+    let guild = match ctx.guild() {
+        Some(guild) => guild,
+        None => return Ok(false),
+    };
+
+    let bot_id = ctx.serenity_context().cache.current_user_id();
+
+    let user_channel = guild
+        .voice_states
+        .get(&ctx.author().id)
+        .and_then(|voice_state| voice_state.channel_id);
+    let bot_channel = guild
+        .voice_states
+        .get(&bot_id)
+        .and_then(|voice_state| voice_state.channel_id);
+
+    Ok(user_channel.is_some() && user_channel == bot_channel)
+}
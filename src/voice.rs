@@ -1,10 +1,140 @@
 use crate::error::{LavalinkError, LavalinkResult};
+use crate::event_loops::dispatch_guild_event;
 use crate::gateway::call_discord_gateway;
-use crate::model::{ChannelId, ConnectionInfo, GuildId, UserId};
+use crate::model::outgoing::{GatewayPayload, ModifyMember, ModifyVoiceState, VoiceStateUpdate};
+use crate::model::{
+    ChannelId, ConnectionInfo, GuildId, MemberVoiceState, SelfDisconnected, SelfMoved,
+    SelfServerMuted, StageSpeakerGranted, UserId,
+};
 use crate::LavalinkClient;
 
+use async_trait::async_trait;
+use serde::Serialize;
+use std::fmt;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 use tokio::time::{sleep, Duration};
 
+/// The discord REST API, on the same version as the gateway.
+const DISCORD_API: &str = "https://discord.com/api/v9";
+
+/// The `ADMINISTRATOR` permission, which implies every other one.
+pub const ADMINISTRATOR: u64 = 1 << 3;
+/// The `CONNECT` permission, required to join a voice channel.
+pub const CONNECT: u64 = 1 << 20;
+/// The `SPEAK` permission, required to play audio in a voice channel.
+pub const SPEAK: u64 = 1 << 21;
+
+/// The transport of the voice state updates, to join the voice channels through the gateway of
+/// serenity, twilight or any other library instead of the internal one.
+///
+/// The crate keeps doing the connection bookkeeping, so the voice state and voice server updates
+/// of the bot received by that gateway must be passed to `raw_handle_event_voice_state_update()`
+/// and `raw_handle_event_voice_server_update()`, and `raw_handle_stage_suppress()` for stage
+/// channels.
+#[async_trait]
+pub trait VoiceGateway: Send + Sync {
+    /// Sends a voice state update (opcode 4) for the guild, leaving the voice channel if
+    /// `channel_id` is None.
+    async fn update_voice_state(
+        &self,
+        guild_id: GuildId,
+        channel_id: Option<ChannelId>,
+        self_mute: bool,
+        self_deaf: bool,
+    ) -> LavalinkResult<()>;
+}
+
+/// A shared handle to a `VoiceGateway`, used to store it on the builder and the client.
+#[derive(Clone)]
+pub struct SharedVoiceGateway(pub Arc<dyn VoiceGateway>);
+
+impl SharedVoiceGateway {
+    #[must_use]
+    pub fn new(gateway: impl VoiceGateway + 'static) -> Self {
+        Self(Arc::new(gateway))
+    }
+}
+
+impl fmt::Debug for SharedVoiceGateway {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("SharedVoiceGateway { .. }")
+    }
+}
+
+impl PartialEq for SharedVoiceGateway {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+/// Gets the permissions of the bot in a voice channel, checked by `join()` before joining it.
+///
+/// The callback returns the permissions bitfield, usually computed from the cache of the discord
+/// library, or None if they're unknown, in which case the channel is joined anyway.
+#[derive(Clone)]
+#[allow(clippy::type_complexity)]
+pub struct PermissionCheck(pub Arc<dyn Fn(GuildId, ChannelId) -> Option<u64> + Send + Sync>);
+
+impl PermissionCheck {
+    #[must_use]
+    pub fn new(check: impl Fn(GuildId, ChannelId) -> Option<u64> + Send + Sync + 'static) -> Self {
+        Self(Arc::new(check))
+    }
+}
+
+impl fmt::Debug for PermissionCheck {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("PermissionCheck { .. }")
+    }
+}
+
+impl PartialEq for PermissionCheck {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+/// Checks that the permissions include `CONNECT` and `SPEAK`, returning the missing ones as
+/// `LavalinkError::MissingPermissions`.
+pub fn check_voice_permissions(permissions: u64) -> LavalinkResult<()> {
+    if permissions & ADMINISTRATOR != 0 {
+        return Ok(());
+    }
+
+    let missing = (CONNECT | SPEAK) & !permissions;
+
+    if missing == 0 {
+        Ok(())
+    } else {
+        Err(LavalinkError::MissingPermissions(missing))
+    }
+}
+
+fn voice_state_update_payload(guild_id: GuildId, channel_id: Option<ChannelId>) -> String {
+    GatewayPayload::new(4, VoiceStateUpdate::new(guild_id, channel_id)).to_json()
+}
+
+/// Sends the voice state update of the guild through the configured `VoiceGateway`, or the
+/// internal gateway if there's none.
+async fn update_voice_state(
+    lavalink: &LavalinkClient,
+    guild_id: GuildId,
+    channel_id: Option<ChannelId>,
+) -> LavalinkResult<()> {
+    let voice_gateway = lavalink.discord_gateway_data().lock().voice_gateway.clone();
+
+    if let Some(voice_gateway) = voice_gateway {
+        voice_gateway
+            .0
+            .update_voice_state(guild_id, channel_id, false, true)
+            .await
+    } else {
+        call_discord_gateway(lavalink, voice_state_update_payload(guild_id, channel_id));
+        Ok(())
+    }
+}
+
 pub async fn join(
     lavalink: &LavalinkClient,
     guild_id: impl Into<GuildId>,
@@ -13,47 +143,221 @@ pub async fn join(
     let guild_id = guild_id.into();
     let channel_id = channel_id.into();
 
-    call_discord_gateway(
-        lavalink,
-        format!(
-            r#"{{
-                "op": 4,
-                "d": {{
-                    "guild_id": "{}",
-                    "channel_id": "{}",
-                    "self_mute": false,
-                    "self_deaf": true
-                }}
-            }}"#,
-            guild_id.0, channel_id.0
-        ),
-    );
+    let (timeout, retries, connections, permission_check) = {
+        let gateway_data = lavalink.discord_gateway_data();
+        let gateway_data = gateway_data.lock();
+        (
+            gateway_data.join_timeout,
+            gateway_data.join_retries,
+            gateway_data.connections.clone(),
+            gateway_data.permission_check.clone(),
+        )
+    };
+
+    if let Some(permissions) = permission_check.and_then(|check| (check.0)(guild_id, channel_id)) {
+        check_voice_permissions(permissions)?;
+    }
+
+    for attempt in 0..=retries {
+        if attempt > 0 {
+            warn!(
+                "Retrying to join the voice channel {} on guild {}",
+                channel_id, guild_id
+            );
+        }
+
+        update_voice_state(lavalink, guild_id, Some(channel_id)).await?;
+
+        // The connection of the guild can still be the one of the previous channel when moving.
+        let wait = async {
+            loop {
+                if let Some(connection) = connections.get(&guild_id) {
+                    if connection.channel_id == Some(channel_id)
+                        && connection.token.is_some()
+                        && connection.endpoint.is_some()
+                        && connection.session_id.is_some()
+                    {
+                        return connection.clone();
+                    }
+                }
+
+                sleep(Duration::from_millis(100)).await;
+            }
+        };
 
-    wait_for_full_connection_info_insert(lavalink, guild_id, None).await
+        if let Ok(connection) = tokio::time::timeout(timeout, wait).await {
+            return Ok(connection);
+        }
+    }
+
+    // Don't stay in the channel without a voice connection, or keep its partial information.
+    if let Err(why) = update_voice_state(lavalink, guild_id, None).await {
+        warn!("Error when leaving after the join timed out: {}", why);
+    }
+
+    connections.remove(&guild_id);
+
+    Err(LavalinkError::JoinTimedOut(guild_id, channel_id))
 }
 
 pub async fn leave(lavalink: &LavalinkClient, guild_id: impl Into<GuildId>) -> LavalinkResult<()> {
     let guild_id = guild_id.into();
 
-    call_discord_gateway(
-        lavalink,
-        format!(
-            r#"{{
-                "op": 4,
-                "d": {{
-                    "guild_id": "{}",
-                    "channel_id": null,
-                    "self_mute": false,
-                    "self_deaf": true
-                }}
-            }}"#,
-            guild_id.0,
-        ),
-    );
+    update_voice_state(lavalink, guild_id, None).await?;
 
     wait_for_connection_info_remove(lavalink, guild_id, None).await
 }
 
+/// Sends a PATCH request to the route of the discord REST API, with the bot token of the gateway.
+async fn discord_patch(
+    lavalink: &LavalinkClient,
+    route: String,
+    body: &impl Serialize,
+) -> LavalinkResult<()> {
+    let headers = lavalink.discord_gateway_data().lock().headers.clone();
+    let http_client = lavalink.inner.lock().http_client.clone();
+
+    http_client
+        .patch(format!("{}/{}", DISCORD_API, route))
+        .headers(headers)
+        .body(serde_json::to_string(body).expect("The payload cannot be serialized."))
+        .send()
+        .await?
+        .error_for_status()?;
+
+    Ok(())
+}
+
+pub async fn server_mute(
+    lavalink: &LavalinkClient,
+    guild_id: impl Into<GuildId>,
+    user_id: impl Into<UserId>,
+    mute: bool,
+) -> LavalinkResult<()> {
+    discord_patch(
+        lavalink,
+        format!("guilds/{}/members/{}", guild_id.into(), user_id.into()),
+        &ModifyMember {
+            mute: Some(mute),
+            ..ModifyMember::default()
+        },
+    )
+    .await
+}
+
+pub async fn server_deafen(
+    lavalink: &LavalinkClient,
+    guild_id: impl Into<GuildId>,
+    user_id: impl Into<UserId>,
+    deafen: bool,
+) -> LavalinkResult<()> {
+    discord_patch(
+        lavalink,
+        format!("guilds/{}/members/{}", guild_id.into(), user_id.into()),
+        &ModifyMember {
+            deaf: Some(deafen),
+            ..ModifyMember::default()
+        },
+    )
+    .await
+}
+
+/// Formats the current time as an ISO 8601 timestamp in UTC, like `2021-05-04T13:37:00Z`.
+fn iso8601_now() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since_epoch| since_epoch.as_secs());
+    let (days, secs) = (secs / 86400, secs % 86400);
+
+    // Converts the days since the epoch to a civil date, from Howard Hinnant's algorithm.
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let day_of_era = z % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+
+    format!(
+        "{}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+/// Makes the bot a speaker of the stage channel it's in, or requests to speak if it lacks the
+/// `MUTE_MEMBERS` permission to unsuppress itself.
+pub async fn become_stage_speaker(
+    lavalink: &LavalinkClient,
+    guild_id: impl Into<GuildId>,
+    channel_id: impl Into<ChannelId>,
+) -> LavalinkResult<()> {
+    let route = format!("guilds/{}/voice-states/@me", guild_id.into());
+    let channel_id = channel_id.into().to_string();
+
+    let unsuppressed = discord_patch(
+        lavalink,
+        route.clone(),
+        &ModifyVoiceState {
+            channel_id: &channel_id,
+            suppress: Some(false),
+            request_to_speak_timestamp: None,
+        },
+    )
+    .await;
+
+    if unsuppressed.is_ok() {
+        return Ok(());
+    }
+
+    discord_patch(
+        lavalink,
+        route,
+        &ModifyVoiceState {
+            channel_id: &channel_id,
+            suppress: None,
+            request_to_speak_timestamp: Some(iso8601_now()),
+        },
+    )
+    .await
+}
+
+/// Re-sends the voice state updates of the guilds with an active player, so discord sends new
+/// voice server updates after the gateway session was lost.
+pub fn backfill_voice_sessions(lavalink: &LavalinkClient) {
+    let voice_gateway = lavalink.discord_gateway_data().lock().voice_gateway.clone();
+
+    // The voice sessions belong to the session of the custom gateway, if there's one.
+    if voice_gateway.is_some() {
+        return;
+    }
+
+    let connections = lavalink.discord_gateway_connections();
+    let nodes = lavalink.inner.lock().nodes.clone();
+
+    let channels = connections
+        .iter()
+        .filter(|connection| nodes.contains_key(&connection.key().0))
+        .filter_map(|connection| Some((*connection.key(), connection.channel_id?)))
+        .collect::<Vec<_>>();
+
+    for (guild_id, channel_id) in channels {
+        debug!("Rejoining voice channel {} on guild {}", channel_id, guild_id);
+        call_discord_gateway(lavalink, voice_state_update_payload(guild_id, Some(channel_id)));
+    }
+}
+
 pub async fn wait_for_full_connection_info_insert(
     lavalink: &LavalinkClient,
     guild_id: impl Into<GuildId>,
@@ -206,3 +510,181 @@ pub fn raw_handle_event_voice_state_update(
         );
     };
 }
+
+/// Tracks the voice state of a guild member, removing it if they left the voice channels.
+///
+/// The changes to the voice state of the bot trigger the `self_moved`, `self_disconnected` and
+/// `self_server_muted` events.
+pub fn raw_handle_member_voice_state(
+    lavalink: &LavalinkClient,
+    guild_id: impl Into<GuildId>,
+    user_id: impl Into<UserId>,
+    state: Option<MemberVoiceState>,
+) {
+    let guild_id = guild_id.into();
+    let user_id = user_id.into();
+
+    let (bot_id, voice_states) = {
+        let gateway_data = lavalink.discord_gateway_data();
+        let gateway_data = gateway_data.lock();
+        (gateway_data.bot_id, gateway_data.voice_states.clone())
+    };
+
+    let old_state = {
+        let mut guild = voice_states.entry(guild_id).or_default();
+
+        match state {
+            Some(state) => guild.insert(user_id, state),
+            None => guild.remove(&user_id),
+        }
+    };
+
+    if user_id == bot_id {
+        dispatch_self_voice_state(lavalink, guild_id, old_state, state);
+    }
+}
+
+/// Triggers the events of the changes between two voice states of the bot.
+fn dispatch_self_voice_state(
+    lavalink: &LavalinkClient,
+    guild_id: GuildId,
+    old_state: Option<MemberVoiceState>,
+    new_state: Option<MemberVoiceState>,
+) {
+    dispatch_guild_event(
+        lavalink,
+        guild_id,
+        "voice_state_update",
+        move |handler, client| async move {
+            match (old_state, new_state) {
+                (Some(old_state), Some(new_state))
+                    if old_state.channel_id != new_state.channel_id =>
+                {
+                    handler
+                        .self_moved(
+                            client.clone(),
+                            SelfMoved {
+                                guild_id,
+                                old_channel: old_state.channel_id,
+                                new_channel: new_state.channel_id,
+                            },
+                        )
+                        .await;
+                }
+                (Some(old_state), None) => {
+                    handler
+                        .self_disconnected(
+                            client.clone(),
+                            SelfDisconnected {
+                                guild_id,
+                                channel_id: old_state.channel_id,
+                            },
+                        )
+                        .await;
+                }
+                _ => (),
+            }
+
+            if let Some(new_state) = new_state {
+                // Joining while already server muted counts as being muted.
+                if old_state.map_or(false, |old_state| old_state.mute) != new_state.mute {
+                    handler
+                        .self_server_muted(
+                            client,
+                            SelfServerMuted {
+                                guild_id,
+                                muted: new_state.mute,
+                            },
+                        )
+                        .await;
+                }
+            }
+        },
+    );
+}
+
+/// Handles the suppression of the bot in stage channels, becoming a speaker when it joins one as
+/// the audience if `auto_stage_speaker` is enabled, and triggering `stage_speaker_granted` once
+/// it's unsuppressed.
+pub fn raw_handle_stage_suppress(
+    lavalink: &LavalinkClient,
+    guild_id: impl Into<GuildId>,
+    channel_id: Option<impl Into<ChannelId>>,
+    user_id: impl Into<UserId>,
+    suppress: bool,
+) {
+    let guild_id = guild_id.into();
+    let channel_id = channel_id.map(std::convert::Into::into);
+
+    let (bot_id, auto_stage_speaker, suppressed) = {
+        let gateway_data = lavalink.discord_gateway_data();
+        let gateway_data = gateway_data.lock();
+        (
+            gateway_data.bot_id,
+            gateway_data.auto_stage_speaker,
+            gateway_data.stage_suppressed.clone(),
+        )
+    };
+
+    if user_id.into() != bot_id {
+        return;
+    }
+
+    match channel_id {
+        Some(channel_id) if suppress => {
+            // Only the first update of a suppression becomes a speaker, not the ones after it.
+            if suppressed.insert(guild_id) && auto_stage_speaker {
+                let lavalink = lavalink.clone();
+
+                tokio::spawn(async move {
+                    if let Err(why) = become_stage_speaker(&lavalink, guild_id, channel_id).await {
+                        warn!(
+                            "Error when becoming a speaker of the stage channel {}: {}",
+                            channel_id, why
+                        );
+                    }
+                });
+            }
+        }
+        Some(channel_id) => {
+            if suppressed.remove(&guild_id).is_some() {
+                dispatch_guild_event(
+                    lavalink,
+                    guild_id,
+                    "stage_speaker_granted",
+                    |handler, client| async move {
+                        handler
+                            .stage_speaker_granted(
+                                client,
+                                StageSpeakerGranted {
+                                    guild_id,
+                                    channel_id,
+                                },
+                            )
+                            .await;
+                    },
+                );
+            }
+        }
+        None => {
+            suppressed.remove(&guild_id);
+        }
+    }
+}
+
+/// Replaces the voice states of the guild with the ones sent when it became available.
+pub fn raw_handle_guild_voice_states(
+    lavalink: &LavalinkClient,
+    guild_id: impl Into<GuildId>,
+    states: Vec<MemberVoiceState>,
+) {
+    let voice_states = lavalink.discord_gateway_data().lock().voice_states.clone();
+
+    voice_states.insert(
+        guild_id.into(),
+        states
+            .into_iter()
+            .map(|state| (state.user_id, state))
+            .collect(),
+    );
+}